@@ -49,10 +49,6 @@ impl Camera {
         point
     }
 
-    pub fn pixels_iter(&self) -> CameraPixelIterator {
-        CameraPixelIterator::new(&self)
-    }
-
     pub fn get_ray(&self, x: u32, y: u32) -> Ray {
         let direction = self.transform(x, y);
         Ray::new(self.position.clone(), direction)
@@ -85,33 +81,3 @@ impl Camera {
         self.position.z += 0.05 * (lr as f64) * left_axis.z;
     }
 }
-
-pub struct CameraPixelIterator<'a> {
-    camera: &'a Camera,
-    index: u32,
-}
-
-impl<'a> CameraPixelIterator<'a> {
-    pub fn new(camera: &Camera) -> CameraPixelIterator {
-        CameraPixelIterator {
-            camera: camera,
-            index: 0,
-        }
-    }
-}
-
-impl<'a> Iterator for CameraPixelIterator<'a> {
-    type Item = (u32, u32);
-
-    fn next(&mut self) -> Option<(u32, u32)> {
-
-        if self.index == (self.camera.width * self.camera.height) {
-            None
-        }
-        else {
-            let result = Some((self.index % self.camera.width, self.index / self.camera.width));
-            self.index += 1;
-            result
-        }
-    }
-}
@@ -2,9 +2,9 @@ use std::fmt;
 use std::convert::From;
 
 macro_rules! ErrorEnum {
-    ( $enum_name:ident, ($($error_type:ident),*)) => (
+    ( $vis:vis $enum_name:ident, ($($error_type:ident),*)) => (
         #[derive(Debug)]
-        enum $enum_name {$(
+        $vis enum $enum_name {$(
             $error_type($error_type),
         )*}
 
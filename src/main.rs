@@ -180,6 +180,20 @@ fn poll_events(context: &mut AppContext) {
                             context.input.events.push(InputEvent::KeyChange(KeyboardKey::S)),
                         Keycode::D =>
                             context.input.events.push(InputEvent::KeyChange(KeyboardKey::D)),
+                        Keycode::F => context.scene.toggle_fog(),
+                        Keycode::N => context.scene.toggle_denoise(),
+                        Keycode::L => {
+                            context.scene.toggle_light_groups();
+                            eprintln!("Light groups {}", if context.scene.light_groups { "on" } else { "off" });
+                        },
+                        Keycode::V => {
+                            context.scene.cycle_aov();
+                            eprintln!("Showing {}", context.scene.aov.name());
+                        },
+                        Keycode::P => match context.scene.save_images("render") {
+                            Ok(()) => eprintln!("Saved render.ppm and its channels"),
+                            Err(e) => eprintln!("Failed to save render: {}", e),
+                        },
                         _ => (),
                     }
                 }
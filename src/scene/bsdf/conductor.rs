@@ -0,0 +1,120 @@
+use crate::scene::bsdf::{Bsdf, BsdfSample, TrowbridgeReitz, same_hemisphere, reflect, fresnel_conductor};
+use crate::scene::primitives::{Point, Spectrum};
+use crate::scene::sampler::Sampler;
+
+// Rough metal, reflectance from the complex index of refraction eta + ik
+// sampled at red, green and blue wavelengths
+pub struct Conductor {
+    pub eta: Spectrum,
+    pub k: Spectrum,
+    distribution: TrowbridgeReitz,
+}
+
+impl Conductor {
+    pub fn new(eta: Spectrum, k: Spectrum, distribution: TrowbridgeReitz) -> Self {
+        Conductor {
+            eta: eta,
+            k: k,
+            distribution: distribution,
+        }
+    }
+
+    pub fn gold(roughness: f64) -> Self {
+        Self::new(
+            Spectrum::new(0.143, 0.374, 1.442),
+            Spectrum::new(3.983, 2.385, 1.603),
+            TrowbridgeReitz::from_roughness(roughness))
+    }
+
+    pub fn copper(roughness: f64) -> Self {
+        Self::new(
+            Spectrum::new(0.200, 0.924, 1.102),
+            Spectrum::new(3.912, 2.452, 2.142),
+            TrowbridgeReitz::from_roughness(roughness))
+    }
+
+    pub fn silver(roughness: f64) -> Self {
+        Self::new(
+            Spectrum::new(0.155, 0.117, 0.138),
+            Spectrum::new(4.828, 3.122, 2.147),
+            TrowbridgeReitz::from_roughness(roughness))
+    }
+
+    pub fn aluminium(roughness: f64) -> Self {
+        Self::new(
+            Spectrum::new(1.657, 0.880, 0.521),
+            Spectrum::new(9.224, 6.270, 4.837),
+            TrowbridgeReitz::from_roughness(roughness))
+    }
+
+    fn fresnel(&self, cos_i: f64) -> Spectrum {
+        Spectrum::new(
+            fresnel_conductor(cos_i, self.eta.r, self.k.r),
+            fresnel_conductor(cos_i, self.eta.g, self.k.g),
+            fresnel_conductor(cos_i, self.eta.b, self.k.b))
+    }
+}
+
+impl Bsdf for Conductor {
+    fn evaluate(&self, wo: &Point, wi: &Point) -> Spectrum {
+        if !same_hemisphere(wo, wi) {
+            return Spectrum::black();
+        }
+
+        let cos_o = wo.z.abs();
+        let cos_i = wi.z.abs();
+        let wm = wo.add(wi);
+        if cos_o == 0.0 || cos_i == 0.0 || wm.abs() == 0.0 {
+            return Spectrum::black();
+        }
+
+        let wm = wm.normalize();
+        let d = &self.distribution;
+        self.fresnel(wi.dot(&wm).abs())
+            .mult(d.d(&wm) * d.g(wo, wi) / (4.0 * cos_o * cos_i))
+    }
+
+    fn sample(&self, wo: &Point, sampler: &mut Sampler) -> Option<BsdfSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+
+        // sample_wm works in the +z hemisphere, mirror for wo below it
+        let flip = if wo.z < 0.0 { -1.0 } else { 1.0 };
+        let wo_up = Point::from((wo.x, wo.y, wo.z * flip));
+        let wm = self.distribution.sample_wm(&wo_up, sampler);
+        let wi = reflect(&wo_up, &wm);
+        if wi.z <= 0.0 {
+            return None;
+        }
+        let wi = Point::from((wi.x, wi.y, wi.z * flip));
+
+        Some(BsdfSample {
+            value: self.evaluate(wo, &wi),
+            pdf: self.pdf(wo, &wi),
+            wi: wi,
+        })
+    }
+
+    fn pdf(&self, wo: &Point, wi: &Point) -> f64 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+
+        let wm = wo.add(wi);
+        if wm.abs() == 0.0 {
+            return 0.0;
+        }
+
+        // face the microfacet normal up, where sample_wm generates them
+        let wm = wm.normalize();
+        let wm = if wm.z < 0.0 { wm.mult(-1.0) } else { wm };
+        let wo_up = if wo.z < 0.0 { wo.mult(-1.0) } else { wo.clone() };
+        self.distribution.visible_d(&wo_up, &wm) / (4.0 * wo_up.dot(&wm).abs())
+    }
+
+    // reflectance at normal incidence
+    fn albedo(&self) -> Spectrum {
+        self.fresnel(1.0)
+    }
+}
@@ -0,0 +1,130 @@
+use crate::scene::bsdf::{Bsdf, BsdfSample, TrowbridgeReitz, reflect, refract, fresnel_dielectric};
+use crate::scene::primitives::{Point, Spectrum};
+use crate::scene::sampler::Sampler;
+
+// Rough glass, reflecting and transmitting through GGX microfacets
+// (Walter et al. 2007). eta is the index of refraction inside over outside.
+pub struct Dielectric {
+    pub eta: f64,
+    distribution: TrowbridgeReitz,
+}
+
+impl Dielectric {
+    pub fn new(eta: f64, distribution: TrowbridgeReitz) -> Self {
+        Dielectric {
+            eta: eta,
+            distribution: distribution,
+        }
+    }
+
+    pub fn glass(roughness: f64) -> Self {
+        Self::new(1.5, TrowbridgeReitz::from_roughness(roughness))
+    }
+
+    // Generalized half vector for the pair, facing +z, along with the
+    // relative eta the pair was refracted with. None for degenerate
+    // configurations and for pairs that are on the wrong side of their
+    // microfacet.
+    fn half_vector(&self, wo: &Point, wi: &Point) -> Option<(Point, f64)> {
+        let cos_o = wo.z;
+        let cos_i = wi.z;
+        let reflected = cos_o * cos_i > 0.0;
+        let etap = if reflected { 1.0 } else if cos_o > 0.0 { self.eta } else { 1.0 / self.eta };
+
+        let wm = wi.mult(etap).add(wo);
+        if cos_o == 0.0 || cos_i == 0.0 || wm.abs() == 0.0 {
+            return None;
+        }
+
+        let wm = wm.normalize();
+        let wm = if wm.z < 0.0 { wm.mult(-1.0) } else { wm };
+        if wm.dot(wi) * cos_i < 0.0 || wm.dot(wo) * cos_o < 0.0 {
+            return None;
+        }
+
+        Some((wm, etap))
+    }
+
+    // change of variables from microfacet normal density to wi density
+    // for refraction
+    fn refraction_jacobian(wo: &Point, wi: &Point, wm: &Point, etap: f64) -> f64 {
+        let denom = wi.dot(wm) + wo.dot(wm) / etap;
+        wi.dot(wm).abs() / (denom * denom)
+    }
+}
+
+impl Bsdf for Dielectric {
+    fn evaluate(&self, wo: &Point, wi: &Point) -> Spectrum {
+        let (wm, etap) = match self.half_vector(wo, wi) {
+            Some(half) => half,
+            None => return Spectrum::black(),
+        };
+
+        let d = &self.distribution;
+        let fresnel = fresnel_dielectric(wo.dot(&wm), self.eta);
+        let value = if etap == 1.0 {
+            d.d(&wm) * d.g(wo, wi) * fresnel / (4.0 * wi.z * wo.z).abs()
+        }
+        else {
+            let denom = wi.dot(&wm) + wo.dot(&wm) / etap;
+            let transmitted = d.d(&wm) * d.g(wo, wi) * (1.0 - fresnel)
+                * (wi.dot(&wm) * wo.dot(&wm) / (wi.z * wo.z * denom * denom)).abs();
+
+            // radiance is compressed into a smaller solid angle on entry
+            transmitted / (etap * etap)
+        };
+
+        Spectrum::new(value, value, value)
+    }
+
+    fn sample(&self, wo: &Point, sampler: &mut Sampler) -> Option<BsdfSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+
+        let wm = self.distribution.sample_wm(wo, sampler);
+        let reflectance = fresnel_dielectric(wo.dot(&wm), self.eta);
+
+        let wi = if sampler.next_f64() < reflectance {
+            let wi = reflect(wo, &wm);
+            if wi.z * wo.z <= 0.0 {
+                return None;
+            }
+            wi
+        }
+        else {
+            let (wi, _) = refract(wo, &wm, self.eta)?;
+            if wi.z * wo.z >= 0.0 {
+                return None;
+            }
+            wi
+        };
+
+        let pdf = self.pdf(wo, &wi);
+        if pdf == 0.0 {
+            return None;
+        }
+
+        Some(BsdfSample {
+            value: self.evaluate(wo, &wi),
+            pdf: pdf,
+            wi: wi,
+        })
+    }
+
+    fn pdf(&self, wo: &Point, wi: &Point) -> f64 {
+        let (wm, etap) = match self.half_vector(wo, wi) {
+            Some(half) => half,
+            None => return 0.0,
+        };
+
+        let reflectance = fresnel_dielectric(wo.dot(&wm), self.eta);
+        let visible = self.distribution.visible_d(wo, &wm);
+        if etap == 1.0 {
+            visible / (4.0 * wo.dot(&wm).abs()) * reflectance
+        }
+        else {
+            visible * Self::refraction_jacobian(wo, wi, &wm, etap) * (1.0 - reflectance)
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::f64::consts::PI;
+
+use crate::scene::bsdf::{Bsdf, BsdfSample};
+use crate::scene::primitives::{Point, Spectrum};
+use crate::scene::sampler::Sampler;
+
+// smallest cosine divided out of evaluate, so light nearly in the plane of
+// the shading normal doesn't blow up
+const MIN_COSINE: f64 = 1e-4;
+
+// Kajiya and Kay 1989 hair, treating a strand as a thin cylinder along the
+// shading tangent. Light is scattered all around the strand, from either
+// side of the shading normal, a diffuse part growing the more squarely the
+// light meets the strand and a specular part along the cone of directions
+// mirrored about it. Both parts are normalized to scatter all the light
+// that arrives squarely, the specular cone less as it narrows towards the
+// strand, so nothing scatters more than it receives.
+//
+// The model's sin_i already weights light by how it meets the strand, so
+// evaluate divides out the cosine with the shading normal the integrator
+// multiplies in, as pbrt's hair does. Otherwise light arriving edge on to
+// the normal would be lost, though it meets the strand squarely.
+pub struct Hair {
+    pub color: Spectrum,
+    // weight of the specular highlight, in [0, 1]
+    pub specular: f64,
+    // tightness of the highlight, higher is shinier
+    exponent: f64,
+    // one over the highlight's integral over the sphere for light meeting
+    // the strand squarely
+    highlight_scale: f64,
+}
+
+impl Hair {
+    pub fn new(color: Spectrum, specular: f64, exponent: f64) -> Self {
+        // the cone is a ring around the strand, 2 pi sin^(n + 1) integrated
+        // over the angle with the strand, summed at the midpoint of steps
+        let steps = 4096;
+        let integral = (0..steps).map(|step| {
+            let angle = (step as f64 + 0.5) / steps as f64 * PI;
+            angle.sin().powf(exponent + 1.0)
+        }).sum::<f64>() * PI / steps as f64 * 2.0 * PI;
+
+        Hair {
+            color: color,
+            specular: specular,
+            exponent: exponent,
+            highlight_scale: 1.0 / integral,
+        }
+    }
+}
+
+impl Bsdf for Hair {
+    fn evaluate(&self, wo: &Point, wi: &Point) -> Spectrum {
+        // the tangent is x, so these are angles with the strand
+        let sin_i = (1.0 - wi.x * wi.x).max(0.0).sqrt();
+        let sin_o = (1.0 - wo.x * wo.x).max(0.0).sqrt();
+
+        // sin_i over the sphere comes to pi^2
+        let diffuse = self.color.mult((1.0 - self.specular) * sin_i / (PI * PI));
+        let cone = (sin_i * sin_o - wi.x * wo.x).max(0.0);
+        let highlight = self.specular * self.highlight_scale * cone.powf(self.exponent);
+        diffuse.add(&Spectrum::new(highlight, highlight, highlight))
+            .mult(1.0 / wi.z.abs().max(MIN_COSINE))
+    }
+
+    // uniform over the sphere, every direction can be reached
+    fn sample(&self, wo: &Point, sampler: &mut Sampler) -> Option<BsdfSample> {
+        let z = 1.0 - 2.0 * sampler.next_f64();
+        let radius = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * sampler.next_f64();
+        let wi = Point::from((radius * phi.cos(), radius * phi.sin(), z));
+
+        Some(BsdfSample {
+            value: self.evaluate(wo, &wi),
+            pdf: self.pdf(wo, &wi),
+            wi: wi,
+        })
+    }
+
+    fn pdf(&self, _wo: &Point, _wi: &Point) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn albedo(&self) -> Spectrum {
+        self.color.clone()
+    }
+}
@@ -0,0 +1,50 @@
+use std::f64::consts::PI;
+
+use crate::scene::bsdf::{Bsdf, BsdfSample, same_hemisphere, cosine_sample_hemisphere};
+use crate::scene::primitives::{Point, Spectrum};
+use crate::scene::sampler::Sampler;
+
+// Ideal diffuse reflection
+pub struct Lambertian {
+    pub albedo: Spectrum,
+}
+
+impl Lambertian {
+    pub fn new(albedo: Spectrum) -> Self {
+        Lambertian {
+            albedo: albedo,
+        }
+    }
+}
+
+impl Bsdf for Lambertian {
+    fn evaluate(&self, wo: &Point, wi: &Point) -> Spectrum {
+        if same_hemisphere(wo, wi) {
+            self.albedo.mult(1.0 / PI)
+        }
+        else {
+            Spectrum::black()
+        }
+    }
+
+    fn sample(&self, wo: &Point, sampler: &mut Sampler) -> Option<BsdfSample> {
+        let mut wi = cosine_sample_hemisphere(sampler);
+        if wo.z < 0.0 {
+            wi.z = -wi.z;
+        }
+
+        Some(BsdfSample {
+            value: self.evaluate(wo, &wi),
+            pdf: self.pdf(wo, &wi),
+            wi: wi,
+        })
+    }
+
+    fn pdf(&self, wo: &Point, wi: &Point) -> f64 {
+        if same_hemisphere(wo, wi) { wi.z.abs() / PI } else { 0.0 }
+    }
+
+    fn albedo(&self) -> Spectrum {
+        self.albedo.clone()
+    }
+}
@@ -0,0 +1,123 @@
+use std::f64::consts::PI;
+
+use crate::scene::primitives::Point;
+use crate::scene::sampler::Sampler;
+
+// GGX / Trowbridge-Reitz microfacet distribution, optionally anisotropic
+// with separate roughness along the tangent (x) and bitangent (y)
+pub struct TrowbridgeReitz {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Self {
+        // perfectly smooth surfaces would need delta distributions, which
+        // point lights can never hit, so keep a tiny amount of roughness
+        TrowbridgeReitz {
+            alpha_x: alpha_x.max(1e-3),
+            alpha_y: alpha_y.max(1e-3),
+        }
+    }
+
+    // perceptually linear roughness in [0, 1] to alpha
+    pub fn from_roughness(roughness: f64) -> Self {
+        let alpha = roughness * roughness;
+        Self::new(alpha, alpha)
+    }
+
+    pub fn from_anisotropic_roughness(roughness_x: f64, roughness_y: f64) -> Self {
+        Self::new(roughness_x * roughness_x, roughness_y * roughness_y)
+    }
+
+    // differential area of microfacets with normal wm
+    pub fn d(&self, wm: &Point) -> f64 {
+        let cos2_theta = wm.z * wm.z;
+        let sin2_theta = (1.0 - cos2_theta).max(0.0);
+        if cos2_theta == 0.0 {
+            return 0.0;
+        }
+
+        let tan2_theta = sin2_theta / cos2_theta;
+        let (cos2_phi, sin2_phi) = Self::cos2_sin2_phi(wm, sin2_theta);
+        let e = tan2_theta * (cos2_phi / (self.alpha_x * self.alpha_x)
+            + sin2_phi / (self.alpha_y * self.alpha_y));
+
+        1.0 / (PI * self.alpha_x * self.alpha_y * cos2_theta * cos2_theta * (1.0 + e) * (1.0 + e))
+    }
+
+    // Smith auxiliary function, ratio of masked to visible microfacet area
+    fn lambda(&self, w: &Point) -> f64 {
+        let cos2_theta = w.z * w.z;
+        let sin2_theta = (1.0 - cos2_theta).max(0.0);
+        if cos2_theta == 0.0 {
+            return std::f64::INFINITY;
+        }
+
+        let tan2_theta = sin2_theta / cos2_theta;
+        let (cos2_phi, sin2_phi) = Self::cos2_sin2_phi(w, sin2_theta);
+        let alpha2 = cos2_phi * self.alpha_x * self.alpha_x + sin2_phi * self.alpha_y * self.alpha_y;
+
+        ((1.0 + alpha2 * tan2_theta).sqrt() - 1.0) / 2.0
+    }
+
+    // fraction of microfacets visible from w
+    pub fn g1(&self, w: &Point) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // fraction visible from both directions, height correlated
+    pub fn g(&self, wo: &Point, wi: &Point) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // density of microfacet normals wm visible from w
+    pub fn visible_d(&self, w: &Point, wm: &Point) -> f64 {
+        if w.z == 0.0 {
+            return 0.0;
+        }
+        self.g1(w) / w.z.abs() * self.d(wm) * w.dot(wm).abs()
+    }
+
+    // Sample a microfacet normal from the distribution of normals visible
+    // from w (Heitz 2018), always in the +z hemisphere
+    pub fn sample_wm(&self, w: &Point, sampler: &mut Sampler) -> Point {
+        // stretch to the hemisphere configuration
+        let mut wh = Point::from((self.alpha_x * w.x, self.alpha_y * w.y, w.z)).normalize();
+        if wh.z < 0.0 {
+            wh = wh.mult(-1.0);
+        }
+
+        let t1 = if wh.z < 0.99999 {
+            Point::from((0.0, 0.0, 1.0)).cross(&wh).normalize()
+        }
+        else {
+            Point::from((1.0, 0.0, 0.0))
+        };
+        let t2 = wh.cross(&t1);
+
+        // uniform point on the disk, warped onto the visible half
+        let radius = sampler.next_f64().sqrt();
+        let phi = 2.0 * PI * sampler.next_f64();
+        let p1 = radius * phi.cos();
+        let p2 = radius * phi.sin();
+        let h = (1.0 - p1 * p1).sqrt();
+        let s = (1.0 + wh.z) / 2.0;
+        let p2 = (1.0 - s) * h + s * p2;
+        let pz = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        // reproject onto the hemisphere and unstretch
+        let nh = t1.mult(p1).add(&t2.mult(p2)).add(&wh.mult(pz));
+        Point::from((self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6))).normalize()
+    }
+
+    fn cos2_sin2_phi(w: &Point, sin2_theta: f64) -> (f64, f64) {
+        if sin2_theta == 0.0 {
+            (1.0, 0.0)
+        }
+        else {
+            let cos2_phi = (w.x * w.x / sin2_theta).min(1.0);
+            (cos2_phi, 1.0 - cos2_phi)
+        }
+    }
+}
@@ -0,0 +1,284 @@
+mod lambertian;
+mod oren_nayar;
+mod microfacet;
+mod conductor;
+mod dielectric;
+mod principled;
+mod hair;
+
+pub use lambertian::Lambertian;
+pub use oren_nayar::OrenNayar;
+pub use microfacet::TrowbridgeReitz;
+pub use conductor::Conductor;
+pub use dielectric::Dielectric;
+pub use principled::Principled;
+pub use hair::Hair;
+
+use std::f64::consts::PI;
+
+use crate::scene::primitives::{Point, Spectrum};
+use crate::scene::sampler::Sampler;
+
+// Index into Scene::materials
+pub type MaterialId = usize;
+
+pub struct BsdfSample {
+    pub wi: Point,
+    pub value: Spectrum,
+    pub pdf: f64,
+}
+
+// All directions are in the local shading frame (see Frame), normalized
+// and pointing away from the surface. wo is towards the viewer and wi
+// towards the light.
+pub trait Bsdf {
+    fn evaluate(&self, wo: &Point, wi: &Point) -> Spectrum;
+
+    fn sample(&self, wo: &Point, sampler: &mut Sampler) -> Option<BsdfSample>;
+
+    // solid angle density sample() picks wi with
+    fn pdf(&self, wo: &Point, wi: &Point) -> f64;
+
+    // overall surface color, used as a guide by the denoiser
+    fn albedo(&self) -> Spectrum {
+        Spectrum::new(1.0, 1.0, 1.0)
+    }
+}
+
+pub fn same_hemisphere(w: &Point, other: &Point) -> bool {
+    w.z * other.z > 0.0
+}
+
+pub fn reflect(wo: &Point, normal: &Point) -> Point {
+    wo.mult(-1.0).add(&normal.mult(2.0 * wo.dot(normal)))
+}
+
+// Refract wi through a surface with relative index of refraction eta,
+// the ratio inside over outside. Returns the transmitted direction and the
+// eta actually used, which is inverted when wi is below the surface.
+pub fn refract(wi: &Point, normal: &Point, eta: f64) -> Option<(Point, f64)> {
+    let mut cos_i = normal.dot(wi);
+    let mut eta = eta;
+    let mut normal = normal.clone();
+    if cos_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_i = -cos_i;
+        normal = normal.mult(-1.0);
+    }
+
+    let sin2_i = (1.0 - cos_i * cos_i).max(0.0);
+    let sin2_t = sin2_i / (eta * eta);
+    if sin2_t >= 1.0 {
+        // total internal reflection
+        return None;
+    }
+
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let wt = wi.mult(-1.0 / eta).add(&normal.mult(cos_i / eta - cos_t));
+    Some((wt, eta))
+}
+
+// Unpolarized reflectance of a dielectric interface
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let (cos_i, eta) = if cos_i < 0.0 { (-cos_i, 1.0 / eta) } else { (cos_i, eta) };
+
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+
+    let cos_t = (1.0 - sin2_t).max(0.0).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+// Unpolarized reflectance of a conductor with complex index of refraction
+// eta + ik, for a single wavelength
+pub fn fresnel_conductor(cos_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2_i = cos_i * cos_i;
+    let sin2_i = 1.0 - cos2_i;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2_i;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2_i;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2_i * a2_plus_b2 + sin2_i * sin2_i;
+    let t4 = t2 * sin2_i;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rp + rs)
+}
+
+// Cosine weighted direction on the +z hemisphere, pdf cos_theta / pi
+pub fn cosine_sample_hemisphere(sampler: &mut Sampler) -> Point {
+    let radius = sampler.next_f64().sqrt();
+    let phi = 2.0 * PI * sampler.next_f64();
+    let x = radius * phi.cos();
+    let y = radius * phi.sin();
+    Point::from((x, y, (1.0 - x * x - y * y).max(0.0).sqrt()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // outgoing directions from overhead to grazing, above and below the
+    // surface
+    fn outgoing(below: bool) -> Vec<Point> {
+        [0.0, 0.6, 1.2, 1.5].iter().map(|theta: &f64| {
+            let z = if below { -theta.cos() } else { theta.cos() };
+            Point::from((theta.sin() * 0.8, theta.sin() * 0.6, z))
+        }).collect()
+    }
+
+    fn near(a: f64, b: f64) -> bool {
+        (a - b).abs() <= 1e-9 * a.abs().max(b.abs()).max(1.0)
+    }
+
+    // what sample returns is what evaluate and pdf give for its direction
+    fn assert_samples_match(name: &str, bsdf: &dyn Bsdf, wo: &Point, sampler: &mut Sampler) {
+        for _ in 0..2000 {
+            let sample = match bsdf.sample(wo, sampler) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            assert!((sample.wi.abs() - 1.0).abs() < 1e-9, "{}: wi {:?} isn't unit length", name, sample.wi);
+            assert!(sample.pdf > 0.0 && sample.pdf.is_finite(), "{}: pdf {} for wo {:?}", name, sample.pdf, wo);
+            let pdf = bsdf.pdf(wo, &sample.wi);
+            assert!(near(sample.pdf, pdf), "{}: sampled pdf {}, pdf gives {} for wo {:?} wi {:?}", name, sample.pdf, pdf, wo, sample.wi);
+            let value = bsdf.evaluate(wo, &sample.wi);
+            for (sampled, evaluated) in [(sample.value.r, value.r), (sample.value.g, value.g), (sample.value.b, value.b)].iter() {
+                assert!(near(*sampled, *evaluated), "{}: sampled value {:?}, evaluate gives {:?} for wo {:?} wi {:?}",
+                    name, sample.value, value, wo, sample.wi);
+            }
+        }
+    }
+
+    // pdf over the whole sphere, on a fine grid in theta and phi that
+    // resolves narrow lobes even at the poles, against how often sample
+    // finds a direction at all
+    fn assert_pdf_integrates(name: &str, bsdf: &dyn Bsdf, wo: &Point, sampler: &mut Sampler) {
+        let (rows, columns) = (2000, 1000);
+        let mut integral = 0.0;
+        for row in 0..rows {
+            let theta = PI * (row as f64 + sampler.next_f64()) / rows as f64;
+            for column in 0..columns {
+                let phi = 2.0 * PI * (column as f64 + sampler.next_f64()) / columns as f64;
+                let wi = Point::from((theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()));
+                integral += bsdf.pdf(wo, &wi) * theta.sin();
+            }
+        }
+        integral *= 2.0 * PI * PI / (rows * columns) as f64;
+        let count = 200000;
+        let found = (0..count).filter(|_| bsdf.sample(wo, sampler).is_some()).count() as f64 / count as f64;
+        assert!((integral - found).abs() < 0.03, "{}: pdf integrates to {}, sample finds {} for wo {:?}", name, integral, found, wo);
+    }
+
+    // Estimate of the light reflected and transmitted back towards wo under
+    // a uniform white sky, which is at most all of it
+    fn assert_conserves_energy(name: &str, bsdf: &dyn Bsdf, wo: &Point, sampler: &mut Sampler) {
+        let count = 100000;
+        let mut total = Spectrum::black();
+        for _ in 0..count {
+            if let Some(sample) = bsdf.sample(wo, sampler) {
+                total = total.add(&sample.value.mult(sample.wi.z.abs() / sample.pdf));
+            }
+        }
+        let albedo = total.mult(1.0 / count as f64);
+        for channel in [albedo.r, albedo.g, albedo.b].iter() {
+            assert!(*channel <= 1.01, "{}: albedo {:?} for wo {:?}", name, albedo, wo);
+        }
+    }
+
+    fn check(name: &str, bsdf: &dyn Bsdf, below: bool, seed: u64) {
+        let mut sampler = Sampler::new(seed);
+        for wo in outgoing(false).iter().chain(if below { outgoing(true) } else { Vec::new() }.iter()) {
+            assert_samples_match(name, bsdf, wo, &mut sampler);
+            assert_pdf_integrates(name, bsdf, wo, &mut sampler);
+            assert_conserves_energy(name, bsdf, wo, &mut sampler);
+        }
+    }
+
+    fn white() -> Spectrum {
+        Spectrum::new(1.0, 1.0, 1.0)
+    }
+
+    #[test]
+    fn lambertian() {
+        check("lambertian", &Lambertian::new(white()), true, 1);
+    }
+
+    #[test]
+    fn oren_nayar() {
+        check("smooth oren-nayar", &OrenNayar::new(white(), 10.0), true, 2);
+        check("rough oren-nayar", &OrenNayar::new(white(), 60.0), true, 3);
+    }
+
+    #[test]
+    fn conductor() {
+        check("gold", &Conductor::gold(0.4), true, 4);
+        check("rough silver", &Conductor::silver(0.9), true, 5);
+        check("brushed aluminium", &Conductor::new(
+            Spectrum::new(1.657, 0.880, 0.521),
+            Spectrum::new(9.224, 6.270, 4.837),
+            TrowbridgeReitz::from_anisotropic_roughness(0.3, 0.8)), true, 6);
+    }
+
+    // Only from outside, light refracted into the glass is concentrated into
+    // a narrower cone and comes out brighter than it went in
+    #[test]
+    fn dielectric() {
+        check("glass", &Dielectric::glass(0.4), false, 7);
+        check("frosted glass", &Dielectric::glass(0.9), false, 8);
+        check("water", &Dielectric::new(1.33, TrowbridgeReitz::from_roughness(0.5)), false, 9);
+    }
+
+    #[test]
+    fn principled() {
+        check("plastic", &Principled::new(white()), true, 10);
+
+        let mut rough = Principled::new(white());
+        rough.roughness = 1.0;
+        check("rough plastic", &rough, true, 19);
+
+        let mut metal = Principled::new(white());
+        metal.metallic = 1.0;
+        metal.roughness = 0.3;
+        check("metal", &metal, true, 11);
+
+        let mut cloth = Principled::new(white());
+        cloth.roughness = 1.0;
+        cloth.sheen = 1.0;
+        check("cloth", &cloth, true, 12);
+
+        let mut lacquer = Principled::new(white());
+        lacquer.clearcoat = 1.0;
+        lacquer.clearcoat_gloss = 0.5;
+        lacquer.specular_tint = 1.0;
+        check("lacquer", &lacquer, true, 13);
+
+        let mut glass = Principled::new(white());
+        glass.transmission = 1.0;
+        glass.roughness = 0.4;
+        check("glass", &glass, false, 14);
+
+        let mut brushed = Principled::new(white());
+        brushed.transmission = 0.5;
+        brushed.anisotropic = 0.8;
+        brushed.clearcoat = 0.5;
+        check("half glass", &brushed, false, 15);
+    }
+
+    #[test]
+    fn hair() {
+        check("hair", &Hair::new(white(), 0.3, 20.0), true, 16);
+        check("matte hair", &Hair::new(white(), 0.0, 10.0), true, 17);
+        check("shiny hair", &Hair::new(white(), 1.0, 50.0), true, 18);
+    }
+}
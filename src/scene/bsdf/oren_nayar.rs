@@ -0,0 +1,65 @@
+use std::f64::consts::PI;
+
+use crate::scene::bsdf::{Bsdf, BsdfSample, same_hemisphere, cosine_sample_hemisphere};
+use crate::scene::primitives::{Point, Spectrum};
+use crate::scene::sampler::Sampler;
+
+// Rough diffuse reflection from v-shaped lambertian microfacets, using
+// Fujii's improved form of the qualitative model from Oren and Nayar's
+// paper. The original reflects more light than arrives at grazing angles
+// when the surface is nearly smooth, this never does.
+pub struct OrenNayar {
+    pub albedo: Spectrum,
+    a: f64,
+    b: f64,
+}
+
+impl OrenNayar {
+    // sigma is the standard deviation of the facet angle in degrees, up
+    // to one radian, past which back scattering would turn negative
+    pub fn new(albedo: Spectrum, sigma: f64) -> Self {
+        let sigma = sigma.to_radians().max(0.0).min(1.0);
+        let a = 1.0 / (PI + (PI / 2.0 - 2.0 / 3.0) * sigma);
+        OrenNayar {
+            albedo: albedo,
+            a: a,
+            b: sigma * a,
+        }
+    }
+}
+
+impl Bsdf for OrenNayar {
+    fn evaluate(&self, wo: &Point, wi: &Point) -> Spectrum {
+        if !same_hemisphere(wo, wi) {
+            return Spectrum::black();
+        }
+
+        // s is cos(phi) sin(theta_i) sin(theta_o), positive when light
+        // comes back towards where it arrived from, which t makes stronger
+        // towards grazing
+        let s = wi.dot(wo) - wi.z * wo.z;
+        let t = if s > 0.0 { wi.z.abs().max(wo.z.abs()) } else { 1.0 };
+        self.albedo.mult(self.a + self.b * s / t)
+    }
+
+    fn sample(&self, wo: &Point, sampler: &mut Sampler) -> Option<BsdfSample> {
+        let mut wi = cosine_sample_hemisphere(sampler);
+        if wo.z < 0.0 {
+            wi.z = -wi.z;
+        }
+
+        Some(BsdfSample {
+            value: self.evaluate(wo, &wi),
+            pdf: self.pdf(wo, &wi),
+            wi: wi,
+        })
+    }
+
+    fn pdf(&self, wo: &Point, wi: &Point) -> f64 {
+        if same_hemisphere(wo, wi) { wi.z.abs() / PI } else { 0.0 }
+    }
+
+    fn albedo(&self) -> Spectrum {
+        self.albedo.clone()
+    }
+}
@@ -0,0 +1,329 @@
+use std::f64::consts::PI;
+
+use crate::scene::bsdf::{Bsdf, BsdfSample, Dielectric, TrowbridgeReitz,
+    same_hemisphere, reflect, cosine_sample_hemisphere};
+use crate::scene::primitives::{Point, Spectrum};
+use crate::scene::sampler::Sampler;
+
+// Disney style principled material (Burley 2012, 2015). One set of artist
+// friendly parameters, all in [0, 1] except ior, drives a layered mix of
+// diffuse, sheen, specular, clearcoat and transmission lobes.
+//
+// The clearcoat sits on top, whatever it doesn't reflect goes on to the
+// layers below. Below it metallic fades between dielectric and conductor
+// response, and transmission between the diffuse base and glass. Like
+// Disney's 2015 model the dielectric part is (1 - t) (diffuse + specular)
+// + t glass, the glass bringing its own fresnel reflection.
+#[derive(Clone)]
+pub struct Principled {
+    pub base_color: Spectrum,
+    pub metallic: f64,
+    pub roughness: f64,
+    // dielectric reflectance, 0.5 is 4% at normal incidence
+    pub specular: f64,
+    // tints dielectric specular towards the base color
+    pub specular_tint: f64,
+    // grazing retro-reflection for cloth
+    pub sheen: f64,
+    pub sheen_tint: f64,
+    pub clearcoat: f64,
+    pub clearcoat_gloss: f64,
+    pub transmission: f64,
+    // stretches highlights along the shading tangent
+    pub anisotropic: f64,
+    // index of refraction used by transmission
+    pub ior: f64,
+}
+
+// Lobes the material is made up of, used when picking one to sample
+enum Lobe {
+    Diffuse,
+    Specular,
+    Clearcoat,
+    Transmission,
+}
+
+impl Principled {
+    pub fn new(base_color: Spectrum) -> Self {
+        Principled {
+            base_color: base_color,
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+            anisotropic: 0.0,
+            ior: 1.5,
+        }
+    }
+
+    fn diffuse_weight(&self) -> f64 {
+        (1.0 - self.metallic) * (1.0 - self.transmission)
+    }
+
+    fn transmission_weight(&self) -> f64 {
+        (1.0 - self.metallic) * self.transmission
+    }
+
+    // the glass lobe reflects for itself, so the specular lobe gives way
+    // to it as transmission goes up
+    fn specular_weight(&self) -> f64 {
+        1.0 - self.transmission_weight()
+    }
+
+    // base color with its luminance normalized out
+    fn tint(&self) -> Spectrum {
+        let luminance = luminance(&self.base_color);
+        if luminance > 0.0 {
+            self.base_color.mult(1.0 / luminance)
+        }
+        else {
+            Spectrum::new(1.0, 1.0, 1.0)
+        }
+    }
+
+    // reflectance at normal incidence for the specular lobe
+    fn specular_color(&self) -> Spectrum {
+        let dielectric = lerp_spectrum(&Spectrum::new(1.0, 1.0, 1.0), &self.tint(), self.specular_tint)
+            .mult(0.08 * self.specular);
+        lerp_spectrum(&dielectric, &self.base_color, self.metallic)
+    }
+
+    fn specular_distribution(&self) -> TrowbridgeReitz {
+        let aspect = (1.0 - 0.9 * self.anisotropic).sqrt();
+        TrowbridgeReitz::from_anisotropic_roughness(
+            self.roughness / aspect.sqrt(),
+            self.roughness * aspect.sqrt())
+    }
+
+    fn glass(&self) -> Dielectric {
+        Dielectric::new(self.ior, TrowbridgeReitz::from_roughness(self.roughness))
+    }
+
+    fn clearcoat_alpha(&self) -> f64 {
+        lerp(0.1, 0.001, self.clearcoat_gloss)
+    }
+
+    // probability of sampling each lobe, roughly their share of the energy
+    fn lobe_weights(&self) -> [(Lobe, f64); 4] {
+        let diffuse = self.diffuse_weight() * luminance(&self.base_color);
+        let specular = luminance(&self.specular_color()).max(0.04) * self.specular_weight();
+        let clearcoat = 0.25 * self.clearcoat;
+        let transmission = self.transmission_weight();
+        let total = diffuse + specular + clearcoat + transmission;
+
+        [(Lobe::Diffuse, diffuse / total),
+         (Lobe::Specular, specular / total),
+         (Lobe::Clearcoat, clearcoat / total),
+         (Lobe::Transmission, transmission / total)]
+    }
+
+    // fraction of light the clearcoat lets through to the layers below
+    fn clearcoat_transmittance(&self, w: &Point) -> f64 {
+        1.0 - self.clearcoat * schlick(0.04, w.z.abs())
+    }
+
+    fn evaluate_reflection(&self, wo: &Point, wi: &Point) -> Spectrum {
+        let cos_o = wo.z.abs();
+        let cos_i = wi.z.abs();
+        let wm = wo.add(wi);
+        if cos_o == 0.0 || cos_i == 0.0 || wm.abs() == 0.0 {
+            return Spectrum::black();
+        }
+        let wm = wm.normalize();
+        let cos_d = wi.dot(&wm).abs();
+
+        // Burley diffuse, with a fresnel factor for grazing retro-reflection
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let diffuse_fresnel = (1.0 + (fd90 - 1.0) * schlick_weight(cos_i))
+            * (1.0 + (fd90 - 1.0) * schlick_weight(cos_o));
+        let diffuse = self.base_color.mult(diffuse_fresnel / PI);
+
+        let sheen_color = lerp_spectrum(&Spectrum::new(1.0, 1.0, 1.0), &self.tint(), self.sheen_tint);
+        let sheen = sheen_color.mult(self.sheen * schlick_weight(cos_d));
+
+        // the diffuse layer only sees light the specular layer lets through,
+        // on the way in and on the way out. Normalized so a surface seen
+        // head on keeps Burley's brightness
+        let f0 = 0.08 * self.specular;
+        let coupling = (1.0 - schlick(f0, cos_i)) * (1.0 - schlick(f0, cos_o)) / ((1.0 - f0) * (1.0 - f0));
+        let base = diffuse.add(&sheen).mult(self.diffuse_weight() * coupling);
+
+        let distribution = self.specular_distribution();
+        let fresnel = schlick_spectrum(&self.specular_color(), cos_d);
+        let specular = fresnel.mult(self.specular_weight() * distribution.d(&wm) * distribution.g(wo, wi) / (4.0 * cos_o * cos_i));
+
+        let attenuation = self.clearcoat_transmittance(wo) * self.clearcoat_transmittance(wi);
+        let under = base.add(&specular).mult(attenuation);
+
+        let clearcoat_g = TrowbridgeReitz::new(0.25, 0.25);
+        let clearcoat = self.clearcoat * gtr1(wm.z, self.clearcoat_alpha()) * schlick(0.04, cos_d)
+            * clearcoat_g.g(wo, wi) / (4.0 * cos_o * cos_i);
+
+        under.add(&Spectrum::new(clearcoat, clearcoat, clearcoat))
+    }
+
+    fn lobe_pdf(&self, lobe: &Lobe, wo: &Point, wi: &Point) -> f64 {
+        match lobe {
+            Lobe::Diffuse => {
+                if same_hemisphere(wo, wi) { wi.z.abs() / PI } else { 0.0 }
+            },
+            Lobe::Specular | Lobe::Clearcoat => {
+                if !same_hemisphere(wo, wi) {
+                    return 0.0;
+                }
+                let wm = wo.add(wi);
+                if wm.abs() == 0.0 {
+                    return 0.0;
+                }
+                let wm = wm.normalize();
+                let wm = if wm.z < 0.0 { wm.mult(-1.0) } else { wm };
+                let wo_up = if wo.z < 0.0 { wo.mult(-1.0) } else { wo.clone() };
+
+                let density = match lobe {
+                    Lobe::Specular => self.specular_distribution().visible_d(&wo_up, &wm),
+                    _ => gtr1(wm.z, self.clearcoat_alpha()) * wm.z,
+                };
+                density / (4.0 * wo_up.dot(&wm).abs())
+            },
+            Lobe::Transmission => self.glass().pdf(wo, wi),
+        }
+    }
+
+    fn sample_lobe(&self, lobe: &Lobe, wo: &Point, sampler: &mut Sampler) -> Option<Point> {
+        // lobes other than glass sample in the +z hemisphere, mirrored for wo below
+        let flip = if wo.z < 0.0 { -1.0 } else { 1.0 };
+        let wo_up = Point::from((wo.x, wo.y, wo.z * flip));
+
+        let wi = match lobe {
+            Lobe::Diffuse => cosine_sample_hemisphere(sampler),
+            Lobe::Specular => {
+                let wm = self.specular_distribution().sample_wm(&wo_up, sampler);
+                reflect(&wo_up, &wm)
+            },
+            Lobe::Clearcoat => {
+                let wm = sample_gtr1(self.clearcoat_alpha(), sampler);
+                reflect(&wo_up, &wm)
+            },
+            Lobe::Transmission => return self.glass().sample(wo, sampler).map(|sample| sample.wi),
+        };
+
+        if wi.z <= 0.0 {
+            None
+        }
+        else {
+            Some(Point::from((wi.x, wi.y, wi.z * flip)))
+        }
+    }
+}
+
+impl Bsdf for Principled {
+    fn evaluate(&self, wo: &Point, wi: &Point) -> Spectrum {
+        let mut value = Spectrum::black();
+        if same_hemisphere(wo, wi) {
+            value = self.evaluate_reflection(wo, wi);
+        }
+
+        let transmission = self.transmission_weight();
+        if transmission > 0.0 {
+            let attenuation = self.clearcoat_transmittance(wo) * self.clearcoat_transmittance(wi);
+            let glass = self.glass().evaluate(wo, wi);
+
+            // light passing through the glass is tinted on the way in
+            let glass = if same_hemisphere(wo, wi) { glass } else { glass.modulate(&self.base_color) };
+            value = value.add(&glass.mult(transmission * attenuation));
+        }
+
+        value
+    }
+
+    fn sample(&self, wo: &Point, sampler: &mut Sampler) -> Option<BsdfSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+
+        // pick a lobe, then evaluate the whole material in that direction
+        let choice = sampler.next_f64();
+        let mut accumulated = 0.0;
+        let weights = self.lobe_weights();
+        let mut chosen = &weights[weights.len() - 1].0;
+        for (lobe, weight) in weights.iter() {
+            accumulated += weight;
+            if choice < accumulated {
+                chosen = lobe;
+                break;
+            }
+        }
+
+        let wi = self.sample_lobe(chosen, wo, sampler)?;
+        let pdf = self.pdf(wo, &wi);
+        if pdf == 0.0 {
+            return None;
+        }
+
+        Some(BsdfSample {
+            value: self.evaluate(wo, &wi),
+            pdf: pdf,
+            wi: wi,
+        })
+    }
+
+    fn pdf(&self, wo: &Point, wi: &Point) -> f64 {
+        self.lobe_weights().iter().fold(0.0, |pdf, (lobe, weight)| {
+            if *weight > 0.0 {
+                pdf + weight * self.lobe_pdf(lobe, wo, wi)
+            }
+            else {
+                pdf
+            }
+        })
+    }
+
+    fn albedo(&self) -> Spectrum {
+        self.base_color.clone()
+    }
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+fn lerp_spectrum(a: &Spectrum, b: &Spectrum, t: f64) -> Spectrum {
+    a.mult(1.0 - t).add(&b.mult(t))
+}
+
+fn luminance(color: &Spectrum) -> f64 {
+    0.2126 * color.r + 0.7152 * color.g + 0.0722 * color.b
+}
+
+fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta).max(0.0).min(1.0).powi(5)
+}
+
+fn schlick(f0: f64, cos_theta: f64) -> f64 {
+    lerp(f0, 1.0, schlick_weight(cos_theta))
+}
+
+fn schlick_spectrum(f0: &Spectrum, cos_theta: f64) -> Spectrum {
+    lerp_spectrum(f0, &Spectrum::new(1.0, 1.0, 1.0), schlick_weight(cos_theta))
+}
+
+// Berry's distribution, the long tailed one Disney uses for clearcoat
+fn gtr1(cos_theta: f64, alpha: f64) -> f64 {
+    let alpha2 = alpha * alpha;
+    let t = 1.0 + (alpha2 - 1.0) * cos_theta * cos_theta;
+    (alpha2 - 1.0) / (PI * alpha2.ln() * t)
+}
+
+// microfacet normal distributed proportional to gtr1 * cos_theta
+fn sample_gtr1(alpha: f64, sampler: &mut Sampler) -> Point {
+    let alpha2 = alpha * alpha;
+    let cos_theta = ((1.0 - alpha2.powf(1.0 - sampler.next_f64())) / (1.0 - alpha2)).max(0.0).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * sampler.next_f64();
+    Point::from((sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta))
+}
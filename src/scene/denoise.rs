@@ -0,0 +1,114 @@
+// Edge avoiding a-trous wavelet filter (Dammertz et al. 2010).
+//
+// Repeated passes of a 5x5 B3 spline kernel, each spreading its taps twice
+// as far apart as the last, blur over growing areas cheaply. Taps whose
+// normal, depth or albedo differ from the center pixel are down weighted
+// so geometric and texture edges stay sharp, and the color weight tightens
+// each pass so shading detail that survived the early passes is kept.
+//
+// Filtering happens on color divided by albedo, so texture detail doesn't
+// have to survive the blur, and is multiplied back in afterwards.
+
+use crate::scene::primitives::Spectrum;
+use crate::scene::render_buffer::{RenderBuffer, Features};
+
+const PASSES: u32 = 5;
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+const SIGMA_COLOR: f64 = 0.8;
+const SIGMA_ALBEDO: f64 = 0.1;
+const NORMAL_POWER: i32 = 64;
+// allowed depth difference, relative to depth and per pixel of tap distance
+const SIGMA_DEPTH: f64 = 0.02;
+
+// smallest albedo divided out, so black surfaces don't blow up
+const MIN_ALBEDO: f64 = 0.01;
+
+pub fn denoise(buffer: &RenderBuffer) -> Vec<Spectrum> {
+    let mut irradiance = buffer.color.iter().zip(buffer.features.iter()).map(|(color, features)| {
+        divide(color, &features.albedo)
+    }).collect::<Vec<Spectrum>>();
+
+    let mut sigma_color = SIGMA_COLOR;
+    for pass in 0..PASSES {
+        irradiance = filter_pass(buffer, &irradiance, 1 << pass, sigma_color);
+        sigma_color /= 2.0;
+    }
+
+    irradiance.iter().zip(buffer.features.iter()).map(|(irradiance, features)| {
+        let albedo = clamp_albedo(&features.albedo);
+        irradiance.modulate(&albedo)
+    }).collect()
+}
+
+fn filter_pass(buffer: &RenderBuffer, input: &Vec<Spectrum>, step: i32, sigma_color: f64) -> Vec<Spectrum> {
+    let width = buffer.width as i32;
+    let height = buffer.height as i32;
+
+    let mut output = Vec::with_capacity(input.len());
+    for y in 0..height {
+        for x in 0..width {
+            let center = buffer.index(x as u32, y as u32);
+            let center_color = &input[center];
+            let center_features = &buffer.features[center];
+
+            let mut sum = Spectrum::black();
+            let mut total_weight = 0.0;
+            for (j, kernel_y) in KERNEL.iter().enumerate() {
+                for (i, kernel_x) in KERNEL.iter().enumerate() {
+                    let tap_x = x + (i as i32 - 2) * step;
+                    let tap_y = y + (j as i32 - 2) * step;
+                    if tap_x < 0 || tap_x >= width || tap_y < 0 || tap_y >= height {
+                        continue;
+                    }
+
+                    let tap = buffer.index(tap_x as u32, tap_y as u32);
+                    let color_distance = distance2(center_color, &input[tap]);
+                    let weight = kernel_x * kernel_y
+                        * (-color_distance / (sigma_color * sigma_color)).exp()
+                        * feature_weight(center_features, &buffer.features[tap], step);
+
+                    sum = sum.add(&input[tap].mult(weight));
+                    total_weight += weight;
+                }
+            }
+
+            // the center tap always has weight, unless the center pixel is broken
+            if total_weight > 0.0 {
+                output.push(sum.mult(1.0 / total_weight));
+            }
+            else {
+                output.push(center_color.clone());
+            }
+        }
+    }
+
+    output
+}
+
+fn feature_weight(center: &Features, tap: &Features, step: i32) -> f64 {
+    // background only blends with background
+    if center.depth.is_infinite() || tap.depth.is_infinite() {
+        return if center.depth.is_infinite() && tap.depth.is_infinite() { 1.0 } else { 0.0 };
+    }
+
+    let normal = center.normal.dot(&tap.normal).max(0.0).powi(NORMAL_POWER);
+    let depth_tolerance = SIGMA_DEPTH * center.depth * step as f64 + 1e-6;
+    let depth = (-(center.depth - tap.depth).abs() / depth_tolerance).exp();
+    let albedo = (-distance2(&center.albedo, &tap.albedo) / (SIGMA_ALBEDO * SIGMA_ALBEDO)).exp();
+
+    normal * depth * albedo
+}
+
+fn distance2(a: &Spectrum, b: &Spectrum) -> f64 {
+    (a.r - b.r).powi(2) + (a.g - b.g).powi(2) + (a.b - b.b).powi(2)
+}
+
+fn clamp_albedo(albedo: &Spectrum) -> Spectrum {
+    Spectrum::new(albedo.r.max(MIN_ALBEDO), albedo.g.max(MIN_ALBEDO), albedo.b.max(MIN_ALBEDO))
+}
+
+fn divide(color: &Spectrum, albedo: &Spectrum) -> Spectrum {
+    let albedo = clamp_albedo(albedo);
+    Spectrum::new(color.r / albedo.r, color.g / albedo.g, color.b / albedo.b)
+}
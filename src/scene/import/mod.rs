@@ -0,0 +1,8 @@
+mod obj;
+mod mtl;
+mod ply;
+mod strands;
+
+pub use obj::{load_obj, load_obj_polygons};
+pub use ply::{load_ply, load_ply_polygons};
+pub use strands::load_strands;
@@ -0,0 +1,191 @@
+// Wavefront MTL material libraries.
+//
+// Each newmtl becomes a Principled material:
+//     Kd      base color
+//     Ks      specular, by its luminance, 1 being the Principled maximum
+//     Ns      Phong exponent, converted to roughness
+//     Ni      index of refraction
+//     d / Tr  dissolve / transparency, the rest is transmission
+//     map_Kd  PPM texture multiplied into the base color
+// Other statements are skipped.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::scene::bsdf::{Principled, MaterialId};
+use crate::scene::material::Material;
+use crate::scene::primitives::Spectrum;
+use crate::scene::texture::ImageTexture;
+use crate::scene::load_err::LoadErr;
+
+// Read a library, adding its materials to the scene's table, and return
+// where each name ended up
+pub fn load_mtl(path: &Path, materials: &mut Vec<Material>) -> Result<HashMap<String, MaterialId>, LoadErr> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| LoadErr::from(format!("{}: {}", path.display(), err)))?;
+    parse_mtl(&text, path, materials)
+}
+
+// The library in text, textures being found next to path
+fn parse_mtl(text: &str, path: &Path, materials: &mut Vec<Material>) -> Result<HashMap<String, MaterialId>, LoadErr> {
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut parsed = Vec::<(String, Principled, Option<ImageTexture>)>::new();
+    for (line_index, line) in text.lines().enumerate() {
+        let fail = |message: String| LoadErr::from(format!("{} line {}: {}", path.display(), line_index + 1, message));
+
+        let content = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        let mut tokens = content.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let arguments = tokens.collect::<Vec<&str>>();
+
+        if keyword == "newmtl" {
+            if arguments.len() != 1 {
+                return Err(fail(String::from("newmtl needs exactly one name")));
+            }
+            let mut principled = Principled::new(Spectrum::new(0.8, 0.8, 0.8));
+            principled.specular = 0.0;
+            parsed.push((String::from(arguments[0]), principled, None));
+            continue;
+        }
+
+        let (_, principled, texture) = match parsed.last_mut() {
+            Some(current) => current,
+            None => return Err(fail(format!("'{}' before any newmtl", keyword))),
+        };
+
+        match keyword {
+            "Kd" => principled.base_color = parse_color(&arguments).map_err(fail)?,
+            "Ks" => {
+                let color = parse_color(&arguments).map_err(fail)?;
+                principled.specular = (0.2126 * color.r + 0.7152 * color.g + 0.0722 * color.b).max(0.0).min(1.0);
+            },
+            "Ns" => {
+                let exponent = parse_scalar(keyword, &arguments).map_err(fail)?.max(0.0);
+                // Phong exponent to microfacet alpha, and alpha to roughness
+                principled.roughness = (2.0 / (exponent + 2.0)).sqrt().sqrt();
+            },
+            "Ni" => principled.ior = parse_scalar(keyword, &arguments).map_err(fail)?,
+            "d" => principled.transmission = 1.0 - parse_scalar(keyword, &arguments).map_err(fail)?.max(0.0).min(1.0),
+            "Tr" => principled.transmission = parse_scalar(keyword, &arguments).map_err(fail)?.max(0.0).min(1.0),
+            "map_Kd" => {
+                // options come before the file name, which is last
+                let file = arguments.last().ok_or_else(|| fail(String::from("map_Kd needs a file name")))?;
+                *texture = Some(ImageTexture::load(directory.join(file))?);
+            },
+            _ => (),
+        }
+    }
+
+    Ok(parsed.into_iter().map(|(name, principled, texture)| {
+        let id = materials.len();
+        materials.push(match texture {
+            Some(texture) => Material::textured(principled, texture),
+            None => Material::new(Box::new(principled)),
+        });
+        (name, id)
+    }).collect())
+}
+
+fn parse_scalar(keyword: &str, arguments: &[&str]) -> Result<f64, String> {
+    match arguments {
+        [value] => value.parse().map_err(|_| format!("bad {} value '{}'", keyword, value)),
+        _ => Err(format!("{} needs exactly one value", keyword)),
+    }
+}
+
+// r g b, or a single gray value
+fn parse_color(arguments: &[&str]) -> Result<Spectrum, String> {
+    let values = arguments.iter().map(|value| {
+        value.parse::<f64>().map_err(|_| format!("bad color component '{}'", value))
+    }).collect::<Result<Vec<f64>, String>>()?;
+
+    match values.as_slice() {
+        [gray] => Ok(Spectrum::new(*gray, *gray, *gray)),
+        [r, g, b] => Ok(Spectrum::new(*r, *g, *b)),
+        _ => Err(String::from("colors need one or three values")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::bsdf::Bsdf;
+    use crate::scene::object::{Intersect, Sphere};
+    use crate::scene::primitives::{Point, Ray};
+    use crate::scene::LightRay;
+
+    fn parse(text: &str, materials: &mut Vec<Material>) -> Result<HashMap<String, MaterialId>, LoadErr> {
+        parse_mtl(text, Path::new("models/colors.mtl"), materials)
+    }
+
+    // the material's color, for any point as nothing here is textured
+    fn base_color(material: &Material) -> Spectrum {
+        let sphere = Sphere::new(Point::from((5.0, 0.0, 0.0)), 0, 1.0);
+        let hit = sphere.intersect(&LightRay::new(Ray::new(Point::from((0.0, 0.0, 0.0)), Point::from((1.0, 0.0, 0.0))))).unwrap();
+        material.at(&hit).albedo()
+    }
+
+    fn fails(result: Result<HashMap<String, MaterialId>, LoadErr>, message: &str) {
+        match result {
+            Ok(_) => panic!("loaded, expected '{}'", message),
+            Err(err) => assert_eq!(format!("{}", err), message),
+        }
+    }
+
+    #[test]
+    fn names_and_colors() {
+        // already in the scene's table
+        let mut materials = vec![Material::new(Box::new(Principled::new(Spectrum::black())))];
+        let library = parse("
+            # two colors
+            newmtl red
+            Kd 1 0 0
+            Ks 0.5
+            Ns 10
+            newmtl gray
+            Kd 0.25
+            d 0.5
+            Tr 0.1
+            Ni 1.5
+            illum 2
+            ", &mut materials).unwrap();
+
+        assert_eq!(materials.len(), 3);
+        assert_eq!(library.len(), 2);
+        assert_eq!(library["red"], 1);
+        assert_eq!(library["gray"], 2);
+        let red = base_color(&materials[1]);
+        assert_eq!((red.r, red.g, red.b), (1.0, 0.0, 0.0));
+        let gray = base_color(&materials[2]);
+        assert_eq!((gray.r, gray.g, gray.b), (0.25, 0.25, 0.25));
+    }
+
+    #[test]
+    fn rejects_bad_statements() {
+        let mut materials = Vec::new();
+        fails(parse("Kd 1 0 0\n", &mut materials), "models/colors.mtl line 1: 'Kd' before any newmtl");
+        fails(parse("newmtl\n", &mut materials), "models/colors.mtl line 1: newmtl needs exactly one name");
+        fails(parse("newmtl a\nKd 1 0\n", &mut materials), "models/colors.mtl line 2: colors need one or three values");
+        fails(parse("newmtl a\nKd 1 red 0\n", &mut materials), "models/colors.mtl line 2: bad color component 'red'");
+        fails(parse("newmtl a\nNs\n", &mut materials), "models/colors.mtl line 2: Ns needs exactly one value");
+        fails(parse("newmtl a\nNi glass\n", &mut materials), "models/colors.mtl line 2: bad Ni value 'glass'");
+        fails(parse("newmtl a\nmap_Kd\n", &mut materials), "models/colors.mtl line 2: map_Kd needs a file name");
+        // nothing is added when the library fails
+        assert!(materials.is_empty());
+    }
+
+    #[test]
+    fn rejects_missing_texture() {
+        let mut materials = Vec::new();
+        assert!(parse("newmtl a\nmap_Kd -s 2 2 1 missing.ppm\n", &mut materials).is_err());
+        assert!(materials.is_empty());
+    }
+}
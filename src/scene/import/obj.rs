@@ -0,0 +1,470 @@
+// Wavefront OBJ models.
+//
+// Reads positions (v), texture coordinates (vt), normals (vn) and polygon
+// faces (f), which are triangulated as fans or kept whole for subdivision.
+// Face corners are v, v/vt, v//vn or v/vt/vn, with negative indices
+// counting back from the latest element. Material libraries (mtllib) are
+// read relative to the model and their materials picked by usemtl.
+//
+// The model is split into one mesh per group (g or o) and material. Lines,
+// points, smoothing groups and other statements are skipped.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::scene::object::TriangleMesh;
+use crate::scene::import::mtl::load_mtl;
+use crate::scene::bsdf::{Principled, MaterialId};
+use crate::scene::material::Material;
+use crate::scene::primitives::{Point, Spectrum};
+use crate::scene::load_err::LoadErr;
+
+// corner of a face, indices into the model's positions, uvs and normals
+type Corner = (usize, Option<usize>, Option<usize>);
+
+// One mesh of the model being put together, with its own vertex buffers
+struct MeshBuilder {
+    material: MaterialId,
+    positions: Vec<Point>,
+    uvs: Vec<(f64, f64)>,
+    normals: Vec<Point>,
+    triangles: Vec<[usize; 3]>,
+    vertices: HashMap<Corner, usize>,
+    // uvs and normals are only kept when every corner has them
+    all_uvs: bool,
+    all_normals: bool,
+}
+
+impl MeshBuilder {
+    fn new(material: MaterialId) -> Self {
+        MeshBuilder {
+            material: material,
+            positions: Vec::new(),
+            uvs: Vec::new(),
+            normals: Vec::new(),
+            triangles: Vec::new(),
+            vertices: HashMap::new(),
+            all_uvs: true,
+            all_normals: true,
+        }
+    }
+
+    // index of the mesh vertex for a corner, shared by every face using it
+    fn vertex(&mut self, corner: Corner, positions: &[Point], uvs: &[(f64, f64)], normals: &[Point]) -> usize {
+        if let Some(index) = self.vertices.get(&corner) {
+            return *index;
+        }
+
+        let (position, uv, normal) = corner;
+        self.positions.push(positions[position].clone());
+        self.all_uvs &= uv.is_some();
+        self.uvs.push(uv.map_or((0.0, 0.0), |uv| uvs[uv]));
+        self.all_normals &= normal.is_some();
+        self.normals.push(normal.map_or(Point::from((0.0, 0.0, 0.0)), |normal| normals[normal].clone()));
+
+        let index = self.positions.len() - 1;
+        self.vertices.insert(corner, index);
+        index
+    }
+
+    fn build(self) -> Result<TriangleMesh, LoadErr> {
+        TriangleMesh::new(
+            self.positions,
+            if self.all_normals { Some(self.normals) } else { None },
+            if self.all_uvs { Some(self.uvs) } else { None },
+            None,
+            self.triangles,
+            self.material)
+    }
+}
+
+// Read a model, adding its materials to the scene's table
+pub fn load_obj<P: AsRef<Path>>(path: P, materials: &mut Vec<Material>) -> Result<Vec<TriangleMesh>, LoadErr> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)
+        .map_err(|err| LoadErr::from(format!("{}: {}", path.display(), err)))?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+    parse_obj(&text, path, materials, &mut |file, materials| load_mtl(&directory.join(file), materials))
+}
+
+// The model in text, path only naming it in errors. Each mtllib file is
+// handed to load_library, which adds its materials to the table.
+fn parse_obj(
+    text: &str,
+    path: &Path,
+    materials: &mut Vec<Material>,
+    load_library: &mut dyn FnMut(&str, &mut Vec<Material>) -> Result<HashMap<String, MaterialId>, LoadErr>,
+) -> Result<Vec<TriangleMesh>, LoadErr> {
+    let mut positions = Vec::<Point>::new();
+    let mut uvs = Vec::<(f64, f64)>::new();
+    let mut normals = Vec::<Point>::new();
+
+    let mut library = HashMap::<String, MaterialId>::new();
+    let mut default_material = None;
+    let mut group = String::new();
+    let mut material = None;
+
+    // meshes in the order they were started, found by group and material
+    let mut meshes = Vec::<MeshBuilder>::new();
+    let mut mesh_index = HashMap::<(String, MaterialId), usize>::new();
+
+    for (line_index, line) in text.lines().enumerate() {
+        let fail = |message: String| LoadErr::from(format!("{} line {}: {}", path.display(), line_index + 1, message));
+
+        let content = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        let mut tokens = content.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let arguments = tokens.collect::<Vec<&str>>();
+
+        match keyword {
+            "v" => {
+                let values = parse_floats(keyword, &arguments, 3, 4).map_err(fail)?;
+                positions.push(Point::from((values[0], values[1], values[2])));
+            },
+            "vt" => {
+                let values = parse_floats(keyword, &arguments, 1, 3).map_err(fail)?;
+                uvs.push((values[0], values.get(1).cloned().unwrap_or(0.0)));
+            },
+            "vn" => {
+                let values = parse_floats(keyword, &arguments, 3, 3).map_err(fail)?;
+                normals.push(Point::from((values[0], values[1], values[2])));
+            },
+            "f" => {
+                if arguments.len() < 3 {
+                    return Err(fail(format!("face needs at least 3 corners, found {}", arguments.len())));
+                }
+                let corners = arguments.iter().map(|corner| {
+                    parse_corner(corner, positions.len(), uvs.len(), normals.len())
+                }).collect::<Result<Vec<Corner>, String>>().map_err(fail)?;
+
+                let material = match material {
+                    Some(material) => material,
+                    None => *default_material.get_or_insert_with(|| {
+                        materials.push(Material::new(Box::new(Principled::new(Spectrum::new(0.8, 0.8, 0.8)))));
+                        materials.len() - 1
+                    }),
+                };
+                let key = (group.clone(), material);
+                let index = *mesh_index.entry(key).or_insert_with(|| {
+                    meshes.push(MeshBuilder::new(material));
+                    meshes.len() - 1
+                });
+
+                let mesh = &mut meshes[index];
+                let vertices = corners.into_iter()
+                    .map(|corner| mesh.vertex(corner, &positions, &uvs, &normals))
+                    .collect::<Vec<usize>>();
+                for i in 1..vertices.len() - 1 {
+                    mesh.triangles.push([vertices[0], vertices[i], vertices[i + 1]]);
+                }
+            },
+            "g" | "o" => group = arguments.join(" "),
+            "mtllib" => {
+                if arguments.is_empty() {
+                    return Err(fail(String::from("mtllib needs a file name")));
+                }
+                for file in arguments.iter() {
+                    library.extend(load_library(file, materials)?);
+                }
+            },
+            "usemtl" => {
+                let name = arguments.join(" ");
+                material = Some(*library.get(&name)
+                    .ok_or_else(|| fail(format!("unknown material '{}'", name)))?);
+            },
+            _ => (),
+        }
+    }
+
+    meshes.into_iter()
+        .filter(|mesh| !mesh.triangles.is_empty())
+        .map(|mesh| mesh.build().map_err(|err| LoadErr::from(format!("{}: {}", path.display(), err))))
+        .collect()
+}
+
+// Positions and faces of a model, the faces left as polygons over the
+// shared positions, for meshes that are subdivided before being rendered.
+// Uvs, normals, groups and materials are passed over.
+pub fn load_obj_polygons<P: AsRef<Path>>(path: P) -> Result<(Vec<Point>, Vec<Vec<usize>>), LoadErr> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)
+        .map_err(|err| LoadErr::from(format!("{}: {}", path.display(), err)))?;
+    parse_obj_polygons(&text, path)
+}
+
+fn parse_obj_polygons(text: &str, path: &Path) -> Result<(Vec<Point>, Vec<Vec<usize>>), LoadErr> {
+    let mut positions = Vec::<Point>::new();
+    // only counted, so face corners using them can be checked
+    let mut uvs = 0;
+    let mut normals = 0;
+    let mut faces = Vec::<Vec<usize>>::new();
+
+    for (line_index, line) in text.lines().enumerate() {
+        let fail = |message: String| LoadErr::from(format!("{} line {}: {}", path.display(), line_index + 1, message));
+
+        let content = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        let mut tokens = content.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let arguments = tokens.collect::<Vec<&str>>();
+
+        match keyword {
+            "v" => {
+                let values = parse_floats(keyword, &arguments, 3, 4).map_err(fail)?;
+                positions.push(Point::from((values[0], values[1], values[2])));
+            },
+            "vt" => uvs += 1,
+            "vn" => normals += 1,
+            "f" => {
+                if arguments.len() < 3 {
+                    return Err(fail(format!("face needs at least 3 corners, found {}", arguments.len())));
+                }
+                faces.push(arguments.iter().map(|corner| {
+                    parse_corner(corner, positions.len(), uvs, normals).map(|(position, _, _)| position)
+                }).collect::<Result<Vec<usize>, String>>().map_err(fail)?);
+            },
+            _ => (),
+        }
+    }
+
+    Ok((positions, faces))
+}
+
+fn parse_floats(keyword: &str, arguments: &[&str], min: usize, max: usize) -> Result<Vec<f64>, String> {
+    if arguments.len() < min || arguments.len() > max {
+        return Err(format!("{} needs {} to {} values, found {}", keyword, min, max, arguments.len()));
+    }
+    arguments.iter().map(|value| {
+        value.parse().map_err(|_| format!("bad {} value '{}'", keyword, value))
+    }).collect()
+}
+
+// Parse one of v, v/vt, v//vn or v/vt/vn into zero based indices
+fn parse_corner(corner: &str, positions: usize, uvs: usize, normals: usize) -> Result<Corner, String> {
+    let parts = corner.split('/').collect::<Vec<&str>>();
+    if parts.len() > 3 {
+        return Err(format!("bad face corner '{}'", corner));
+    }
+
+    let resolve = |part: &str, count: usize, name: &str| -> Result<usize, String> {
+        let index = part.parse::<i64>()
+            .map_err(|_| format!("bad {} index '{}' in face corner '{}'", name, part, corner))?;
+        let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+        if index == 0 || resolved < 0 || resolved >= count as i64 {
+            return Err(format!("{} index {} out of range, there are {}", name, index, count));
+        }
+        Ok(resolved as usize)
+    };
+    let optional = |index: usize, count: usize, name: &str| -> Result<Option<usize>, String> {
+        match parts.get(index) {
+            None | Some(&"") => Ok(None),
+            Some(part) => resolve(part, count, name).map(Some),
+        }
+    };
+
+    Ok((resolve(parts[0], positions, "position")?, optional(1, uvs, "uv")?, optional(2, normals, "normal")?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::object::{Intersect, Hit};
+    use crate::scene::primitives::Ray;
+    use crate::scene::LightRay;
+
+    // red and blue, from any library
+    fn library(file: &str, materials: &mut Vec<Material>) -> Result<HashMap<String, MaterialId>, LoadErr> {
+        assert_eq!(file, "colors.mtl");
+        let mut library = HashMap::new();
+        for (name, color) in [("red", Spectrum::new(1.0, 0.0, 0.0)), ("blue", Spectrum::new(0.0, 0.0, 1.0))].iter() {
+            materials.push(Material::new(Box::new(Principled::new(color.clone()))));
+            library.insert(String::from(*name), materials.len() - 1);
+        }
+        Ok(library)
+    }
+
+    fn parse(text: &str, materials: &mut Vec<Material>) -> Result<Vec<TriangleMesh>, LoadErr> {
+        parse_obj(text, Path::new("model.obj"), materials, &mut library)
+    }
+
+    // straight down onto the z = 0 plane
+    fn hit(mesh: &TriangleMesh, x: f64, y: f64) -> Option<Hit> {
+        mesh.intersect(&LightRay::new(Ray::new(Point::from((x, y, 5.0)), Point::from((0.0, 0.0, -1.0)))))
+    }
+
+    fn fails(result: Result<Vec<TriangleMesh>, LoadErr>, message: &str) {
+        match result {
+            Ok(_) => panic!("loaded, expected '{}'", message),
+            Err(err) => assert_eq!(format!("{}", err), message),
+        }
+    }
+
+    #[test]
+    fn corner_forms() {
+        assert_eq!(parse_corner("3", 4, 3, 2), Ok((2, None, None)));
+        assert_eq!(parse_corner("3/2", 4, 3, 2), Ok((2, Some(1), None)));
+        assert_eq!(parse_corner("3//1", 4, 3, 2), Ok((2, None, Some(0))));
+        assert_eq!(parse_corner("3/2/1", 4, 3, 2), Ok((2, Some(1), Some(0))));
+        assert_eq!(parse_corner("4//", 4, 3, 2), Ok((3, None, None)));
+    }
+
+    #[test]
+    fn negative_corners_count_back() {
+        assert_eq!(parse_corner("-1/-1/-1", 4, 3, 2), Ok((3, Some(2), Some(1))));
+        assert_eq!(parse_corner("-4//-2", 4, 3, 2), Ok((0, None, Some(0))));
+    }
+
+    #[test]
+    fn rejects_bad_corners() {
+        assert_eq!(parse_corner("0", 4, 3, 2), Err(String::from("position index 0 out of range, there are 4")));
+        assert_eq!(parse_corner("5", 4, 3, 2), Err(String::from("position index 5 out of range, there are 4")));
+        assert_eq!(parse_corner("-5", 4, 3, 2), Err(String::from("position index -5 out of range, there are 4")));
+        assert_eq!(parse_corner("1/4", 4, 3, 2), Err(String::from("uv index 4 out of range, there are 3")));
+        assert_eq!(parse_corner("1//0", 4, 3, 2), Err(String::from("normal index 0 out of range, there are 2")));
+        assert_eq!(parse_corner("1/x", 4, 3, 2), Err(String::from("bad uv index 'x' in face corner '1/x'")));
+        assert_eq!(parse_corner("1/1/1/1", 4, 3, 2), Err(String::from("bad face corner '1/1/1/1'")));
+        assert!(parse_corner("", 4, 3, 2).is_err());
+    }
+
+    #[test]
+    fn negative_indices_in_faces() {
+        let mut materials = Vec::new();
+        let meshes = parse("
+            v 9 9 9
+            v 0 0 0
+            v 1 0 0
+            v 0 1 0
+            f -3 -2 -1 # the last three
+            ", &mut materials).unwrap();
+        assert_eq!(meshes.len(), 1);
+        assert!(hit(&meshes[0], 0.2, 0.2).is_some());
+        assert!(hit(&meshes[0], 0.8, 0.8).is_none());
+    }
+
+    #[test]
+    fn corner_normals_shade() {
+        let mut materials = Vec::new();
+        let meshes = parse("
+            v 0 0 0
+            v 1 0 0
+            v 0 1 0
+            vn 0.6 0 0.8
+            f 1//1 2//1 3//1
+            ", &mut materials).unwrap();
+        let hit = hit(&meshes[0], 0.2, 0.2).unwrap();
+        assert!(hit.shading_normal.add(&Point::from((0.6, 0.0, 0.8)).mult(-1.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fans_polygons() {
+        let mut materials = Vec::new();
+        let meshes = parse("
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            f 1 2 3 4
+            ", &mut materials).unwrap();
+        assert_eq!(hit(&meshes[0], 0.8, 0.2).unwrap().primitive, 0);
+        assert_eq!(hit(&meshes[0], 0.2, 0.8).unwrap().primitive, 1);
+    }
+
+    #[test]
+    fn splits_by_group_and_material() {
+        let mut materials = Vec::new();
+        // faces along x, each tagged with its group and material
+        let meshes = parse("
+            mtllib colors.mtl
+            v 0 0 0
+            v 1 0 0
+            v 0 1 0
+            v 2 0 0
+            v 3 0 0
+            v 2 1 0
+            v 4 0 0
+            v 5 0 0
+            v 4 1 0
+            v 6 0 0
+            v 7 0 0
+            v 6 1 0
+            v 8 0 0
+            v 9 0 0
+            v 8 1 0
+            f 1 2 3
+            g a
+            usemtl red
+            f 4 5 6
+            usemtl blue
+            f 7 8 9
+            o b
+            usemtl red
+            f 10 11 12
+            g a
+            f 13 14 15
+            ", &mut materials).unwrap();
+
+        // red and blue, then the default for the first face
+        assert_eq!(materials.len(), 3);
+        let found = meshes.iter().map(|mesh| {
+            (0..5).filter_map(|face| hit(mesh, 2.0 * face as f64 + 0.2, 0.2).map(|hit| (face, hit.primitive, hit.material)))
+                .collect::<Vec<(usize, usize, Option<MaterialId>)>>()
+        }).collect::<Vec<_>>();
+        assert_eq!(found, vec![
+            vec![(0, 0, Some(2))],
+            vec![(1, 0, Some(0)), (4, 1, Some(0))],
+            vec![(2, 0, Some(1))],
+            vec![(3, 0, Some(0))]]);
+    }
+
+    #[test]
+    fn rejects_unknown_material() {
+        let mut materials = Vec::new();
+        fails(parse("mtllib colors.mtl\nusemtl green\n", &mut materials), "model.obj line 2: unknown material 'green'");
+        fails(parse("usemtl red\n", &mut materials), "model.obj line 1: unknown material 'red'");
+    }
+
+    #[test]
+    fn rejects_bad_faces() {
+        let mut materials = Vec::new();
+        fails(parse("v 0 0 0\nv 1 0 0\nf 1 2\n", &mut materials), "model.obj line 3: face needs at least 3 corners, found 2");
+        fails(parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n", &mut materials),
+            "model.obj line 4: position index 4 out of range, there are 3");
+        fails(parse("v 0 0\n", &mut materials), "model.obj line 1: v needs 3 to 4 values, found 2");
+        fails(parse("vn 0 x 1\n", &mut materials), "model.obj line 1: bad vn value 'x'");
+    }
+
+    #[test]
+    fn polygons_stay_whole() {
+        let (positions, faces) = parse_obj_polygons("
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            vt 0 0
+            vn 0 0 1
+            f 1/1/1 2/1/1 3/1/1 4/1/1
+            usemtl ignored
+            f -4 -3 -1
+            ", Path::new("cage.obj")).unwrap();
+        assert_eq!(positions.len(), 4);
+        assert_eq!(faces, vec![vec![0, 1, 2, 3], vec![0, 1, 3]]);
+
+        match parse_obj_polygons("v 0 0 0\nf 1 1 2\n", Path::new("cage.obj")) {
+            Ok(_) => panic!("loaded a face past the positions"),
+            Err(err) => assert_eq!(format!("{}", err), "cage.obj line 2: position index 2 out of range, there are 1"),
+        }
+    }
+}
@@ -0,0 +1,491 @@
+// Stanford PLY meshes, with ascii, binary little endian or binary big
+// endian bodies.
+//
+// Vertex properties read are x y z, nx ny nz, red green blue (integer
+// types scaled from their full range, floats taken as is) and uvs named
+// u v, s t or texture_u texture_v. Faces come from a vertex_indices (or
+// vertex_index) list and are triangulated as fans, or kept whole for
+// subdivision. Other elements and properties are read past and ignored.
+
+use std::fs;
+use std::path::Path;
+
+use crate::scene::object::TriangleMesh;
+use crate::scene::bsdf::MaterialId;
+use crate::scene::primitives::{Point, Spectrum};
+use crate::scene::load_err::LoadErr;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        match name {
+            "char" | "int8" => Some(Scalar::I8),
+            "uchar" | "uint8" => Some(Scalar::U8),
+            "short" | "int16" => Some(Scalar::I16),
+            "ushort" | "uint16" => Some(Scalar::U16),
+            "int" | "int32" => Some(Scalar::I32),
+            "uint" | "uint32" => Some(Scalar::U32),
+            "float" | "float32" => Some(Scalar::F32),
+            "double" | "float64" => Some(Scalar::F64),
+            _ => None,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    // largest value of integer types, colors stored in them are scaled by it
+    fn range(&self) -> f64 {
+        match self {
+            Scalar::I8 => 127.0,
+            Scalar::U8 => 255.0,
+            Scalar::I16 => 32767.0,
+            Scalar::U16 => 65535.0,
+            Scalar::I32 => 2147483647.0,
+            Scalar::U32 => 4294967295.0,
+            Scalar::F32 | Scalar::F64 => 1.0,
+        }
+    }
+}
+
+enum Property {
+    Single(String, Scalar),
+    // count type, item type
+    List(String, Scalar, Scalar),
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// Reads values one at a time from the body, whichever format it is in
+struct Body<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    position: usize,
+    // line of the current ascii token, for errors
+    line: usize,
+}
+
+impl<'a> Body<'a> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64, String> {
+        if self.format == Format::Ascii {
+            return self.read_ascii();
+        }
+
+        let size = scalar.size();
+        let bytes = self.bytes.get(self.position..self.position + size)
+            .ok_or_else(|| String::from("body ends early"))?;
+        self.position += size;
+
+        let mut word = [0u8; 8];
+        word[..size].copy_from_slice(bytes);
+        if self.format == Format::BigEndian {
+            word[..size].reverse();
+        }
+
+        Ok(match scalar {
+            Scalar::I8 => word[0] as i8 as f64,
+            Scalar::U8 => word[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([word[0], word[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([word[0], word[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([word[0], word[1], word[2], word[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([word[0], word[1], word[2], word[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([word[0], word[1], word[2], word[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes(word),
+        })
+    }
+
+    fn read_ascii(&mut self) -> Result<f64, String> {
+        while self.position < self.bytes.len() && self.bytes[self.position].is_ascii_whitespace() {
+            if self.bytes[self.position] == b'\n' {
+                self.line += 1;
+            }
+            self.position += 1;
+        }
+
+        let start = self.position;
+        while self.position < self.bytes.len() && !self.bytes[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+        if start == self.position {
+            return Err(String::from("body ends early"));
+        }
+
+        let token = String::from_utf8_lossy(&self.bytes[start..self.position]);
+        token.parse().map_err(|_| format!("line {}: bad value '{}'", self.line, token))
+    }
+}
+
+// Read a mesh, all of it using the given material
+pub fn load_ply<P: AsRef<Path>>(path: P, material: MaterialId) -> Result<TriangleMesh, LoadErr> {
+    let path = path.as_ref();
+    let (positions, normals, uvs, colors, faces) = read(path)?;
+    let mut triangles = Vec::new();
+    for face in faces.iter() {
+        for i in 1..face.len() - 1 {
+            triangles.push([face[0], face[i], face[i + 1]]);
+        }
+    }
+    TriangleMesh::new(positions, normals, uvs, colors, triangles, material)
+        .map_err(|err| LoadErr::from(format!("{}: {}", path.display(), err)))
+}
+
+// Positions and faces of a mesh, the faces left as the polygons in the
+// file, for meshes that are subdivided before being rendered
+pub fn load_ply_polygons<P: AsRef<Path>>(path: P) -> Result<(Vec<Point>, Vec<Vec<usize>>), LoadErr> {
+    read(path.as_ref()).map(|(positions, _, _, _, faces)| (positions, faces))
+}
+
+type Parsed = (Vec<Point>, Option<Vec<Point>>, Option<Vec<(f64, f64)>>, Option<Vec<Spectrum>>, Vec<Vec<usize>>);
+
+fn read(path: &Path) -> Result<Parsed, LoadErr> {
+    let bytes = fs::read(path)
+        .map_err(|err| LoadErr::from(format!("{}: {}", path.display(), err)))?;
+    parse(&bytes).map_err(|err| LoadErr::from(format!("{}: {}", path.display(), err)))
+}
+
+fn parse(bytes: &[u8]) -> Result<Parsed, String> {
+    let (format, elements, body_start, body_line) = parse_header(bytes)?;
+    let mut body = Body {
+        format: format,
+        bytes: bytes,
+        position: body_start,
+        line: body_line,
+    };
+
+    let mut positions = Vec::<Point>::new();
+    let mut normals = Vec::<Point>::new();
+    let mut uvs = Vec::<(f64, f64)>::new();
+    let mut colors = Vec::<Spectrum>::new();
+    let mut faces = Vec::<Vec<usize>>::new();
+    let mut has = (false, false, false);
+
+    for element in elements.iter() {
+        let index_of = |names: &[&str]| element.properties.iter().position(|property| match property {
+            Property::Single(name, _) => names.contains(&name.as_str()),
+            Property::List(..) => false,
+        });
+
+        // indices of a group of properties, when the element has all of them
+        let all = |indices: &[Option<usize>]| indices.iter().cloned().collect::<Option<Vec<usize>>>();
+
+        if element.name == "vertex" {
+            let position = all(&[index_of(&["x"]), index_of(&["y"]), index_of(&["z"])])
+                .ok_or_else(|| String::from("vertices need x, y and z"))?;
+            let normal = all(&[index_of(&["nx"]), index_of(&["ny"]), index_of(&["nz"])]);
+            let uv = all(&[index_of(&["u", "s", "texture_u"]), index_of(&["v", "t", "texture_v"])]);
+            // with the range each channel is scaled by
+            let color = all(&[index_of(&["red"]), index_of(&["green"]), index_of(&["blue"])]).map(|color| {
+                color.into_iter().map(|index| match &element.properties[index] {
+                    Property::Single(_, scalar) => (index, scalar.range()),
+                    Property::List(..) => (index, 1.0),
+                }).collect::<Vec<(usize, f64)>>()
+            });
+            has = (normal.is_some(), uv.is_some(), color.is_some());
+
+            for _vertex in 0..element.count {
+                let mut values = Vec::with_capacity(element.properties.len());
+                for property in element.properties.iter() {
+                    match property {
+                        Property::Single(_, scalar) => values.push(body.read(*scalar)?),
+                        Property::List(_, count, item) => {
+                            for _item in 0..body.read(*count)? as usize {
+                                body.read(*item)?;
+                            }
+                            values.push(0.0);
+                        },
+                    }
+                }
+
+                positions.push(Point::from((values[position[0]], values[position[1]], values[position[2]])));
+                if let Some(normal) = &normal {
+                    normals.push(Point::from((values[normal[0]], values[normal[1]], values[normal[2]])));
+                }
+                if let Some(uv) = &uv {
+                    uvs.push((values[uv[0]], values[uv[1]]));
+                }
+                if let Some(color) = &color {
+                    let channel = |(index, range): (usize, f64)| values[index] / range;
+                    colors.push(Spectrum::new(channel(color[0]), channel(color[1]), channel(color[2])));
+                }
+            }
+        }
+        else {
+            let is_face = element.name == "face";
+            for face in 0..element.count {
+                for property in element.properties.iter() {
+                    match property {
+                        Property::Single(_, scalar) => {
+                            body.read(*scalar)?;
+                        },
+                        Property::List(name, count, item) => {
+                            // not preallocated, the count comes straight from
+                            // the file and running out of body ends the list
+                            let count = body.read(*count)? as usize;
+                            let mut indices = Vec::new();
+                            for _item in 0..count {
+                                indices.push(body.read(*item)?);
+                            }
+
+                            if !is_face || (name != "vertex_indices" && name != "vertex_index") {
+                                continue;
+                            }
+                            if count < 3 {
+                                return Err(format!("face {} has {} vertices, needs at least 3", face, count));
+                            }
+                            let indices = indices.into_iter().map(|index| {
+                                if index < 0.0 || index as usize >= positions.len() {
+                                    Err(format!("face {} uses vertex {}, there are {}", face, index, positions.len()))
+                                }
+                                else {
+                                    Ok(index as usize)
+                                }
+                            }).collect::<Result<Vec<usize>, String>>()?;
+                            faces.push(indices);
+                        },
+                    }
+                }
+            }
+        }
+    }
+
+    Ok((
+        positions,
+        if has.0 { Some(normals) } else { None },
+        if has.1 { Some(uvs) } else { None },
+        if has.2 { Some(colors) } else { None },
+        faces))
+}
+
+// Returns the body format, the elements in order, and where the body
+// starts along with its first line number
+fn parse_header(bytes: &[u8]) -> Result<(Format, Vec<Element>, usize, usize), String> {
+    let mut format = None;
+    let mut elements = Vec::<Element>::new();
+    let mut position = 0;
+    let mut line_number = 0;
+
+    loop {
+        let end = bytes[position..].iter().position(|byte| *byte == b'\n')
+            .ok_or_else(|| String::from("header has no end_header"))?;
+        let line = String::from_utf8_lossy(&bytes[position..position + end]);
+        position += end + 1;
+        line_number += 1;
+
+        let tokens = line.split_whitespace().collect::<Vec<&str>>();
+        let fail = |message: &str| format!("line {}: {}", line_number, message);
+
+        if line_number == 1 {
+            if tokens != ["ply"] {
+                return Err(String::from("not a PLY file"));
+            }
+            continue;
+        }
+
+        match tokens.as_slice() {
+            [] | ["comment", ..] | ["obj_info", ..] => (),
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::LittleEndian,
+                    "binary_big_endian" => Format::BigEndian,
+                    _ => return Err(fail(&format!("unknown format '{}'", name))),
+                });
+            },
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| fail(&format!("bad element count '{}'", count)))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let element = elements.last_mut().ok_or_else(|| fail("property before any element"))?;
+                let count = Scalar::parse(count).ok_or_else(|| fail(&format!("unknown type '{}'", count)))?;
+                let item = Scalar::parse(item).ok_or_else(|| fail(&format!("unknown type '{}'", item)))?;
+                element.properties.push(Property::List(name.to_string(), count, item));
+            },
+            ["property", scalar, name] => {
+                let element = elements.last_mut().ok_or_else(|| fail("property before any element"))?;
+                let scalar = Scalar::parse(scalar).ok_or_else(|| fail(&format!("unknown type '{}'", scalar)))?;
+                element.properties.push(Property::Single(name.to_string(), scalar));
+            },
+            ["end_header"] => break,
+            _ => return Err(fail(&format!("malformed header line '{}'", line.trim()))),
+        }
+    }
+
+    let format = format.ok_or_else(|| String::from("header has no format"))?;
+    Ok((format, elements, position, line_number + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a colored square, with a list on each vertex and a short on the face
+    // that are read past
+    fn header(format: &str, vertices: &str) -> String {
+        format!("ply\nformat {} 1.0\ncomment a square\nelement vertex {}\nproperty float x\nproperty float y\nproperty float z\n\
+            property uchar red\nproperty uchar green\nproperty uchar blue\nproperty list uchar int extra\n\
+            element face 1\nproperty list uchar uint vertex_indices\nproperty short flags\nend_header\n", format, vertices)
+    }
+
+    const CORNERS: [(f32, f32, f32); 4] = [(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (1.0, 1.0, 0.0), (0.0, 1.0, 0.0)];
+    const COLORS: [(u8, u8, u8); 4] = [(255, 0, 0), (0, 255, 0), (0, 0, 255), (255, 255, 255)];
+
+    fn ascii() -> Vec<u8> {
+        let mut text = header("ascii", "4");
+        for (i, ((x, y, z), (r, g, b))) in CORNERS.iter().zip(COLORS.iter()).enumerate() {
+            let extra = if i == 2 { String::from("0") } else { String::from("2 7 -8") };
+            text += &format!("{} {} {}  {} {} {} {}\n", x, y, z, r, g, b, extra);
+        }
+        text += "4 0 1 2 3 -3\n";
+        text.into_bytes()
+    }
+
+    fn binary(big_endian: bool) -> Vec<u8> {
+        let mut bytes = header(if big_endian { "binary_big_endian" } else { "binary_little_endian" }, "4").into_bytes();
+        let mut push = |word: &[u8]| {
+            let mut word = word.to_vec();
+            if big_endian {
+                word.reverse();
+            }
+            bytes.extend(word);
+        };
+        for (i, ((x, y, z), (r, g, b))) in CORNERS.iter().zip(COLORS.iter()).enumerate() {
+            for value in [x, y, z].iter() {
+                push(&value.to_le_bytes());
+            }
+            for channel in [r, g, b].iter() {
+                push(&[**channel]);
+            }
+            if i == 2 {
+                push(&[0]);
+            }
+            else {
+                push(&[2]);
+                push(&7i32.to_le_bytes());
+                push(&(-8i32).to_le_bytes());
+            }
+        }
+        push(&[4]);
+        for index in 0..4u32 {
+            push(&index.to_le_bytes());
+        }
+        push(&(-3i16).to_le_bytes());
+        bytes
+    }
+
+    fn fails(bytes: &[u8], message: &str) {
+        match parse(bytes) {
+            Ok(_) => panic!("parsed, expected '{}'", message),
+            Err(err) => assert_eq!(err, message),
+        }
+    }
+
+    #[test]
+    fn reads_every_format() {
+        for bytes in [ascii(), binary(false), binary(true)].iter() {
+            let (positions, normals, uvs, colors, faces) = parse(bytes).unwrap();
+            let positions = positions.iter().map(|p| (p.x as f32, p.y as f32, p.z as f32)).collect::<Vec<_>>();
+            assert_eq!(positions, CORNERS.to_vec());
+            let colors = colors.unwrap().iter().map(|c| (c.r, c.g, c.b)).collect::<Vec<_>>();
+            assert_eq!(colors, vec![(1.0, 0.0, 0.0), (0.0, 1.0, 0.0), (0.0, 0.0, 1.0), (1.0, 1.0, 1.0)]);
+            assert!(normals.is_none());
+            assert!(uvs.is_none());
+            assert_eq!(faces, vec![vec![0, 1, 2, 3]]);
+        }
+    }
+
+    #[test]
+    fn reads_normals_uvs_and_other_elements() {
+        let text = "ply\nformat ascii 1.0\nelement vertex 3\nproperty double x\nproperty double y\nproperty double z\n\
+            property float nx\nproperty float ny\nproperty float nz\nproperty float s\nproperty float t\n\
+            element edge 1\nproperty int a\nproperty int b\n\
+            element face 2\nproperty list uchar int vertex_index\nend_header\n\
+            0 0 0 0 0 1 0 0\n1 0 0 0 0 1 1 0\n0 1 0 0 0 1 0 1\n\
+            0 1\n\
+            3 0 1 2\n3 2 1 0\n";
+        let (_, normals, uvs, colors, faces) = parse(text.as_bytes()).unwrap();
+        let normals = normals.unwrap().iter().map(|n| (n.x, n.y, n.z)).collect::<Vec<_>>();
+        assert_eq!(normals, vec![(0.0, 0.0, 1.0); 3]);
+        assert_eq!(uvs, Some(vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]));
+        assert!(colors.is_none());
+        assert_eq!(faces, vec![vec![0, 1, 2], vec![2, 1, 0]]);
+    }
+
+    #[test]
+    fn rejects_truncated_bodies() {
+        for bytes in [ascii(), binary(false), binary(true)].iter() {
+            // the face's flags are the last thing in each body
+            let end = bytes.len() - if bytes[bytes.len() - 1] == b'\n' { 4 } else { 1 };
+            fails(&bytes[..end], "body ends early");
+        }
+        let mut text = header("ascii", "4").into_bytes();
+        text.extend(b"0 0 0 255 0 x 0\n");
+        fails(&text, "line 16: bad value 'x'");
+    }
+
+    #[test]
+    fn rejects_oversize_counts() {
+        // more vertices than the body holds, or than fit in a usize
+        let square = binary(false);
+        let body = header("binary_little_endian", "4").len();
+        fails(&[header("binary_little_endian", "4000000000").as_bytes(), &square[body..]].concat(), "body ends early");
+        fails(header("ascii", "99999999999999999999999").as_bytes(), "line 4: bad element count '99999999999999999999999'");
+
+        // a face list claiming four billion indices
+        let text = "ply\nformat binary_little_endian 1.0\nelement vertex 0\nproperty float x\nproperty float y\nproperty float z\n\
+            element face 1\nproperty list uint int vertex_indices\nend_header\n";
+        let mut bytes = text.as_bytes().to_vec();
+        bytes.extend(&4000000000u32.to_le_bytes());
+        bytes.extend(&0i32.to_le_bytes());
+        fails(&bytes, "body ends early");
+    }
+
+    #[test]
+    fn rejects_bad_faces() {
+        let square = String::from_utf8(ascii()).unwrap();
+        fails(square.replace("4 0 1 2 3 -3", "4 0 1 2 9 -3").as_bytes(), "face 0 uses vertex 9, there are 4");
+        fails(square.replace("4 0 1 2 3 -3", "2 0 1 -3").as_bytes(), "face 0 has 2 vertices, needs at least 3");
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let square = String::from_utf8(ascii()).unwrap();
+        fails(b"obj\nformat ascii 1.0\nend_header\n", "not a PLY file");
+        fails(b"ply\nformat ascii 1.0\nelement vertex 0\n", "header has no end_header");
+        fails(b"ply\nend_header\n", "header has no format");
+        fails(square.replace("format ascii", "format utf8").as_bytes(), "line 2: unknown format 'utf8'");
+        fails(square.replace("property uchar red", "property color red").as_bytes(), "line 8: unknown type 'color'");
+        fails(b"ply\nformat ascii 1.0\nproperty float x\nend_header\n", "line 3: property before any element");
+        fails(square.replace("property float z", "property float").as_bytes(), "line 7: malformed header line 'property float'");
+        fails(square.replace("property float z", "property float w").as_bytes(), "vertices need x, y and z");
+    }
+}
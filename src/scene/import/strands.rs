@@ -0,0 +1,45 @@
+// Plain text strand files, one strand per line:
+//
+//     root_width tip_width x y z x y z ...
+//
+// giving the strand's width at each end followed by the points it passes
+// through from root to tip, at least two. Everything after a # is a
+// comment and blank lines are skipped.
+
+use std::fs;
+use std::path::Path;
+
+use crate::scene::object::Strand;
+use crate::scene::primitives::Point;
+use crate::scene::load_err::LoadErr;
+
+pub fn load_strands<P: AsRef<Path>>(path: P) -> Result<Vec<Strand>, LoadErr> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)
+        .map_err(|err| LoadErr::from(format!("{}: {}", path.display(), err)))?;
+
+    let mut strands = Vec::new();
+    for (line_index, line) in text.lines().enumerate() {
+        let fail = |message: String| LoadErr::from(format!("{} line {}: {}", path.display(), line_index + 1, message));
+
+        let content = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        let values = content.split_whitespace().map(|token| {
+            token.parse::<f64>().map_err(|_| fail(format!("bad number '{}'", token)))
+        }).collect::<Result<Vec<f64>, LoadErr>>()?;
+        if values.is_empty() {
+            continue;
+        }
+
+        if values.len() < 2 || (values.len() - 2) % 3 != 0 {
+            return Err(fail(format!("expected two widths and x y z points, found {} numbers", values.len())));
+        }
+
+        let points = values[2..].chunks(3).map(|xyz| Point::from((xyz[0], xyz[1], xyz[2]))).collect();
+        strands.push(Strand::new(points, values[0], values[1]).map_err(|err| fail(format!("{}", err)))?);
+    }
+
+    Ok(strands)
+}
@@ -1,20 +1,29 @@
-use std::cmp::Ordering;
 use std::fmt::Debug;
 
 use crate::scene::{Scene, LightSource};
-use crate::scene::object::Intersect;
-use super::primitives::{Ray, Point, Color};
+use crate::scene::object::Hit;
+use crate::scene::medium::{Medium, MediumEvent};
+use crate::scene::sampler::Sampler;
+use crate::scene::bsdf::Bsdf;
+use crate::scene::render_buffer::{Features, LightGroups};
+use super::primitives::{Ray, Point, Color, Spectrum};
+
+// number of medium boundaries a ray may cross before it is given up on
+const MAX_BOUNDARIES: u32 = 16;
 
 pub struct LightRay {
     pub ray: Ray,
-    color: Color,
+    radiance: LightGroups,
+    // what the ray first hit, for the denoiser
+    pub features: Features,
 }
 
 impl LightRay {
     pub fn new(ray: Ray) -> LightRay {
         LightRay {
             ray: ray,
-            color: Color::from((0, 0, 0)),
+            radiance: LightGroups::new(0),
+            features: Features::background(),
         }
     }
 
@@ -26,105 +35,295 @@ impl LightRay {
         &self.ray.direction
     }
 
-    // Trace this camera ray through the scene and compute color at that point
-    pub fn trace(&mut self, scene: &Scene) -> Color {
+    // Trace this camera ray through the scene and compute radiance arriving
+    // along it, split by the light it came from, recording features of the
+    // first thing it hits on the way. The closest intersection is passed
+    // in, as camera rays find theirs together in packets.
+    pub fn trace(&mut self, first_intersection: Option<(usize, Hit)>, scene: &Scene, sampler: &mut Sampler) -> LightGroups {
         const NUM_RAYS: u32 = 1; // number of reflections
 
-        for _ray_index in 0..NUM_RAYS {
-            let intersection = self.find_closest_intersection(&scene.objects);
+        self.radiance = LightGroups::new(scene.lights.len());
+        let mut medium = scene.medium.as_deref();
+        let mut throughput = 1.0;
+        let mut ray_index = 0;
+        let mut boundaries = 0;
+        let mut first_intersection = Some(first_intersection);
+        while ray_index < NUM_RAYS && boundaries < MAX_BOUNDARIES {
+            let intersection = match first_intersection.take() {
+                Some(intersection) => intersection,
+                None => self.find_closest_intersection(scene),
+            };
+
+            // area lights are visible when nothing is in front of them
+            let t_surface = intersection.as_ref().map_or(std::f64::INFINITY, |(_, hit)| hit.t);
+            let light_hit = self.find_closest_light(&scene.lights)
+                .filter(|(_, t)| *t < t_surface);
+            let t_max = light_hit.as_ref().map_or(t_surface, |(_, t)| *t);
+
+            if ray_index == 0 && boundaries == 0 {
+                self.features = self.first_hit_features(&intersection, &light_hit, scene);
+            }
+
+            // the ray may scatter in the medium before reaching the surface
+            if let Some(medium) = medium {
+                match medium.sample_distance(&self.ray, t_max, sampler) {
+                    MediumEvent::Scatter { t, weight } => {
+                        let position = self.ray.position.add(&self.ray.direction.mult(t));
+                        let in_scattered = self.compute_in_scattering(&position, medium, scene, sampler);
+                        self.radiance.add_lights(&in_scattered, throughput * weight);
+                        break;
+                    },
+                    MediumEvent::Pass { weight } => throughput *= weight,
+                }
+            }
+
+            if let Some((light, _)) = light_hit {
+                let emitted = light.radiance() * throughput;
+                self.radiance.emission = self.radiance.emission.add(&Spectrum::new(emitted, emitted, emitted));
+                break;
+            }
 
             match intersection {
                 None => {
-                    self.color.mix(&Color::from((100, 100, 100)));
+                    let background = Spectrum::from(&Color::from((100, 100, 100)));
+                    self.radiance.environment = self.radiance.environment.add(&background.mult(throughput));
                     break;
                 },
-                Some((normal, color)) => {
+                Some((index, hit)) => {
+                    // volume boundaries only change which medium the ray is in,
+                    // objects without a surface material are passed straight through
+                    let material = match (scene.objects[index].medium(), hit.material) {
+                        (None, Some(material)) => material,
+                        (interior, _) => {
+                            if interior.is_some() {
+                                medium = if hit.front_face { interior } else { scene.medium.as_deref() };
+                            }
+                            self.ray = Ray::spawn(&hit.point, &hit.geometric_normal, self.ray.direction.clone());
+                            boundaries += 1;
+                            continue;
+                        },
+                    };
+
                     // modify starting point of ray to compute reflection
-                    let bounce_ray = Ray::new(normal.position.clone(), self.reflect(&normal));
-                    let shadow_scalar = self.compute_shadows(&normal, &scene.lights, &scene.objects);
-                    let color = color.scale(shadow_scalar);
+                    let bounce_ray = Ray::spawn(&hit.point, &hit.geometric_normal, self.reflect(&hit));
+                    let bsdf = scene.materials[material].at(&hit);
+                    let direct = self.compute_shadows(&hit, &bsdf, medium, scene, sampler);
 
                     self.ray = bounce_ray;
-                    self.color.mix(&color);
+                    self.radiance.add_lights(&direct, throughput);
+                    ray_index += 1;
                 },
             }
         }
 
-        self.color.clone()
+        self.radiance.clone()
     }
 
-    // computes ratio 1.0 to 0.0 of intensity of light
-    // TODO does intersections interior type get optimized away since
-    //   we only care about the count?
+    // Features of the first surface, volume boundary or light along the ray.
+    // Volumes are treated like a white surface so the noise inside them can
+    // be blended, no matter how deep into them each ray happened to scatter.
+    fn first_hit_features(&self,
+        intersection: &Option<(usize, Hit)>,
+        light_hit: &Option<(&LightSource, f64)>,
+        scene: &Scene) -> Features {
+
+        let white = Spectrum::new(1.0, 1.0, 1.0);
+        if let Some((light, t)) = light_hit {
+            let position = self.ray.position.add(&self.ray.direction.mult(*t));
+            return Features {
+                albedo: white,
+                normal: position.add(&light.position.mult(-1.0)).normalize(),
+                depth: *t,
+                position: position,
+                object: None,
+                material: None,
+            };
+        }
+
+        match intersection {
+            None => Features::background(),
+            Some((index, hit)) => {
+                let albedo = match (scene.objects[*index].medium(), hit.material) {
+                    (None, Some(material)) => scene.materials[material].at(hit).albedo(),
+                    _ => white,
+                };
+                Features {
+                    albedo: albedo,
+                    normal: hit.shading_normal.clone(),
+                    depth: hit.t,
+                    position: hit.point.clone(),
+                    object: Some(*index),
+                    material: hit.material,
+                }
+            },
+        }
+    }
+
+    // Computes light reflected towards the ray origin from each light source,
+    // in the order of Scene::lights, accounting for shadows. Area lights are
+    // sampled both by picking a point on the light and by following the bsdf,
+    // the two estimates are combined with multiple importance sampling so
+    // each covers where the other is noisy.
     fn compute_shadows(&self,
-        normal: &Ray,
-        lights: &Vec<LightSource>,
-        objects: &Vec<Box<dyn Intersect>>) -> f64 {
-
-        // compute intensities from each light source
-        let intensities = lights.iter().map(|light| {
-            // compute ray to light source
-            let ray_dir = light.position.add(&normal.position.mult(-1.0));
-            let ray_position = normal.position.add(&normal.direction.mult(1e-20));
-            let ray = LightRay::new(Ray::new(ray_position, ray_dir));
-
-            // count number of intersections
-            let intersections : Vec<_> = objects.iter().filter_map(|obj| {
-                obj.intersect(&ray)
-            }).collect();
-
-            if intersections.len() == 0 {
-                let cos_theta = normal.direction.dot(&ray.ray.direction) / ray.ray.direction.abs();
-                cos_theta
+        hit: &Hit,
+        bsdf: &dyn Bsdf,
+        medium: Option<&dyn Medium>,
+        scene: &Scene,
+        sampler: &mut Sampler) -> Vec<Spectrum> {
+
+        let frame = hit.frame();
+        let wo = frame.to_local(&self.ray.direction.mult(-1.0));
+
+        scene.lights.iter().map(|light| {
+            let mut contribution = Spectrum::black();
+
+            // sample a direction towards the light
+            if let Some(sample) = light.sample(&hit.point, sampler) {
+                let wi = frame.to_local(&sample.direction);
+                let reflected = bsdf.evaluate(&wo, &wi);
+                if !reflected.is_black() {
+                    let to = hit.point.add(&sample.direction.mult(sample.distance));
+                    let shadow_ray = Ray::spawn_to(&hit.point, &hit.geometric_normal, &to);
+                    let visibility = Self::transmittance(shadow_ray, medium, scene, sampler);
+
+                    // point lights can't be found by bsdf sampling, so get full weight
+                    let weight = if light.is_point() {
+                        1.0
+                    }
+                    else {
+                        power_heuristic(sample.pdf, bsdf.pdf(&wo, &wi))
+                    };
+
+                    let scale = wi.z.abs() * sample.radiance * visibility * weight / sample.pdf;
+                    contribution = contribution.add(&reflected.mult(scale));
+                }
             }
-            else {
-                0.0
+
+            // sample a direction from the bsdf and see if it finds the light
+            if !light.is_point() {
+                if let Some(sample) = bsdf.sample(&wo, sampler) {
+                    let direction = frame.to_world(&sample.wi);
+                    if let Some(distance) = light.intersect(&hit.point, &direction) {
+                        let to = hit.point.add(&direction.mult(distance));
+                        let shadow_ray = Ray::spawn_to(&hit.point, &hit.geometric_normal, &to);
+                        let visibility = Self::transmittance(shadow_ray, medium, scene, sampler);
+                        let weight = power_heuristic(sample.pdf, light.pdf(&hit.point, &direction));
+
+                        let scale = sample.wi.z.abs() * light.radiance() * visibility * weight / sample.pdf;
+                        contribution = contribution.add(&sample.value.mult(scale));
+                    }
+                }
             }
-        }).collect::<Vec<f64>>();
 
-        intensities.iter().fold(0.0, |value, &intensity| {
-            value + (intensity / intensities.len() as f64)
-        })
+            contribution
+        }).collect()
     }
 
-    // Returns normal to intersection and color picked up
-    fn find_closest_intersection(&self, objects: &Vec<Box<dyn Intersect>>) -> Option<(Ray, Color)> {
-        // remove Nones
-        // TODO examine
-        let intersected = objects.iter().filter_map(|obj| {
-            obj.intersect(&self)
-        });
-
-        // Intersects at Point, f64 distance away with object of intersection and ray color Color
-        let mut sorted = intersected.collect::<Vec<(Ray, f64, Color)>>();
-        sorted.sort_by(|tup, other| {
-            if tup.1 < other.1 {
-                Ordering::Less
-            }
-            else if tup.1 > other.1 {
-                Ordering::Greater
-            }
-            else {
-                Ordering::Equal
+    // light scattered towards the ray origin from a point inside a medium,
+    // from each light source
+    fn compute_in_scattering(&self,
+        position: &Point,
+        medium: &dyn Medium,
+        scene: &Scene,
+        sampler: &mut Sampler) -> Vec<Spectrum> {
+
+        let phase = medium.phase();
+        scene.lights.iter().map(|light| {
+            let sample = match light.sample(position, sampler) {
+                Some(sample) => sample,
+                None => return Spectrum::black(),
+            };
+
+            let cos_theta = self.ray.direction.dot(&sample.direction);
+            // not on a surface, so nothing to offset from
+            let shadow_ray = Ray::bounded(position.clone(), sample.direction.clone(), 0.0, sample.distance);
+            let visibility = Self::transmittance(shadow_ray, Some(medium), scene, sampler);
+            let intensity = phase.evaluate(cos_theta) * sample.radiance * visibility / sample.pdf;
+            Spectrum::new(intensity, intensity, intensity)
+        }).collect()
+    }
+
+    // Returns the closest area light the ray hits and the distance to it
+    fn find_closest_light<'a>(&self, lights: &'a Vec<LightSource>) -> Option<(&'a LightSource, f64)> {
+        lights.iter().fold(None, |closest, light| {
+            let hit = light.intersect(self.pos(), self.dir()).filter(|t| self.ray.contains(*t));
+            match (hit, closest) {
+                (Some(t), Some((_, closest_t))) if t >= closest_t => closest,
+                (Some(t), _) => Some((light, t)),
+                (None, _) => closest,
             }
-        });
+        })
+    }
+
+    // Fraction of light making it along a shadow ray up to its t_max.
+    // Opaque objects block it entirely, media along the way attenuate it.
+    fn transmittance(shadow_ray: Ray,
+        medium: Option<&dyn Medium>,
+        scene: &Scene,
+        sampler: &mut Sampler) -> f64 {
 
-        // Check if no intersection
-        if sorted.len() == 0 {
-            None
+        let target = shadow_ray.at(shadow_ray.t_max);
+        let mut ray = LightRay::new(shadow_ray);
+        if scene.occluded(&ray) {
+            return 0.0;
         }
-        else {
-            let closest = sorted[0].clone();
-            Some((closest.0, closest.2))
+
+        // nothing blocks it, so only boundaries of objects filled with a
+        // medium are left to step through
+        let mut medium = medium;
+        let mut transmittance = 1.0;
+        for _boundary in 0..MAX_BOUNDARIES {
+            let boundary = scene.media.iter()
+                .filter_map(|index| scene.objects[*index].intersect(&ray).map(|hit| (*index, hit)))
+                .fold(None, |closest: Option<(usize, Hit)>, (index, hit)| match closest {
+                    Some(closest) if closest.1.t <= hit.t => Some(closest),
+                    _ => Some((index, hit)),
+                });
+            let segment = boundary.as_ref().map_or(ray.ray.t_max, |(_, hit)| hit.t);
+
+            if let Some(medium) = medium {
+                transmittance *= medium.transmittance(&ray.ray, segment, sampler);
+            }
+
+            match boundary {
+                None => return transmittance,
+                Some((index, hit)) => {
+                    let interior = scene.objects[index].medium();
+                    medium = if hit.front_face { interior } else { scene.medium.as_deref() };
+                    ray.ray = Ray::spawn_to(&hit.point, &hit.geometric_normal, &target);
+                },
+            }
         }
+
+        0.0
+    }
+
+    // Returns index of the object hit and where it was hit
+    fn find_closest_intersection(&self, scene: &Scene) -> Option<(usize, Hit)> {
+        scene.bvh.closest(&self.ray, |index| {
+            scene.objects[index].intersect(self).map(|hit| (hit.t, (index, hit)))
+        })
     }
 
     // Reflect self ray across normal and normalize
-    fn reflect(&self, normal: &Ray) -> Point {
+    fn reflect(&self, hit: &Hit) -> Point {
         Point::from((0.0, 0.0, 0.0))
     }
 }
 
+// Weight for combining a sample from one strategy with another that
+// could also have produced it (Veach 1997)
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let pdf2 = pdf * pdf;
+    let other_pdf2 = other_pdf * other_pdf;
+    if pdf2 + other_pdf2 == 0.0 {
+        0.0
+    }
+    else {
+        pdf2 / (pdf2 + other_pdf2)
+    }
+}
+
 impl From<Ray> for LightRay {
     fn from(ray: Ray) -> Self {
         LightRay::new(ray)
@@ -1,15 +1,139 @@
-use crate::scene::primitives::Point;
+use std::f64::consts::PI;
 
+use crate::scene::primitives::{Point, Frame};
+use crate::scene::sampler::Sampler;
+
+// Light source, either a point or a sphere with area. Intensity is the
+// radiant intensity, so far enough away a sphere light of any radius
+// lights the scene like a point light of the same intensity.
 pub struct LightSource {
-// TODO implement intensity
-//    pub intensity: f64
+    pub intensity: f64,
     pub position: Point,
+    pub radius: f64,
+}
+
+// Direction towards a light picked by LightSource::sample
+pub struct LightSample {
+    pub direction: Point,
+    pub distance: f64,
+    // radiance arriving along direction, or irradiance for point lights
+    pub radiance: f64,
+    // solid angle density of direction, 1 for point lights
+    pub pdf: f64,
 }
 
 impl LightSource {
-    pub fn new(position: Point) -> Self {
+    // a radius of zero gives a point light
+    pub fn sphere(position: Point, radius: f64, intensity: f64) -> Self {
         LightSource {
+            intensity: intensity,
             position: position,
+            radius: radius,
+        }
+    }
+
+    // point lights can only be reached by sampling them directly
+    pub fn is_point(&self) -> bool {
+        self.radius <= 0.0
+    }
+
+    // radiance leaving the surface of a sphere light
+    pub fn radiance(&self) -> f64 {
+        if self.is_point() {
+            0.0
+        }
+        else {
+            self.intensity / (PI * self.radius * self.radius)
+        }
+    }
+
+    // Pick a direction towards the light as seen from a point. Sphere lights
+    // are sampled uniformly over the cone they subtend.
+    pub fn sample(&self, from: &Point, sampler: &mut Sampler) -> Option<LightSample> {
+        let offset = self.position.add(&from.mult(-1.0));
+        let distance2 = offset.dot(&offset);
+        if distance2 == 0.0 {
+            return None;
+        }
+
+        if self.is_point() {
+            return Some(LightSample {
+                direction: offset.normalize(),
+                distance: distance2.sqrt(),
+                radiance: self.intensity / distance2,
+                pdf: 1.0,
+            });
+        }
+
+        let (one_minus_cos, solid_angle) = self.cone(from)?;
+        let cos_theta = 1.0 - sampler.next_f64() * one_minus_cos;
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * sampler.next_f64();
+
+        let frame = Frame::from_normal(&offset.normalize());
+        let direction = frame.to_world(&Point::from((sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)));
+
+        // grazing samples can numerically miss, fall back to the tangent point
+        let distance = self.intersect(from, &direction)
+            .unwrap_or_else(|| (distance2 - self.radius * self.radius).max(0.0).sqrt());
+
+        Some(LightSample {
+            direction: direction,
+            distance: distance,
+            radiance: self.radiance(),
+            pdf: 1.0 / solid_angle,
+        })
+    }
+
+    // density sample() would pick direction with, from the given point
+    pub fn pdf(&self, from: &Point, direction: &Point) -> f64 {
+        if self.is_point() || self.intersect(from, direction).is_none() {
+            return 0.0;
+        }
+
+        match self.cone(from) {
+            Some((_, solid_angle)) => 1.0 / solid_angle,
+            None => 0.0,
+        }
+    }
+
+    // distance along a normalized direction to the front of a sphere light
+    pub fn intersect(&self, from: &Point, direction: &Point) -> Option<f64> {
+        if self.is_point() {
+            return None;
+        }
+
+        let offset = from.add(&self.position.mult(-1.0));
+        let b = direction.dot(&offset);
+        let c = offset.dot(&offset) - self.radius * self.radius;
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let root = discriminant.sqrt();
+        if -b - root > 0.0 {
+            Some(-b - root)
+        }
+        else if -b + root > 0.0 {
+            Some(-b + root)
+        }
+        else {
+            None
         }
     }
+
+    // one minus the cosine of the half angle, and the solid angle the
+    // sphere subtends, None from inside it
+    fn cone(&self, from: &Point) -> Option<(f64, f64)> {
+        let offset = self.position.add(&from.mult(-1.0));
+        let sin2_max = self.radius * self.radius / offset.dot(&offset);
+        if sin2_max >= 1.0 {
+            return None;
+        }
+
+        // written to keep precision for small distant lights
+        let one_minus_cos = sin2_max / (1.0 + (1.0 - sin2_max).sqrt());
+        Some((one_minus_cos, 2.0 * PI * one_minus_cos))
+    }
 }
@@ -0,0 +1,11 @@
+use std::fmt;
+use std::convert::From;
+use std::io::Error as IoError;
+
+// Errors from reading scene assets off disk. Malformed files are
+// reported as a String describing what and where.
+ErrorEnum!(
+    pub LoadErr,
+    (String,
+     IoError)
+);
@@ -0,0 +1,91 @@
+use crate::scene::bsdf::{Bsdf, BsdfSample, Principled};
+use crate::scene::primitives::{Point, Spectrum};
+use crate::scene::sampler::Sampler;
+use crate::scene::texture::ImageTexture;
+use crate::scene::object::Hit;
+
+// Entry of the scene's material table, a bsdf whose color is varied over
+// the surface by a texture and any color painted on it
+pub struct Material {
+    surface: Surface,
+}
+
+enum Surface {
+    Plain(Box<dyn Bsdf>),
+    // principled material with its base color multiplied by the texture,
+    // so highlights, clearcoat and glass keep their own color
+    Textured(Principled, ImageTexture),
+}
+
+impl Material {
+    pub fn new(bsdf: Box<dyn Bsdf>) -> Self {
+        Material {
+            surface: Surface::Plain(bsdf),
+        }
+    }
+
+    pub fn textured(principled: Principled, texture: ImageTexture) -> Self {
+        Material {
+            surface: Surface::Textured(principled, texture),
+        }
+    }
+
+    // the bsdf at a point on the surface
+    pub fn at(&self, hit: &Hit) -> Tinted {
+        let bsdf = match &self.surface {
+            Surface::Plain(bsdf) => Shading::Shared(bsdf.as_ref()),
+            Surface::Textured(principled, texture) => {
+                let mut principled = principled.clone();
+                principled.base_color = principled.base_color.modulate(&texture.lookup(hit.uv));
+                Shading::Textured(principled)
+            },
+        };
+
+        Tinted {
+            bsdf: bsdf,
+            tint: hit.color.clone().unwrap_or_else(|| Spectrum::new(1.0, 1.0, 1.0)),
+        }
+    }
+}
+
+// the material's own bsdf, or one made for this hit
+enum Shading<'a> {
+    Shared(&'a dyn Bsdf),
+    Textured(Principled),
+}
+
+// Bsdf with everything it reflects and transmits scaled by a color
+pub struct Tinted<'a> {
+    bsdf: Shading<'a>,
+    tint: Spectrum,
+}
+
+impl<'a> Tinted<'a> {
+    fn bsdf(&self) -> &dyn Bsdf {
+        match &self.bsdf {
+            Shading::Shared(bsdf) => *bsdf,
+            Shading::Textured(principled) => principled,
+        }
+    }
+}
+
+impl<'a> Bsdf for Tinted<'a> {
+    fn evaluate(&self, wo: &Point, wi: &Point) -> Spectrum {
+        self.bsdf().evaluate(wo, wi).modulate(&self.tint)
+    }
+
+    fn sample(&self, wo: &Point, sampler: &mut Sampler) -> Option<BsdfSample> {
+        self.bsdf().sample(wo, sampler).map(|sample| BsdfSample {
+            value: sample.value.modulate(&self.tint),
+            ..sample
+        })
+    }
+
+    fn pdf(&self, wo: &Point, wi: &Point) -> f64 {
+        self.bsdf().pdf(wo, wi)
+    }
+
+    fn albedo(&self) -> Spectrum {
+        self.bsdf().albedo().modulate(&self.tint)
+    }
+}
@@ -0,0 +1,96 @@
+use std::f64::consts::PI;
+
+use crate::scene::primitives::Ray;
+use crate::scene::sampler::Sampler;
+
+// Outcome of sampling a free flight distance through a medium
+pub enum MediumEvent {
+    // ray interacts with the medium at distance t, throughput scaled by weight
+    Scatter { t: f64, weight: f64 },
+    // ray made it through the whole segment
+    Pass { weight: f64 },
+}
+
+pub trait Medium {
+    // sample where along [0, t_max) the ray first scatters, if at all
+    fn sample_distance(&self, ray: &Ray, t_max: f64, sampler: &mut Sampler) -> MediumEvent;
+
+    // fraction of light surviving the segment [0, t_max)
+    fn transmittance(&self, ray: &Ray, t_max: f64, sampler: &mut Sampler) -> f64;
+
+    fn phase(&self) -> &HenyeyGreenstein;
+}
+
+pub struct HenyeyGreenstein {
+    // asymmetry, -1 back scattering, 0 isotropic, 1 forward scattering
+    pub g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> Self {
+        HenyeyGreenstein {
+            g: g.max(-0.99).min(0.99),
+        }
+    }
+
+    // cos_theta is between the direction the light travelled in
+    // and the direction it leaves in
+    pub fn evaluate(&self, cos_theta: f64) -> f64 {
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+}
+
+// Constant density medium, coefficients are per unit of scene distance
+pub struct HomogeneousMedium {
+    pub absorption: f64,
+    pub scattering: f64,
+    phase: HenyeyGreenstein,
+}
+
+impl HomogeneousMedium {
+    pub fn new(absorption: f64, scattering: f64, g: f64) -> Self {
+        HomogeneousMedium {
+            absorption: absorption,
+            scattering: scattering,
+            phase: HenyeyGreenstein::new(g),
+        }
+    }
+
+    fn extinction(&self) -> f64 {
+        self.absorption + self.scattering
+    }
+}
+
+impl Medium for HomogeneousMedium {
+    fn sample_distance(&self, _ray: &Ray, t_max: f64, sampler: &mut Sampler) -> MediumEvent {
+        let extinction = self.extinction();
+        if extinction <= 0.0 {
+            return MediumEvent::Pass { weight: 1.0 };
+        }
+
+        // sample proportional to transmittance, which cancels against the
+        // pdf and leaves just the single scattering albedo as the weight
+        let t = -(1.0 - sampler.next_f64()).ln() / extinction;
+        if t < t_max {
+            MediumEvent::Scatter { t: t, weight: self.scattering / extinction }
+        }
+        else {
+            MediumEvent::Pass { weight: 1.0 }
+        }
+    }
+
+    fn transmittance(&self, _ray: &Ray, t_max: f64, _sampler: &mut Sampler) -> f64 {
+        if t_max.is_infinite() {
+            if self.extinction() > 0.0 { 0.0 } else { 1.0 }
+        }
+        else {
+            (-self.extinction() * t_max).exp()
+        }
+    }
+
+    fn phase(&self) -> &HenyeyGreenstein {
+        &self.phase
+    }
+}
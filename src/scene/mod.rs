@@ -1,12 +1,25 @@
 mod object;
 pub mod primitives;
+mod bsdf;
 //mod render_mesh;
 mod light_ray;
 mod light_source;
+mod load_err;
+mod medium;
+mod sampler;
+mod texture;
+mod material;
+mod import;
+mod render_buffer;
+mod denoise;
+mod packet;
 
 use std::convert::From;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::f64::consts::PI;
 use std::cell::RefCell;
+use std::io;
 
 extern crate sdl2;
 use sdl2::render::{Canvas};
@@ -15,48 +28,446 @@ use sdl2::pixels::Color as SdlColor;
 use sdl2::rect::Point as SdlPoint;
 
 use crate::camera::Camera;
-use object::{Intersect, Sphere};
-use primitives::{Point, Color};
+use object::{Intersect, Hit, Sphere, Plane, Disk, Cuboid, Cylinder, Cone, Torus, Csg, Instance, Bvh, SubdivisionSurface, ControlMesh, Sdf, SphereField, BoxField, TorusField, CapsuleField, Mandelbulb, Translate, SmoothUnion, Repeat, Twist, Displace, TriangleMesh, GridVolume, DensityGrid, Heightfield, ElevationGrid, Curves, CurveKind, Strand};
+use primitives::{Point, Color, Spectrum, Transform, Bounds};
+use bsdf::{Lambertian, OrenNayar, Conductor, Dielectric, Principled, Hair};
 use light_ray::LightRay;
+use medium::{Medium, HomogeneousMedium};
+use sampler::Sampler;
+use packet::{RayPacket, PACKET_SIZE};
+use material::Material;
+use load_err::LoadErr;
+use render_buffer::{RenderBuffer, LightGroups, Aov, AOVS, write_ppm, write_pfm};
 pub use light_source::LightSource;
 
 pub struct Scene {
     pub lights: Vec<LightSource>,
     pub objects: Vec<Box<dyn Intersect>>,
+    // over objects, by index, brought up to date before every frame so
+    // objects can be moved, added or replaced between them
+    bvh: Bvh,
+    // objects filled with a medium, by index, which shadow rays go through
+    // rather than being blocked by
+    media: Vec<usize>,
+    // surfaces refer to these by MaterialId
+    pub materials: Vec<Material>,
     pub camera: Rc<RefCell<Camera>>,
+    // medium filling the space between objects, None for vacuum
+    pub medium: Option<Box<dyn Medium>>,
+    // filter the noise out of each frame before showing it
+    pub denoise: bool,
+    // channel shown in the window
+    pub aov: Aov,
+    // keep radiance per light, so saved images can be relit
+    pub light_groups: bool,
+    frame: u64,
+    // everything traced for the last frame
+    buffer: RenderBuffer,
 }
 
 impl Scene {
     pub fn new(camera: Rc<RefCell<Camera>>) -> Scene {
         let mut lights = Vec::<LightSource>::new();
         let mut objects = Vec::<Box<dyn Intersect>>::new();
+        let mut materials = Vec::<Material>::new();
 
-        lights.push(LightSource::new(Point::from((10.0, -5.0, 3.5))));
+        lights.push(LightSource::sphere(Point::from((10.0, -5.0, 3.5)), 0.3, 120.0));
+
+        let yellow = materials.len();
+        materials.push(Material::new(Box::new(Lambertian::new(Spectrum::from(&Color::from((255, 255, 100)))))));
+        let blue = materials.len();
+        materials.push(Material::new(Box::new(OrenNayar::new(Spectrum::from(&Color::from((100, 100, 255))), 20.0))));
+        let gold = materials.len();
+        materials.push(Material::new(Box::new(Conductor::gold(0.3))));
+        let copper = materials.len();
+        materials.push(Material::new(Box::new(Conductor::copper(0.4))));
+        let silver = materials.len();
+        materials.push(Material::new(Box::new(Conductor::silver(0.15))));
+        let aluminium = materials.len();
+        materials.push(Material::new(Box::new(Conductor::aluminium(0.5))));
+        let glass = materials.len();
+        materials.push(Material::new(Box::new(Dielectric::glass(0.05))));
+        let mut lacquer = Principled::new(Spectrum::from(&Color::from((200, 40, 30))));
+        lacquer.roughness = 0.6;
+        lacquer.clearcoat = 1.0;
+        lacquer.clearcoat_gloss = 0.9;
+        let red = materials.len();
+        materials.push(Material::new(Box::new(lacquer)));
+        let floor = materials.len();
+        materials.push(Material::new(Box::new(Lambertian::new(Spectrum::from(&Color::from((180, 180, 170)))))));
+        let rug = materials.len();
+        materials.push(Material::new(Box::new(OrenNayar::new(Spectrum::from(&Color::from((60, 120, 80))), 30.0))));
+        let mut glaze = Principled::new(Spectrum::from(&Color::from((60, 170, 190))));
+        glaze.roughness = 0.35;
+        let teal = materials.len();
+        materials.push(Material::new(Box::new(glaze)));
+        // white so the vertex colors of painted meshes come through as is
+        let mut porcelain = Principled::new(Spectrum::new(0.9, 0.9, 0.9));
+        porcelain.roughness = 0.25;
+        let painted = materials.len();
+        materials.push(Material::new(Box::new(porcelain)));
+        let auburn = materials.len();
+        materials.push(Material::new(Box::new(Hair::new(Spectrum::from(&Color::from((150, 70, 35))), 0.4, 60.0))));
+        let grass = materials.len();
+        materials.push(Material::new(Box::new(Hair::new(Spectrum::from(&Color::from((70, 140, 40))), 0.1, 10.0))));
+
+        objects.push(Box::new(Plane::new(
+            Point::from((0.0, 0.0, -2.0)),
+            Point::from((0.0, 0.0, 1.0)),
+            floor)));
+        objects.push(Box::new(Disk::new(
+            Point::from((10.0, 0.0, -1.999)),
+            Point::from((0.0, 0.0, 1.0)),
+            2.5,
+            rug)));
 
         objects.push(Box::new(Sphere::new(
             Point::from((10.0, 0.0, -1.0)),
-            Color::from((255, 255, 100)),
+            yellow,
             1.0)));
         objects.push(Box::new(Sphere::new(
             Point::from((9.5, 0.0, 1.0)),
-            Color::from((100, 100, 255)),
+            blue,
             0.25)));
+        objects.push(Box::new(Sphere::new(
+            Point::from((9.0, 1.8, -0.8)),
+            gold,
+            0.5)));
+        objects.push(Box::new(Sphere::new(
+            Point::from((9.0, -1.6, -0.9)),
+            red,
+            0.5)));
+        objects.push(Box::new(Sphere::with_medium(
+            Point::from((12.0, -2.5, 0.5)),
+            1.0,
+            Box::new(HomogeneousMedium::new(0.1, 1.5, 0.2)))));
+
+        // octahedron with vertex normals pointing out from its center, so it
+        // shades smoothly like a rounded gem. One mesh shared by a few
+        // instances placed around the floor.
+        let directions = vec![
+            Point::from((1.0, 0.0, 0.0)), Point::from((-1.0, 0.0, 0.0)),
+            Point::from((0.0, 1.0, 0.0)), Point::from((0.0, -1.0, 0.0)),
+            Point::from((0.0, 0.0, 1.0)), Point::from((0.0, 0.0, -1.0))];
+        let octahedron = TriangleMesh::new(
+            directions.clone(),
+            Some(directions),
+            None,
+            None,
+            vec![[0, 2, 4], [2, 1, 4], [1, 3, 4], [3, 0, 4],
+                 [2, 0, 5], [1, 2, 5], [3, 1, 5], [0, 3, 5]],
+            teal);
+        match octahedron {
+            Ok(octahedron) => {
+                let octahedron: Arc<dyn Intersect> = Arc::new(octahedron);
+                objects.push(Box::new(Instance::new(
+                    octahedron.clone(),
+                    Transform::scaling(0.6, 0.6, 0.6)
+                        .then(&Transform::translation(&Point::from((9.0, -3.0, -1.4)))))));
+                objects.push(Box::new(Instance::new(
+                    octahedron.clone(),
+                    Transform::scaling(0.25, 0.25, 0.25)
+                        .then(&Transform::rotation(&Point::from((0.0, 0.0, 1.0)), PI / 4.0))
+                        .then(&Transform::translation(&Point::from((6.0, -1.9, -1.75)))))));
+                objects.push(Box::new(Instance::new(
+                    octahedron,
+                    Transform::scaling(0.2, 0.2, 0.5)
+                        .then(&Transform::rotation(&Point::from((1.0, 0.0, 0.0)), 0.4))
+                        .then(&Transform::translation(&Point::from((6.3, 1.4, -1.55)))))));
+            },
+            Err(e) => eprintln!("Skipping octahedron gems: {}", e),
+        }
+
+        // Subdivision surfaces floating over the rug: a cube rounded off by
+        // Catmull-Clark with its top rim kept sharp, and a glass octahedron
+        // smoothed by Loop with a softened crease around its middle
+        let corners = (0..8).map(|corner| Point::from((
+            if corner & 1 == 0 { -0.35 } else { 0.35 },
+            if corner & 2 == 0 { -0.35 } else { 0.35 },
+            if corner & 4 == 0 { -0.3 } else { 0.3 })).add(&Point::from((11.0, 1.5, 1.1)))).collect();
+        let cube = ControlMesh::new(
+            corners,
+            vec![vec![0, 2, 3, 1], vec![4, 5, 7, 6], vec![0, 1, 5, 4], vec![2, 6, 7, 3], vec![0, 4, 6, 2], vec![1, 3, 7, 5]],
+            vec![([4, 5], std::f64::INFINITY), ([5, 7], std::f64::INFINITY), ([7, 6], std::f64::INFINITY), ([6, 4], std::f64::INFINITY)]);
+        match cube.and_then(|cube| SubdivisionSurface::new(&cube, 4, yellow)) {
+            Ok(surface) => objects.push(Box::new(surface)),
+            Err(e) => eprintln!("Skipping subdivided cube: {}", e),
+        }
+
+        let gem = Point::from((10.5, 0.95, 0.35));
+        let octahedron = ControlMesh::new(
+            [(0.4, 0.0, 0.0), (-0.4, 0.0, 0.0), (0.0, 0.4, 0.0), (0.0, -0.4, 0.0), (0.0, 0.0, 0.3), (0.0, 0.0, -0.3)].iter()
+                .map(|offset| Point::from(*offset).add(&gem))
+                .collect(),
+            vec![vec![0, 2, 4], vec![2, 1, 4], vec![1, 3, 4], vec![3, 0, 4],
+                 vec![2, 0, 5], vec![1, 2, 5], vec![3, 1, 5], vec![0, 3, 5]],
+            vec![([0, 2], 2.0), ([2, 1], 2.0), ([1, 3], 2.0), ([3, 0], 2.0)]);
+        match octahedron.and_then(|octahedron| SubdivisionSurface::new(&octahedron, 4, glass)) {
+            Ok(surface) => objects.push(Box::new(surface)),
+            Err(e) => eprintln!("Skipping subdivided octahedron: {}", e),
+        }
+
+        // silver ring from a model, its quads kept whole for Catmull-Clark
+        match ControlMesh::load("assets/ring.obj").and_then(|ring| SubdivisionSurface::new(&ring, 3, silver)) {
+            Ok(surface) => objects.push(Box::new(surface)),
+            Err(e) => eprintln!("Skipping subdivided ring: {}", e),
+        }
+
+        // an aluminium post on a pedestal and a copper spire at the back,
+        // and a ring on the floor
+        objects.push(Box::new(Cuboid::new(
+            Point::from((11.4, -3.9, -2.0)),
+            Point::from((12.2, -3.1, -1.5)),
+            floor)));
+        objects.push(Box::new(Cylinder::new(
+            Point::from((11.8, -3.5, -1.5)),
+            Point::from((0.0, 0.0, 1.0)),
+            0.25,
+            0.5,
+            aluminium)));
+        objects.push(Box::new(Cone::new(
+            Point::from((12.2, -1.6, -2.0)),
+            Point::from((0.0, 0.0, 1.0)),
+            0.4,
+            1.2,
+            copper)));
+        match Torus::new(Point::from((7.3, -2.3, -1.85)), Point::from((0.0, 0.0, 1.0)), 0.4, 0.15, gold) {
+            Ok(torus) => objects.push(Box::new(torus)),
+            Err(e) => eprintln!("Skipping ring: {}", e),
+        }
 
+        // rounded die, a cube cut down by a sphere with two crossing holes
+        // drilled through it
+        let die = Point::from((6.9, 0.3, -1.65));
+        objects.push(Box::new(Csg::difference(
+            Box::new(Csg::intersection(
+                Box::new(Cuboid::new(die.add(&Point::from((-0.35, -0.35, -0.35))), die.add(&Point::from((0.35, 0.35, 0.35))), yellow)),
+                Box::new(Sphere::new(die.clone(), yellow, 0.47)))),
+            Box::new(Csg::union(
+                Box::new(Cylinder::new(die.add(&Point::from((-0.5, 0.0, 0.0))), Point::from((1.0, 0.0, 0.0)), 0.18, 1.0, red)),
+                Box::new(Cylinder::new(die.add(&Point::from((0.0, -0.5, 0.0))), Point::from((0.0, 1.0, 0.0)), 0.12, 1.0, red)))))));
+
+        // twisted bar melting into a rippled ball with a stubby arm, traced
+        // as distance fields
+        let blob = Point::from((7.6, 2.3, -1.45));
+        objects.push(Box::new(Sdf::new(
+            Box::new(Translate::new(
+                Box::new(SmoothUnion::new(
+                    Box::new(Twist::new(Box::new(BoxField::new(Point::from((0.12, 0.12, 0.55)), 0.03)), 3.0)),
+                    Box::new(SmoothUnion::new(
+                        Box::new(Translate::new(
+                            Box::new(Displace::new(Box::new(SphereField::new(0.3)), 0.02, 3.0)),
+                            Point::from((0.0, 0.0, -0.25)))),
+                        Box::new(CapsuleField::new(Point::from((0.0, 0.0, -0.25)), Point::from((0.0, 0.4, -0.45)), 0.07)),
+                        0.1)),
+                    0.15)),
+                blob.clone())),
+            Bounds::new(blob.add(&Point::from((-0.4, -0.4, -0.6))), blob.add(&Point::from((0.4, 0.55, 0.6)))),
+            teal)));
+
+        // row of rings lying on the floor, one field repeated along y
+        objects.push(Box::new(Sdf::new(
+            Box::new(Translate::new(
+                Box::new(Repeat::new(Box::new(TorusField::new(0.1, 0.035)), Point::from((0.0, 0.3, 0.0)))),
+                Point::from((6.1, 0.0, -1.965)))),
+            Bounds::new(Point::from((5.95, -0.45, -2.0)), Point::from((6.25, 0.75, -1.93))),
+            gold)));
+
+        let mandelbulb: Arc<dyn Intersect> = Arc::new(Sdf::new(
+            Box::new(Mandelbulb::new(8.0, 8)),
+            Bounds::new(Point::from((-1.25, -1.25, -1.25)), Point::from((1.25, 1.25, 1.25))),
+            painted));
+        objects.push(Box::new(Instance::new(mandelbulb,
+            Transform::scaling(0.7, 0.7, 0.7)
+                .then(&Transform::translation(&Point::from((10.5, -1.3, 2.0)))))));
+
+        match import::load_obj("assets/crate.obj", &mut materials) {
+            Ok(meshes) => for mesh in meshes {
+                objects.push(Box::new(mesh));
+            },
+            Err(e) => eprintln!("Skipping crate model: {}", e),
+        }
+        match import::load_ply("assets/gem.ply", painted) {
+            Ok(mesh) => objects.push(Box::new(mesh)),
+            Err(e) => eprintln!("Skipping gem model: {}", e),
+        }
+
+        // tuft of grass blades bending away from the camera
+        let mut sampler = Sampler::new(11);
+        let blades = (0..400).map(|_| {
+            let root = Point::from((
+                7.0 + 1.2 * sampler.next_f64(),
+                -2.2 + 1.0 * sampler.next_f64(),
+                -2.0));
+            let height = 0.25 + 0.3 * sampler.next_f64();
+            let lean = Point::from((0.5 * sampler.next_f64(), 0.3 * (sampler.next_f64() - 0.5), 0.0)).mult(height);
+            let points = (0..4).map(|i| {
+                let s = i as f64 / 3.0;
+                root.add(&lean.mult(s * s)).add(&Point::from((0.0, 0.0, height * s)))
+            }).collect();
+            Strand::new(points, 0.02, 0.002)
+        }).collect::<Result<Vec<Strand>, LoadErr>>();
+        match blades {
+            Ok(blades) => objects.push(Box::new(Curves::new(blades, CurveKind::Ribbon, grass))),
+            Err(e) => eprintln!("Skipping grass: {}", e),
+        }
+        match import::load_strands("assets/tassel.strands") {
+            Ok(strands) => objects.push(Box::new(Curves::new(strands, CurveKind::Cylinder, auburn))),
+            Err(e) => eprintln!("Skipping hair strands: {}", e),
+        }
+
+        // hills along the horizon, falling back to generated ones
+        let hills = ElevationGrid::load("assets/hills.pgm").or_else(|e| {
+            eprintln!("Generating hills instead: {}", e);
+            ElevationGrid::fractal(96, 128, 6, 7)
+        });
+        if let Ok(hills) = hills {
+            objects.push(Box::new(Heightfield::new(
+                hills,
+                Point::from((16.0, -14.0, -2.0)),
+                Point::from((30.0, 14.0, 2.5)),
+                rug)));
+        }
+
+        match DensityGrid::load("assets/cloud.density") {
+            Ok(grid) => objects.push(Box::new(GridVolume::new(
+                Point::from((10.0, 2.0, 0.0)),
+                Point::from((12.0, 4.0, 2.0)),
+                grid,
+                4.0,
+                0.9,
+                0.3))),
+            Err(e) => eprintln!("Skipping cloud volume: {}", e),
+        }
+
+        let bounds = objects.iter().map(|object| object.bounds()).collect::<Vec<Bounds>>();
         Scene {
             lights: lights,
+            bvh: Bvh::new(&bounds),
+            media: (0..objects.len()).filter(|index| objects[*index].medium().is_some()).collect(),
             objects: objects,
+            materials: materials,
             camera: camera,
+            medium: None,
+            denoise: false,
+            aov: Aov::Beauty,
+            light_groups: false,
+            frame: 0,
+            buffer: RenderBuffer::new(0, 0),
+        }
+    }
+
+    // Whether anything opaque lies along the ray inside its interval,
+    // stopping at the first thing found. Objects filled with a medium don't
+    // block it, LightRay::transmittance accounts for those.
+    pub fn occluded(&self, ray: &LightRay) -> bool {
+        self.bvh.any(&ray.ray, |index| {
+            let object = &self.objects[index];
+            object.medium().is_none() && object.occludes(ray)
+        })
+    }
+
+    // Closest object each ray hits and where, for up to PACKET_SIZE rays
+    // that start out close together and run in similar directions, such as
+    // camera rays through neighbouring pixels
+    fn closest_packet(&self, rays: &[LightRay]) -> [Option<(usize, Hit)>; PACKET_SIZE] {
+        let packet = RayPacket::new(rays);
+        self.bvh.closest_packet(&packet, packet.active, |index, lanes| {
+            self.objects[index].intersect_packet(&packet, rays, lanes)
+                .map(|hit| hit.map(|hit| (hit.t, (index, hit))))
+        })
+    }
+
+    pub fn toggle_fog(&mut self) {
+        self.medium = match self.medium {
+            Some(_) => None,
+            None => Some(Box::new(HomogeneousMedium::new(0.005, 0.025, 0.6))),
+        };
+    }
+
+    pub fn toggle_denoise(&mut self) {
+        self.denoise = !self.denoise;
+    }
+
+    pub fn cycle_aov(&mut self) {
+        self.aov = self.aov.next();
+    }
+
+    pub fn toggle_light_groups(&mut self) {
+        self.light_groups = !self.light_groups;
+    }
+
+    // Write every channel of the last rendered frame to PPM files, the
+    // beauty image to <stem>.ppm and the others to <stem>_<channel>.ppm.
+    // With light groups on, each group is also written as a PFM file,
+    // <stem>_light<index>.pfm, <stem>_environment.pfm and <stem>_emission.pfm,
+    // which sum to the beauty image before denoising.
+    pub fn save_images(&self, stem: &str) -> io::Result<()> {
+        let (width, height) = (self.buffer.width, self.buffer.height);
+        for aov in AOVS.iter() {
+            let path = match aov {
+                Aov::Beauty => format!("{}.ppm", stem),
+                _ => format!("{}_{}.ppm", stem, aov.name()),
+            };
+            write_ppm(&path, width, height, &self.buffer.aov_colors(*aov))?;
         }
+
+        if !self.buffer.groups.is_empty() {
+            let mut light = 0;
+            while let Some(radiance) = self.buffer.light_group(light) {
+                write_pfm(format!("{}_light{}.pfm", stem, light), width, height, &radiance)?;
+                light += 1;
+            }
+            write_pfm(format!("{}_environment.pfm", stem), width, height, &self.buffer.environment_group())?;
+            write_pfm(format!("{}_emission.pfm", stem), width, height, &self.buffer.emission_group())?;
+        }
+        Ok(())
     }
 
     // TODO don't copy around the x and y's
-    pub fn render(&self, canvas: &mut Canvas<Window>) {
+    pub fn render(&mut self, canvas: &mut Canvas<Window>) {
+        self.frame += 1;
+        let bounds = self.objects.iter().map(|object| object.bounds()).collect::<Vec<Bounds>>();
+        self.bvh.update(&bounds);
+        self.media = (0..self.objects.len()).filter(|index| self.objects[*index].medium().is_some()).collect();
+
         let camera = (*self.camera).borrow();
-        let pixel_colors = camera.pixels_iter().map(|(x, y)| {
-            let mut ray = LightRay::from(camera.get_ray(x, y));
-            let color = ray.trace(&self);
-            color
-        }).collect::<Vec<Color>>();
+        let mut buffer = RenderBuffer::new(camera.width, camera.height);
+        if self.light_groups {
+            buffer.groups = vec![LightGroups::new(self.lights.len()); buffer.color.len()];
+        }
+        // camera rays go out in 2x2 tiles of pixels, whose first hits are
+        // found as one packet
+        for tile_y in (0..camera.height).step_by(2) {
+            for tile_x in (0..camera.width).step_by(2) {
+                let pixels = [(tile_x, tile_y), (tile_x + 1, tile_y), (tile_x, tile_y + 1), (tile_x + 1, tile_y + 1)]
+                    .iter()
+                    .filter(|(x, y)| *x < camera.width && *y < camera.height)
+                    .cloned()
+                    .collect::<Vec<(u32, u32)>>();
+                let rays = pixels.iter().map(|(x, y)| LightRay::from(camera.get_ray(*x, *y))).collect::<Vec<LightRay>>();
+                let first_hits = self.closest_packet(&rays);
+
+                for (((x, y), mut ray), first_hit) in pixels.iter().zip(rays).zip(first_hits) {
+                    // reseed per pixel and frame so noise doesn't stay fixed on screen
+                    let pixel_index = (*y as u64) * (camera.width as u64) + (*x as u64);
+                    let mut sampler = Sampler::new((self.frame << 32) ^ pixel_index);
+
+                    let index = buffer.index(*x, *y);
+                    let radiance = ray.trace(first_hit, &self, &mut sampler);
+                    buffer.color[index] = radiance.total();
+                    buffer.features[index] = ray.features;
+                    if self.light_groups {
+                        buffer.groups[index] = radiance;
+                    }
+                }
+            }
+        }
+
+        if self.denoise {
+            buffer.color = denoise::denoise(&buffer);
+        }
+        let pixel_colors = buffer.aov_colors(self.aov);
 
         for (count, color) in pixel_colors.iter().enumerate() {
             // println!("{:?}", &color);
@@ -65,5 +476,8 @@ impl Scene {
                 (count as i32) % (camera.width as i32),
                 (count as i32) / (camera.width as i32))).unwrap();
         }
+
+        drop(camera);
+        self.buffer = buffer;
     }
 }
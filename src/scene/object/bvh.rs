@@ -0,0 +1,572 @@
+// Bounding volume hierarchy over anything that can be boxed, the objects
+// of a scene or the triangles of a mesh. It only holds indices, callers
+// test the primitives themselves.
+//
+// Built top down with the surface area heuristic, evaluated over a fixed
+// number of bins along each axis rather than every possible split.
+// Primitives with infinite bounds, like planes, can't be placed in it and
+// are kept aside to be tested by every ray.
+//
+// When primitives move the tree is refit, keeping its structure and only
+// growing or shrinking the boxes. That gets slower to traverse the further
+// things move from where they were at the build, so the tree is rebuilt
+// once its cost has grown by REBUILD_RATIO.
+
+use crate::scene::primitives::{Point, Ray, Bounds};
+use crate::scene::packet::{RayPacket, Mask, F64x4, PACKET_SIZE};
+
+const BINS: usize = 12;
+// cost of visiting a node relative to testing a primitive
+const TRAVERSAL_COST: f64 = 0.5;
+// leaves are split while they have more primitives than this, even when
+// the heuristic says a split doesn't pay off
+const MAX_LEAF_SIZE: usize = 8;
+const REBUILD_RATIO: f64 = 1.5;
+
+struct Node {
+    bounds: Bounds,
+    // for leaves the range of primitives, for interior nodes the index of
+    // the second child, the first one follows the node directly
+    start: usize,
+    count: usize,
+    // axis the children were split along, interior nodes only
+    axis: usize,
+}
+
+pub struct Bvh {
+    nodes: Vec<Node>,
+    primitives: Vec<usize>,
+    unbounded: Vec<usize>,
+    // surface area heuristic cost of the tree as built
+    built_cost: f64,
+}
+
+fn component(point: &Point, axis: usize) -> f64 {
+    match axis {
+        0 => point.x,
+        1 => point.y,
+        _ => point.z,
+    }
+}
+
+impl Bvh {
+    // hierarchy over the primitives with these bounds, by index
+    pub fn new(bounds: &[Bounds]) -> Bvh {
+        let (bounded, unbounded): (Vec<usize>, Vec<usize>) = (0..bounds.len())
+            .partition(|index| bounds[*index].is_finite());
+        let centroids = bounds.iter().map(|bounds| bounds.centroid()).collect::<Vec<Point>>();
+
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            primitives: bounded,
+            unbounded: unbounded,
+            built_cost: 0.0,
+        };
+        if !bvh.primitives.is_empty() {
+            bvh.build(bounds, &centroids, 0, bvh.primitives.len());
+        }
+        bvh.built_cost = bvh.cost();
+        bvh
+    }
+
+    // Bring the tree up to date with new bounds for the primitives, by
+    // index as given to new. Refits, or rebuilds when the refit tree has
+    // degraded too far or primitives were added, removed, or gained or
+    // lost their bounds. Returns whether it was rebuilt.
+    pub fn update(&mut self, bounds: &[Bounds]) -> bool {
+        let same_primitives = bounds.len() == self.primitives.len() + self.unbounded.len()
+            && self.primitives.iter().all(|index| bounds[*index].is_finite())
+            && self.unbounded.iter().all(|index| !bounds[*index].is_finite());
+
+        if same_primitives {
+            self.refit(bounds);
+            if !(self.cost() > self.built_cost * REBUILD_RATIO) {
+                return false;
+            }
+        }
+
+        *self = Bvh::new(bounds);
+        true
+    }
+
+    // Recompute every box bottom up, children always come after their parent
+    fn refit(&mut self, bounds: &[Bounds]) {
+        for index in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[index];
+            let refit = if node.count > 0 {
+                self.primitives[node.start..node.start + node.count].iter()
+                    .fold(Bounds::empty(), |node, primitive| node.union(&bounds[*primitive]))
+            }
+            else {
+                self.nodes[index + 1].bounds.union(&self.nodes[node.start].bounds)
+            };
+            self.nodes[index].bounds = refit;
+        }
+    }
+
+    // Expected cost of a ray through the tree by the surface area
+    // heuristic, the chance of visiting each node being its area over the
+    // root's
+    fn cost(&self) -> f64 {
+        let root_area = match self.nodes.first() {
+            Some(root) => root.bounds.surface_area(),
+            None => return 0.0,
+        };
+        if !(root_area > 0.0) {
+            return 0.0;
+        }
+
+        self.nodes.iter().map(|node| {
+            let work = if node.count > 0 { node.count as f64 } else { TRAVERSAL_COST };
+            work * node.bounds.surface_area() / root_area
+        }).sum()
+    }
+
+    // box around everything, infinite when there are unbounded primitives
+    pub fn bounds(&self) -> Bounds {
+        if !self.unbounded.is_empty() {
+            Bounds::infinite()
+        }
+        else {
+            self.nodes.first().map_or(Bounds::empty(), |root| root.bounds.clone())
+        }
+    }
+
+    // Builds the node for primitives[start..end] and everything below it,
+    // returning its index
+    fn build(&mut self, bounds: &[Bounds], centroids: &[Point], start: usize, end: usize) -> usize {
+        let node_bounds = self.primitives[start..end].iter()
+            .fold(Bounds::empty(), |node, index| node.union(&bounds[*index]));
+        let centroid_bounds = Bounds::from_points(self.primitives[start..end].iter().map(|index| &centroids[*index]));
+        let count = end - start;
+
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            bounds: node_bounds,
+            start: start,
+            count: count,
+            axis: 0,
+        });
+        if count == 1 {
+            return index;
+        }
+
+        // cheapest split over the bin boundaries of every axis
+        let area = self.nodes[index].bounds.surface_area();
+        let mut best: Option<(f64, usize, usize)> = None;
+        for axis in 0..3 {
+            let low = component(&centroid_bounds.min, axis);
+            let extent = component(&centroid_bounds.max, axis) - low;
+            if !(extent > 0.0) {
+                continue;
+            }
+            let bin = |index: usize| (((component(&centroids[index], axis) - low) / extent * BINS as f64) as usize).min(BINS - 1);
+
+            let mut bins = vec![(Bounds::empty(), 0usize); BINS];
+            for primitive in self.primitives[start..end].iter() {
+                let (bin_bounds, bin_count) = &mut bins[bin(*primitive)];
+                *bin_bounds = bin_bounds.union(&bounds[*primitive]);
+                *bin_count += 1;
+            }
+
+            // areas and counts to the right of each boundary, then sweep
+            // from the left
+            let mut right = vec![(0.0, 0usize); BINS];
+            let mut accumulated = (Bounds::empty(), 0);
+            for split in (1..BINS).rev() {
+                accumulated = (accumulated.0.union(&bins[split].0), accumulated.1 + bins[split].1);
+                right[split] = (accumulated.0.surface_area(), accumulated.1);
+            }
+            let mut left = (Bounds::empty(), 0);
+            for split in 1..BINS {
+                left = (left.0.union(&bins[split - 1].0), left.1 + bins[split - 1].1);
+                if left.1 == 0 || right[split].1 == 0 {
+                    continue;
+                }
+                let cost = TRAVERSAL_COST
+                    + (left.0.surface_area() * left.1 as f64 + right[split].0 * right[split].1 as f64) / area;
+                if best.map_or(true, |(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, split));
+                }
+            }
+        }
+
+        let (axis, split) = match best {
+            Some((cost, axis, split)) if cost < count as f64 || count > MAX_LEAF_SIZE => (axis, split),
+            // all centroids in one place, or splitting costs more than it saves
+            _ => return index,
+        };
+
+        let low = component(&centroid_bounds.min, axis);
+        let extent = component(&centroid_bounds.max, axis) - low;
+        let mut middle = start;
+        for i in start..end {
+            let primitive = self.primitives[i];
+            let bin = (((component(&centroids[primitive], axis) - low) / extent * BINS as f64) as usize).min(BINS - 1);
+            if bin < split {
+                self.primitives.swap(i, middle);
+                middle += 1;
+            }
+        }
+
+        self.build(bounds, centroids, start, middle);
+        let second = self.build(bounds, centroids, middle, end);
+        let node = &mut self.nodes[index];
+        node.start = second;
+        node.count = 0;
+        node.axis = axis;
+        index
+    }
+
+    // Closest hit along the ray. The test gives the distance to the hit on
+    // a primitive, if any, along with whatever the caller wants back. Nodes
+    // are visited front to back and skipped once they lie entirely past the
+    // closest hit found so far.
+    pub fn closest<H, F: FnMut(usize) -> Option<(f64, H)>>(&self, ray: &Ray, mut test: F) -> Option<H> {
+        let mut closest: Option<(f64, H)> = None;
+        for primitive in self.unbounded.iter() {
+            if let Some((t, hit)) = test(*primitive) {
+                if closest.as_ref().map_or(true, |(closest_t, _)| t < *closest_t) {
+                    closest = Some((t, hit));
+                }
+            }
+        }
+        if self.nodes.is_empty() {
+            return closest.map(|(_, hit)| hit);
+        }
+
+        let inverse_direction = Point::from((1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z));
+        let negative = [ray.direction.x < 0.0, ray.direction.y < 0.0, ray.direction.z < 0.0];
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let t_max = closest.as_ref().map_or(ray.t_max, |(t, _)| t.min(ray.t_max));
+            if !node.bounds.hit(ray, &inverse_direction, t_max) {
+                continue;
+            }
+
+            if node.count > 0 {
+                for primitive in self.primitives[node.start..node.start + node.count].iter() {
+                    if let Some((t, hit)) = test(*primitive) {
+                        if closest.as_ref().map_or(true, |(closest_t, _)| t < *closest_t) {
+                            closest = Some((t, hit));
+                        }
+                    }
+                }
+            }
+            else if negative[node.axis] {
+                // going down the split axis the second child, holding the
+                // higher side, comes first, so push it last
+                stack.push(index + 1);
+                stack.push(node.start);
+            }
+            else {
+                stack.push(node.start);
+                stack.push(index + 1);
+            }
+        }
+
+        closest.map(|(_, hit)| hit)
+    }
+
+    // Whether the test passes for any primitive whose box the ray reaches
+    // within its interval, stopping at the first one that does. No need to
+    // go front to back when any hit will do.
+    pub fn any<F: FnMut(usize) -> bool>(&self, ray: &Ray, mut test: F) -> bool {
+        if self.unbounded.iter().any(|primitive| test(*primitive)) {
+            return true;
+        }
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let inverse_direction = Point::from((1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z));
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bounds.hit(ray, &inverse_direction, ray.t_max) {
+                continue;
+            }
+
+            if node.count > 0 {
+                if self.primitives[node.start..node.start + node.count].iter().any(|primitive| test(*primitive)) {
+                    return true;
+                }
+            }
+            else {
+                stack.push(node.start);
+                stack.push(index + 1);
+            }
+        }
+
+        false
+    }
+
+    // Closest hits for the lanes of a packet, like closest for each ray
+    // but walking the tree once for all of them. A node is entered while
+    // any lane still reaches its box before that lane's closest hit, and
+    // the test is given the lanes reaching a primitive's leaf, returning
+    // hits for any of them.
+    pub fn closest_packet<H, F>(&self, packet: &RayPacket, lanes: Mask, mut test: F) -> [Option<H>; PACKET_SIZE]
+        where F: FnMut(usize, Mask) -> [Option<(f64, H)>; PACKET_SIZE] {
+
+        let mut closest: [Option<(f64, H)>; PACKET_SIZE] = Default::default();
+        for primitive in self.unbounded.iter() {
+            keep_closest(&mut closest, test(*primitive, lanes));
+        }
+        if self.nodes.is_empty() {
+            return closest.map(|hit| hit.map(|(_, hit)| hit));
+        }
+
+        // each lane's t_max, brought in to its closest hit as they're found
+        let ray_t_max = packet.t_max.to_array();
+        let closest_t_max = |closest: &[Option<(f64, H)>; PACKET_SIZE]| {
+            let mut t_max = ray_t_max;
+            for (t_max, closest) in t_max.iter_mut().zip(closest.iter()) {
+                if let Some((t, _)) = closest {
+                    *t_max = t.min(*t_max);
+                }
+            }
+            F64x4::new(t_max)
+        };
+        let mut t_max = closest_t_max(&closest);
+
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let reached = packet.hits_bounds(&node.bounds, &t_max).and(&lanes);
+            if !reached.any() {
+                continue;
+            }
+
+            if node.count > 0 {
+                let mut found = false;
+                for primitive in self.primitives[node.start..node.start + node.count].iter() {
+                    found |= keep_closest(&mut closest, test(*primitive, reached));
+                }
+                if found {
+                    t_max = closest_t_max(&closest);
+                }
+            }
+            else if packet.negative[node.axis] {
+                stack.push(index + 1);
+                stack.push(node.start);
+            }
+            else {
+                stack.push(node.start);
+                stack.push(index + 1);
+            }
+        }
+
+        closest.map(|hit| hit.map(|(_, hit)| hit))
+    }
+}
+
+// whether any of the hits was closer than the one already kept for its lane
+fn keep_closest<H>(closest: &mut [Option<(f64, H)>; PACKET_SIZE], hits: [Option<(f64, H)>; PACKET_SIZE]) -> bool {
+    let mut found = false;
+    for (closest, hit) in closest.iter_mut().zip(hits) {
+        if let Some((t, hit)) = hit {
+            if closest.as_ref().map_or(true, |(closest_t, _)| t < *closest_t) {
+                *closest = Some((t, hit));
+                found = true;
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::sampler::Sampler;
+
+    struct Ball {
+        center: Point,
+        radius: f64,
+    }
+
+    impl Ball {
+        fn bounds(&self) -> Bounds {
+            let extent = Point::from((self.radius, self.radius, self.radius));
+            Bounds::new(self.center.add(&extent.mult(-1.0)), self.center.add(&extent))
+        }
+
+        // nearest root within the ray's interval
+        fn hit(&self, ray: &Ray) -> Option<f64> {
+            let offset = ray.position.add(&self.center.mult(-1.0));
+            let b = ray.direction.dot(&offset);
+            let discriminant = b * b - offset.dot(&offset) + self.radius * self.radius;
+            if discriminant < 0.0 {
+                return None;
+            }
+            [-b - discriminant.sqrt(), -b + discriminant.sqrt()].iter().cloned().find(|t| ray.contains(*t))
+        }
+    }
+
+    fn random_point(sampler: &mut Sampler, size: f64) -> Point {
+        Point::from((
+            size * (2.0 * sampler.next_f64() - 1.0),
+            size * (2.0 * sampler.next_f64() - 1.0),
+            size * (2.0 * sampler.next_f64() - 1.0)))
+    }
+
+    fn random_balls(sampler: &mut Sampler, count: usize) -> Vec<Ball> {
+        (0..count).map(|_| Ball {
+            center: random_point(sampler, 10.0),
+            radius: 0.05 + 0.5 * sampler.next_f64(),
+        }).collect()
+    }
+
+    // from anywhere around the balls in any direction, some rays cut short
+    fn random_rays(sampler: &mut Sampler, count: usize) -> Vec<Ray> {
+        (0..count).map(|i| {
+            let origin = random_point(sampler, 12.0);
+            let direction = random_point(sampler, 1.0);
+            let t_max = if i % 3 == 0 { 20.0 * sampler.next_f64() } else { std::f64::INFINITY };
+            Ray::bounded(origin, direction, 0.0, t_max)
+        }).collect()
+    }
+
+    fn brute_force(balls: &[Ball], ray: &Ray) -> Option<(f64, usize)> {
+        balls.iter().enumerate()
+            .filter_map(|(index, ball)| ball.hit(ray).map(|t| (t, index)))
+            .fold(None, |closest: Option<(f64, usize)>, (t, index)| {
+                if closest.map_or(true, |(closest_t, _)| t < closest_t) { Some((t, index)) } else { closest }
+            })
+    }
+
+    fn assert_matches(bvh: &Bvh, balls: &[Ball], rays: &[Ray]) {
+        for ray in rays.iter() {
+            let expected = brute_force(balls, ray);
+            let found = bvh.closest(ray, |index| balls[index].hit(ray).map(|t| (t, (t, index))));
+            assert_eq!(found, expected, "closest along {:?}", ray);
+
+            let any = bvh.any(ray, |index| balls[index].hit(ray).is_some());
+            assert_eq!(any, expected.is_some(), "any along {:?}", ray);
+        }
+    }
+
+    fn bounds(balls: &[Ball]) -> Vec<Bounds> {
+        balls.iter().map(|ball| ball.bounds()).collect()
+    }
+
+    #[test]
+    fn closest_and_any_match_brute_force() {
+        let mut sampler = Sampler::new(1);
+        let balls = random_balls(&mut sampler, 500);
+        let rays = random_rays(&mut sampler, 2000);
+        assert_matches(&Bvh::new(&bounds(&balls)), &balls, &rays);
+    }
+
+    #[test]
+    fn single_primitive() {
+        let mut sampler = Sampler::new(2);
+        let balls = vec![Ball { center: Point::from((1.0, 2.0, 3.0)), radius: 4.0 }];
+        let rays = random_rays(&mut sampler, 500);
+        assert!(rays.iter().any(|ray| brute_force(&balls, ray).is_some()));
+        assert_matches(&Bvh::new(&bounds(&balls)), &balls, &rays);
+    }
+
+    #[test]
+    fn identical_centroids() {
+        // more than fit in a leaf and nowhere to split them
+        let mut sampler = Sampler::new(3);
+        let balls = (0..20).map(|i| Ball {
+            center: Point::from((0.5, -0.5, 1.0)),
+            radius: 0.5 + 0.25 * i as f64,
+        }).collect::<Vec<Ball>>();
+        let rays = random_rays(&mut sampler, 500);
+        assert_matches(&Bvh::new(&bounds(&balls)), &balls, &rays);
+    }
+
+    #[test]
+    fn empty_and_unbounded() {
+        let mut sampler = Sampler::new(4);
+        let ray = Ray::new(Point::from((0.0, 0.0, 0.0)), Point::from((1.0, 0.0, 0.0)));
+
+        let nothing = Bvh::new(&[]);
+        assert!(nothing.closest(&ray, |index| Some((1.0, index))).is_none());
+        assert!(!nothing.any(&ray, |_| true));
+        assert!(nothing.bounds().is_empty());
+
+        // primitives with empty or infinite boxes are still offered to every
+        // ray, alongside the ones in the tree
+        let balls = random_balls(&mut sampler, 50);
+        let mut all = bounds(&balls);
+        all.push(Bounds::empty());
+        all.push(Bounds::infinite());
+        let bvh = Bvh::new(&all);
+        assert!(!bvh.bounds().is_finite());
+        for ray in random_rays(&mut sampler, 500).iter() {
+            let mut offered = Vec::new();
+            bvh.closest(ray, |index| {
+                offered.push(index);
+                None::<(f64, ())>
+            });
+            assert!(offered.contains(&50) && offered.contains(&51));
+
+            let found = bvh.closest(ray, |index| match index {
+                50 => None,
+                // a far wall behind everything
+                51 => Some((1e6, (1e6, index))),
+                _ => balls[index].hit(ray).map(|t| (t, (t, index))),
+            });
+            let expected = brute_force(&balls, ray).or(Some((1e6, 51)));
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn small_moves_refit() {
+        let mut sampler = Sampler::new(5);
+        let mut balls = random_balls(&mut sampler, 300);
+        let mut bvh = Bvh::new(&bounds(&balls));
+        for ball in balls.iter_mut() {
+            ball.center = ball.center.add(&random_point(&mut sampler, 0.05));
+        }
+
+        assert!(!bvh.update(&bounds(&balls)));
+        let rays = random_rays(&mut sampler, 1000);
+        assert_matches(&bvh, &balls, &rays);
+        assert_matches(&Bvh::new(&bounds(&balls)), &balls, &rays);
+    }
+
+    #[test]
+    fn scattering_rebuilds() {
+        let mut sampler = Sampler::new(6);
+        let mut balls = random_balls(&mut sampler, 300);
+        let mut bvh = Bvh::new(&bounds(&balls));
+        for ball in balls.iter_mut() {
+            ball.center = random_point(&mut sampler, 10.0);
+        }
+
+        assert!(bvh.update(&bounds(&balls)));
+        assert!(!(bvh.cost() > bvh.built_cost));
+        assert_matches(&bvh, &balls, &random_rays(&mut sampler, 1000));
+    }
+
+    #[test]
+    fn changed_primitives_rebuild() {
+        let mut sampler = Sampler::new(7);
+        let mut balls = random_balls(&mut sampler, 100);
+        let mut bvh = Bvh::new(&bounds(&balls));
+
+        balls.push(Ball { center: Point::from((0.0, 0.0, 0.0)), radius: 1.0 });
+        assert!(bvh.update(&bounds(&balls)));
+        assert_matches(&bvh, &balls, &random_rays(&mut sampler, 500));
+
+        balls.truncate(40);
+        assert!(bvh.update(&bounds(&balls)));
+        assert_matches(&bvh, &balls, &random_rays(&mut sampler, 500));
+
+        // same count, but one of them loses its box
+        let mut unbounded = bounds(&balls);
+        unbounded[3] = Bounds::infinite();
+        assert!(bvh.update(&unbounded));
+        assert!(!bvh.bounds().is_finite());
+
+        // and nothing changing at all is a refit
+        assert!(!bvh.update(&unbounded));
+    }
+}
@@ -0,0 +1,96 @@
+use std::f64::consts::PI;
+
+use crate::scene::object::{Intersect, Hit, Interval};
+use crate::scene::object::local_hit::{LocalHit, to_local, local_bounds, closest, intervals, azimuth, intersect_cap};
+use crate::scene::object::polynomial::solve_quadratic;
+use crate::scene::primitives::{Point, Frame, Bounds};
+use crate::scene::bsdf::MaterialId;
+use crate::scene::LightRay;
+
+// Cone standing on a disk base, narrowing to its apex at the given height
+// up the axis. On the side u goes around the axis and v up to the apex,
+// on the base v goes out from the center like on a Disk.
+pub struct Cone {
+    position: Point,
+    frame: Frame,
+    radius: f64,
+    height: f64,
+    material: MaterialId,
+}
+
+impl Cone {
+    pub fn new(base: Point, axis: Point, radius: f64, height: f64, material: MaterialId) -> Cone {
+        Cone {
+            position: base,
+            frame: Frame::from_normal(&axis.normalize()),
+            radius: radius,
+            height: height,
+            material: material,
+        }
+    }
+
+    // every hit along the ray's line, in local space
+    fn local_hits(&self, ray: &LightRay) -> Vec<LocalHit> {
+        let (origin, direction) = to_local(ray, &self.position, &self.frame);
+        let mut hits = Vec::new();
+
+        // x^2 + y^2 = (k (height - z))^2, keeping only the nappe below the
+        // apex and above the base
+        let k = self.radius / self.height;
+        let k2 = k * k;
+        let w = self.height - origin.z;
+        let a = direction.x * direction.x + direction.y * direction.y - k2 * direction.z * direction.z;
+        let b = origin.x * direction.x + origin.y * direction.y + k2 * w * direction.z;
+        let c = origin.x * origin.x + origin.y * origin.y - k2 * w * w;
+        for t in solve_quadratic(a, b, c).iter().flatten() {
+            let local = origin.add(&direction.mult(*t));
+            if !t.is_finite() || local.z < 0.0 || local.z > self.height {
+                continue;
+            }
+
+            // project back onto the side, the apex itself has no direction
+            // around the axis so it keeps the hit as is
+            let ring = k * (self.height - local.z);
+            let distance = (local.x * local.x + local.y * local.y).sqrt();
+            let local = if distance > 0.0 {
+                Point::from((local.x * ring / distance, local.y * ring / distance, local.z))
+            }
+            else {
+                local
+            };
+            let phi = azimuth(&local);
+
+            hits.push(LocalHit {
+                t: *t,
+                normal: Point::from((phi.cos(), phi.sin(), k)).normalize(),
+                uv: (phi / (2.0 * PI), local.z / self.height),
+                dpdu: Point::from((-2.0 * PI * local.y, 2.0 * PI * local.x, 0.0)),
+                dpdv: Point::from((-self.radius * phi.cos(), -self.radius * phi.sin(), self.height)),
+                point: local,
+            });
+        }
+
+        // only the base is capped, the apex closes the top
+        hits.extend(intersect_cap(&origin, &direction, 0.0, false, self.radius));
+        hits
+    }
+}
+
+impl Intersect for Cone {
+    fn intersect(&self, ray: &LightRay) -> Option<Hit> {
+        closest(ray, self.local_hits(ray))
+            .map(|hit| hit.to_world(ray, &self.position, &self.frame, self.material))
+    }
+
+    fn bounds(&self) -> Bounds {
+        local_bounds(
+            &Point::from((-self.radius, -self.radius, 0.0)),
+            &Point::from((self.radius, self.radius, self.height)),
+            &self.position,
+            &self.frame)
+    }
+
+    fn intervals(&self, ray: &LightRay) -> Option<Vec<Interval>> {
+        Some(intervals(ray, self.local_hits(ray), &self.position, &self.frame, self.material))
+    }
+}
@@ -0,0 +1,252 @@
+use crate::scene::object::{Intersect, Hit};
+use crate::scene::primitives::Bounds;
+use crate::scene::LightRay;
+
+// Span of a ray's line inside a closed object, between the hit where the
+// line goes in and the one where it comes back out
+#[derive(Clone, Debug)]
+pub struct Interval {
+    pub enter: Hit,
+    pub exit: Hit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    // inside the left object but not the right one
+    Difference,
+}
+
+impl CsgOperation {
+    fn inside(&self, left: bool, right: bool) -> bool {
+        match self {
+            CsgOperation::Union => left || right,
+            CsgOperation::Intersection => left && right,
+            CsgOperation::Difference => left && !right,
+        }
+    }
+}
+
+// Solid made by a boolean operation on two closed objects, each of which
+// can be another Csg. Surfaces keep the material of the object they come
+// from. Objects that do not enclose a volume count as empty.
+pub struct Csg {
+    operation: CsgOperation,
+    left: Box<dyn Intersect>,
+    right: Box<dyn Intersect>,
+}
+
+impl Csg {
+    pub fn new(operation: CsgOperation, left: Box<dyn Intersect>, right: Box<dyn Intersect>) -> Csg {
+        Csg {
+            operation: operation,
+            left: left,
+            right: right,
+        }
+    }
+
+    pub fn union(left: Box<dyn Intersect>, right: Box<dyn Intersect>) -> Csg {
+        Csg::new(CsgOperation::Union, left, right)
+    }
+
+    pub fn intersection(left: Box<dyn Intersect>, right: Box<dyn Intersect>) -> Csg {
+        Csg::new(CsgOperation::Intersection, left, right)
+    }
+
+    pub fn difference(left: Box<dyn Intersect>, right: Box<dyn Intersect>) -> Csg {
+        Csg::new(CsgOperation::Difference, left, right)
+    }
+}
+
+// the same hit seen from the other side, for surfaces of a subtracted
+// object which bound the result from the outside in
+fn flipped(hit: Hit) -> Hit {
+    Hit {
+        geometric_normal: hit.geometric_normal.mult(-1.0),
+        shading_normal: hit.shading_normal.mult(-1.0),
+        front_face: !hit.front_face,
+        ..hit
+    }
+}
+
+impl Intersect for Csg {
+    fn intersect(&self, ray: &LightRay) -> Option<Hit> {
+        self.intervals(ray)?.into_iter()
+            .flat_map(|interval| vec![interval.enter, interval.exit])
+            .find(|hit| ray.ray.contains(hit.t))
+    }
+
+    fn bounds(&self) -> Bounds {
+        match self.operation {
+            CsgOperation::Union => self.left.bounds().union(&self.right.bounds()),
+            CsgOperation::Intersection => self.left.bounds().overlap(&self.right.bounds()),
+            CsgOperation::Difference => self.left.bounds(),
+        }
+    }
+
+    // Walks the entries and exits of both objects in order along the line,
+    // keeping the ones where being inside the result changes
+    fn intervals(&self, ray: &LightRay) -> Option<Vec<Interval>> {
+        // (hit, from the right object, entering)
+        let mut events = Vec::<(Hit, bool, bool)>::new();
+        for (object, is_right) in [(&self.left, false), (&self.right, true)].iter() {
+            for interval in object.intervals(ray).unwrap_or_default() {
+                events.push((interval.enter, *is_right, true));
+                events.push((interval.exit, *is_right, false));
+            }
+        }
+        events.sort_by(|a, b| a.0.t.partial_cmp(&b.0.t).unwrap_or(std::cmp::Ordering::Equal));
+
+        let mut in_left = false;
+        let mut in_right = false;
+        let mut inside = false;
+        let mut enter = None;
+        let mut intervals = Vec::new();
+        for (hit, is_right, entering) in events {
+            if is_right {
+                in_right = entering;
+            }
+            else {
+                in_left = entering;
+            }
+            if self.operation.inside(in_left, in_right) == inside {
+                continue;
+            }
+            inside = !inside;
+
+            let hit = if is_right && self.operation == CsgOperation::Difference { flipped(hit) } else { hit };
+            if inside {
+                enter = Some(hit);
+            }
+            else if let Some(enter) = enter.take() {
+                intervals.push(Interval {
+                    enter: enter,
+                    exit: hit,
+                });
+            }
+        }
+
+        Some(intervals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::object::Sphere;
+    use crate::scene::primitives::{Point, Ray};
+
+    // unit spheres centered at x = 0 and x = 1
+    fn spheres() -> (Box<dyn Intersect>, Box<dyn Intersect>) {
+        (Box::new(Sphere::new(Point::from((0.0, 0.0, 0.0)), 0, 1.0)),
+         Box::new(Sphere::new(Point::from((1.0, 0.0, 0.0)), 1, 1.0)))
+    }
+
+    fn ray(from: (f64, f64, f64), direction: (f64, f64, f64)) -> LightRay {
+        LightRay::new(Ray::new(Point::from(from), Point::from(direction)))
+    }
+
+    // along +x from x = -5, entering the left sphere at t = 4
+    fn along_x() -> LightRay {
+        ray((-5.0, 0.0, 0.0), (1.0, 0.0, 0.0))
+    }
+
+    fn spans(csg: &Csg, ray: &LightRay) -> Vec<(f64, f64)> {
+        csg.intervals(ray).unwrap().iter().map(|interval| (interval.enter.t, interval.exit.t)).collect()
+    }
+
+    fn assert_spans(spans: Vec<(f64, f64)>, expected: &[(f64, f64)]) {
+        assert_eq!(spans.len(), expected.len(), "{:?}, expected {:?}", spans, expected);
+        for (span, expected) in spans.iter().zip(expected.iter()) {
+            assert!((span.0 - expected.0).abs() < 1e-9 && (span.1 - expected.1).abs() < 1e-9,
+                "{:?}, expected {:?}", spans, expected);
+        }
+    }
+
+    fn assert_normal(hit: &Hit, expected: (f64, f64, f64)) {
+        assert!(hit.geometric_normal.add(&Point::from(expected).mult(-1.0)).abs() < 1e-9,
+            "normal {:?} at t {}, expected {:?}", hit.geometric_normal, hit.t, expected);
+    }
+
+    #[test]
+    fn union_spans_both() {
+        let (left, right) = spheres();
+        assert_spans(spans(&Csg::union(left, right), &along_x()), &[(4.0, 7.0)]);
+    }
+
+    #[test]
+    fn intersection_spans_overlap() {
+        let (left, right) = spheres();
+        let csg = Csg::intersection(left, right);
+        assert_spans(spans(&csg, &along_x()), &[(5.0, 6.0)]);
+
+        // entered through the right sphere and left through the left one
+        let intervals = csg.intervals(&along_x()).unwrap();
+        assert_eq!(intervals[0].enter.material, Some(1));
+        assert_eq!(intervals[0].exit.material, Some(0));
+    }
+
+    #[test]
+    fn difference_flips_right_normals() {
+        let (left, right) = spheres();
+        let csg = Csg::difference(left, right);
+        assert_spans(spans(&csg, &along_x()), &[(4.0, 5.0)]);
+
+        // the hollow left by the right sphere faces into it, along +x
+        let intervals = csg.intervals(&along_x()).unwrap();
+        assert_normal(&intervals[0].enter, (-1.0, 0.0, 0.0));
+        assert_normal(&intervals[0].exit, (1.0, 0.0, 0.0));
+        assert!(!intervals[0].exit.front_face);
+
+        // and from the other side, where the right sphere is left first
+        let back = ray((5.0, 0.0, 0.0), (-1.0, 0.0, 0.0));
+        let hit = csg.intersect(&back).unwrap();
+        assert!((hit.t - 5.0).abs() < 1e-9, "t {}", hit.t);
+        assert_normal(&hit, (1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn nested() {
+        // both spheres with a small ball taken out of their overlap
+        let (left, right) = spheres();
+        let hole = Box::new(Sphere::new(Point::from((0.5, 0.0, 0.0)), 2, 0.3));
+        let csg = Csg::difference(Box::new(Csg::union(left, right)), hole);
+        assert_spans(spans(&csg, &along_x()), &[(4.0, 5.2), (5.8, 7.0)]);
+
+        let intervals = csg.intervals(&along_x()).unwrap();
+        assert_normal(&intervals[0].exit, (1.0, 0.0, 0.0));
+        assert_normal(&intervals[1].enter, (-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn tangent_to_subtracted() {
+        // touching the right sphere at the origin, inside the left one,
+        // whatever the roots come out as only that point is cut away
+        let (left, right) = spheres();
+        let csg = Csg::difference(left, right);
+        let spans = spans(&csg, &ray((0.0, -5.0, 0.0), (0.0, 1.0, 0.0)));
+        assert!(!spans.is_empty());
+        assert!((spans[0].0 - 4.0).abs() < 1e-9 && (spans[spans.len() - 1].1 - 6.0).abs() < 1e-9, "{:?}", spans);
+        let length = spans.iter().map(|(enter, exit)| exit - enter).sum::<f64>();
+        assert!((length - 2.0).abs() < 1e-6, "{:?}", spans);
+    }
+
+    #[test]
+    fn starting_inside() {
+        let inside = ray((0.5, 0.0, 0.0), (1.0, 0.0, 0.0));
+
+        let (left, right) = spheres();
+        let hit = Csg::union(left, right).intersect(&inside).unwrap();
+        assert!((hit.t - 1.5).abs() < 1e-9, "t {}", hit.t);
+        assert_normal(&hit, (1.0, 0.0, 0.0));
+
+        let (left, right) = spheres();
+        let hit = Csg::intersection(left, right).intersect(&inside).unwrap();
+        assert!((hit.t - 0.5).abs() < 1e-9, "t {}", hit.t);
+
+        // in the part taken away, with nothing left ahead
+        let (left, right) = spheres();
+        assert!(Csg::difference(left, right).intersect(&inside).is_none());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::scene::object::{Intersect, Hit, Interval};
+use crate::scene::primitives::{Point, Bounds};
+use crate::scene::bsdf::MaterialId;
+use crate::scene::LightRay;
+
+// Axis aligned box. Each face has its own uv square, spanning it along
+// the two other axes in x, y, z order.
+pub struct Cuboid {
+    min: Point,
+    max: Point,
+    material: MaterialId,
+}
+
+impl Cuboid {
+    pub fn new(min: Point, max: Point, material: MaterialId) -> Cuboid {
+        Cuboid {
+            min: Point::from((min.x.min(max.x), min.y.min(max.y), min.z.min(max.z))),
+            max: Point::from((min.x.max(max.x), min.y.max(max.y), min.z.max(max.z))),
+            material: material,
+        }
+    }
+}
+
+// Slab test against the box from min to max, returns the entry and exit
+// distances along with the axis of the face crossed at each
+pub fn intersect_slabs(origin: &Point, direction: &Point, min: &Point, max: &Point) -> Option<((f64, usize), (f64, usize))> {
+    let origin = [origin.x, origin.y, origin.z];
+    let direction = [direction.x, direction.y, direction.z];
+    let min = [min.x, min.y, min.z];
+    let max = [max.x, max.y, max.z];
+
+    let mut t_near = (std::f64::NEG_INFINITY, 0);
+    let mut t_far = (std::f64::INFINITY, 0);
+    for axis in 0..3 {
+        if direction[axis] == 0.0 {
+            if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                return None;
+            }
+            continue;
+        }
+
+        let t0 = (min[axis] - origin[axis]) / direction[axis];
+        let t1 = (max[axis] - origin[axis]) / direction[axis];
+        let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+        if t0 > t_near.0 {
+            t_near = (t0, axis);
+        }
+        if t1 < t_far.0 {
+            t_far = (t1, axis);
+        }
+    }
+
+    if t_near.0 > t_far.0 {
+        None
+    }
+    else {
+        Some((t_near, t_far))
+    }
+}
+
+// Hit on the face of the box crossed along the given axis, snapped onto it
+pub fn box_face_hit(ray: &LightRay, t: f64, axis: usize, min: &Point, max: &Point, material: Option<MaterialId>) -> Hit {
+    let intersection = ray.ray.at(t);
+    let min = [min.x, min.y, min.z];
+    let max = [max.x, max.y, max.z];
+    let mut position = [intersection.x, intersection.y, intersection.z];
+    let on_max = 2.0 * position[axis] > min[axis] + max[axis];
+    let mut normal_dir = [0.0; 3];
+    normal_dir[axis] = if on_max { 1.0 } else { -1.0 };
+
+    // snap onto the face exactly
+    position[axis] = if on_max { max[axis] } else { min[axis] };
+
+    // uv spans the face along the two other axes in order
+    let u_axis = (axis + 1) % 3;
+    let v_axis = (axis + 2) % 3;
+    let extent = |axis: usize| max[axis] - min[axis];
+    let mut dpdu = [0.0; 3];
+    let mut dpdv = [0.0; 3];
+    dpdu[u_axis] = extent(u_axis);
+    dpdv[v_axis] = extent(v_axis);
+    let uv = (
+        (position[u_axis] - min[u_axis]) / extent(u_axis),
+        (position[v_axis] - min[v_axis]) / extent(v_axis));
+
+    Hit::new(
+        t,
+        Point::from((position[0], position[1], position[2])),
+        Point::from((normal_dir[0], normal_dir[1], normal_dir[2])),
+        ray.dir(),
+        uv,
+        Point::from((dpdu[0], dpdu[1], dpdu[2])),
+        Point::from((dpdv[0], dpdv[1], dpdv[2])),
+        material)
+}
+
+impl Intersect for Cuboid {
+    fn intersect(&self, ray: &LightRay) -> Option<Hit> {
+        let (t_near, t_far) = intersect_slabs(ray.pos(), ray.dir(), &self.min, &self.max)?;
+        let (t, axis) = if ray.ray.contains(t_near.0) {
+            t_near
+        }
+        else if ray.ray.contains(t_far.0) {
+            t_far
+        }
+        else {
+            return None;
+        };
+
+        Some(box_face_hit(ray, t, axis, &self.min, &self.max, Some(self.material)))
+    }
+
+    fn bounds(&self) -> Bounds {
+        Bounds::new(self.min.clone(), self.max.clone())
+    }
+
+    fn intervals(&self, ray: &LightRay) -> Option<Vec<Interval>> {
+        let slabs = intersect_slabs(ray.pos(), ray.dir(), &self.min, &self.max);
+        Some(slabs.into_iter().map(|((t_near, near_axis), (t_far, far_axis))| Interval {
+            enter: box_face_hit(ray, t_near, near_axis, &self.min, &self.max, Some(self.material)),
+            exit: box_face_hit(ray, t_far, far_axis, &self.min, &self.max, Some(self.material)),
+        }).collect())
+    }
+}
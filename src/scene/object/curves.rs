@@ -0,0 +1,339 @@
+use crate::scene::object::{Intersect, Hit, Bvh};
+use crate::scene::primitives::{Point, Frame, Bounds};
+use crate::scene::bsdf::MaterialId;
+use crate::scene::LightRay;
+use crate::scene::load_err::LoadErr;
+
+// deepest a segment is split when looking for a hit
+const MAX_DEPTH: u32 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CurveKind {
+    // flat strip always turned to face the ray, cheap and fine for thin
+    // strands like fur
+    Ribbon,
+    // round tube, for strands thick enough to show their shape
+    Cylinder,
+}
+
+// Single strand passing through the given points, at least two, smoothly
+// interpolated between them. Its width tapers linearly from root to tip.
+pub struct Strand {
+    points: Vec<Point>,
+    root_width: f64,
+    tip_width: f64,
+}
+
+impl Strand {
+    // Fails with fewer than two points or a negative width
+    pub fn new(points: Vec<Point>, root_width: f64, tip_width: f64) -> Result<Strand, LoadErr> {
+        if points.len() < 2 {
+            return Err(LoadErr::from(format!("strand needs at least two points, found {}", points.len())));
+        }
+        if !(root_width >= 0.0) || !(tip_width >= 0.0) {
+            return Err(LoadErr::from(format!("bad strand widths {} {}", root_width, tip_width)));
+        }
+
+        Ok(Strand {
+            points: points,
+            root_width: root_width,
+            tip_width: tip_width,
+        })
+    }
+}
+
+// cubic Bezier piece of a strand
+struct Segment {
+    points: [Point; 4],
+    widths: (f64, f64),
+    // span of the strand it covers, for uvs
+    u: (f64, f64),
+}
+
+fn bezier(points: &[Point; 4], u: f64) -> Point {
+    let v = 1.0 - u;
+    points[0].mult(v * v * v)
+        .add(&points[1].mult(3.0 * v * v * u))
+        .add(&points[2].mult(3.0 * v * u * u))
+        .add(&points[3].mult(u * u * u))
+}
+
+fn bezier_derivative(points: &[Point; 4], u: f64) -> Point {
+    let v = 1.0 - u;
+    points[1].add(&points[0].mult(-1.0)).mult(3.0 * v * v)
+        .add(&points[2].add(&points[1].mult(-1.0)).mult(6.0 * v * u))
+        .add(&points[3].add(&points[2].mult(-1.0)).mult(3.0 * u * u))
+}
+
+// the two halves of a curve, by de Casteljau
+fn split(points: &[Point; 4]) -> ([Point; 4], [Point; 4]) {
+    let middle = |a: &Point, b: &Point| a.add(b).mult(0.5);
+    let p01 = middle(&points[0], &points[1]);
+    let p12 = middle(&points[1], &points[2]);
+    let p23 = middle(&points[2], &points[3]);
+    let p012 = middle(&p01, &p12);
+    let p123 = middle(&p12, &p23);
+    let center = middle(&p012, &p123);
+    ([points[0].clone(), p01, p012, center.clone()], [center, p123, p23, points[3].clone()])
+}
+
+// Space where the ray starts at the origin and runs along +z, in which a
+// strand is hit where it passes within half its width of the z axis
+struct RaySpace {
+    origin: Point,
+    frame: Frame,
+    // length of the ray's direction, which isn't normalized in instances
+    speed: f64,
+}
+
+impl RaySpace {
+    fn new(ray: &LightRay) -> RaySpace {
+        let speed = ray.dir().abs();
+        RaySpace {
+            origin: ray.pos().clone(),
+            frame: Frame::from_normal(&ray.dir().mult(1.0 / speed)),
+            speed: speed,
+        }
+    }
+
+    fn point(&self, point: &Point) -> Point {
+        self.frame.to_local(&point.add(&self.origin.mult(-1.0)))
+    }
+}
+
+// Closest approach of a segment to the ray, in ray space, as the
+// parameter along the segment and distance along the ray
+struct CurveHit {
+    u: f64,
+    z: f64,
+    // signed distance across the strand from its center line
+    offset: f64,
+}
+
+impl Segment {
+    // Halves the curve until the pieces are close enough to straight, then
+    // tests those as line segments (Nakamaru and Ohno 2002). Pieces whose
+    // box misses the ray are dropped on the way.
+    fn intersect(&self, space: &RaySpace, z_range: (f64, f64), kind: CurveKind) -> Option<CurveHit> {
+        let points = [
+            space.point(&self.points[0]),
+            space.point(&self.points[1]),
+            space.point(&self.points[2]),
+            space.point(&self.points[3])];
+
+        // depth from how far the control polygon bends, so the error of the
+        // straight pieces stays under a twentieth of the width
+        let bend = (0..2).map(|i| {
+            points[i].add(&points[i + 1].mult(-2.0)).add(&points[i + 2]).abs()
+        }).fold(0.0, f64::max);
+        let tolerance = 0.05 * self.widths.0.max(self.widths.1);
+        let depth = if bend > 0.0 && tolerance > 0.0 {
+            ((6.0 * bend / (8.0 * tolerance)).log2() / 2.0).ceil().max(0.0).min(MAX_DEPTH as f64) as u32
+        }
+        else {
+            0
+        };
+
+        let mut closest: Option<CurveHit> = None;
+        self.recurse(&points, (0.0, 1.0), depth, z_range, kind, &mut closest);
+        closest
+    }
+
+    fn width(&self, u: f64) -> f64 {
+        self.widths.0 + (self.widths.1 - self.widths.0) * u
+    }
+
+    fn recurse(&self,
+        points: &[Point; 4],
+        span: (f64, f64),
+        depth: u32,
+        z_range: (f64, f64),
+        kind: CurveKind,
+        closest: &mut Option<CurveHit>) {
+
+        let radius = 0.5 * self.width(span.0).max(self.width(span.1));
+        let bounds = Bounds::from_points(points.iter());
+        let z_max = closest.as_ref().map_or(z_range.1, |hit| hit.z);
+        if bounds.min.x - radius > 0.0 || bounds.max.x + radius < 0.0
+            || bounds.min.y - radius > 0.0 || bounds.max.y + radius < 0.0
+            || bounds.max.z + radius < z_range.0 || bounds.min.z - radius > z_max {
+            return;
+        }
+
+        if depth > 0 {
+            let middle = 0.5 * (span.0 + span.1);
+            let (first, second) = split(points);
+            self.recurse(&first, (span.0, middle), depth - 1, z_range, kind, closest);
+            self.recurse(&second, (middle, span.1), depth - 1, z_range, kind, closest);
+            return;
+        }
+
+        // closest approach of the line through the piece's ends to the z
+        // axis, only counting it when it lies between the ends
+        let start = &points[0];
+        let end = &points[3];
+        let (dx, dy) = (end.x - start.x, end.y - start.y);
+        let length2 = dx * dx + dy * dy;
+        if !(length2 > 0.0) {
+            return;
+        }
+        let along = -(start.x * dx + start.y * dy) / length2;
+        if along < 0.0 || along > 1.0 {
+            // leave the ends to the neighbouring pieces, except at the ends
+            // of the whole segment
+            if !(along < 0.0 && span.0 == 0.0) && !(along > 1.0 && span.1 == 1.0) {
+                return;
+            }
+        }
+        let along = along.max(0.0).min(1.0);
+
+        let u = span.0 + along * (span.1 - span.0);
+        let center = bezier(points, along);
+        let radius = 0.5 * self.width(u);
+        let distance2 = center.x * center.x + center.y * center.y;
+        if distance2 > radius * radius {
+            return;
+        }
+
+        let depth_offset = match kind {
+            CurveKind::Ribbon => 0.0,
+            CurveKind::Cylinder => (radius * radius - distance2).sqrt(),
+        };
+        let z = center.z - depth_offset;
+        if z <= z_range.0 || z >= z_max {
+            return;
+        }
+
+        // which side of the center line the ray passes on
+        let side = if dx * center.y - dy * center.x > 0.0 { 1.0 } else { -1.0 };
+        *closest = Some(CurveHit {
+            u: u,
+            z: z,
+            offset: side * distance2.sqrt(),
+        });
+    }
+}
+
+// Hair, fur or grass as a set of strands, each made of cubic Bezier
+// segments through its points (Catmull-Rom splines). u runs from root to
+// tip, v across the strand.
+//
+// A ray leaving a strand can find the same strand again right away, so
+// hits nearer its origin than the strand is wide are passed over.
+pub struct Curves {
+    segments: Vec<Segment>,
+    kind: CurveKind,
+    material: MaterialId,
+    // over the segments, by index
+    bvh: Bvh,
+}
+
+impl Curves {
+    pub fn new(strands: Vec<Strand>, kind: CurveKind, material: MaterialId) -> Curves {
+        let mut segments = Vec::new();
+        for strand in strands.iter() {
+            let points = &strand.points;
+            let count = points.len() - 1;
+            for i in 0..count {
+                // the ends are repeated so the tangent there follows the
+                // first and last spans
+                let before = &points[i.saturating_sub(1)];
+                let after = &points[(i + 2).min(count)];
+                let (start, end) = (&points[i], &points[i + 1]);
+                let u = (i as f64 / count as f64, (i + 1) as f64 / count as f64);
+                let width = |u: f64| strand.root_width + (strand.tip_width - strand.root_width) * u;
+
+                segments.push(Segment {
+                    points: [
+                        start.clone(),
+                        start.add(&end.add(&before.mult(-1.0)).mult(1.0 / 6.0)),
+                        end.add(&after.add(&start.mult(-1.0)).mult(-1.0 / 6.0)),
+                        end.clone()],
+                    widths: (width(u.0), width(u.1)),
+                    u: u,
+                });
+            }
+        }
+
+        let bounds = segments.iter().map(|segment| {
+            let radius = 0.5 * segment.widths.0.max(segment.widths.1);
+            let bounds = Bounds::from_points(segment.points.iter());
+            Bounds::new(
+                bounds.min.add(&Point::from((-radius, -radius, -radius))),
+                bounds.max.add(&Point::from((radius, radius, radius))))
+        }).collect::<Vec<Bounds>>();
+
+        Curves {
+            bvh: Bvh::new(&bounds),
+            segments: segments,
+            kind: kind,
+            material: material,
+        }
+    }
+
+    fn hit(&self, ray: &LightRay, segment: usize, curve_hit: CurveHit, space: &RaySpace) -> Hit {
+        let segment_hit = &self.segments[segment];
+        let t = curve_hit.z / space.speed;
+        let point = ray.ray.at(t);
+        let center = bezier(&segment_hit.points, curve_hit.u);
+        let derivative = bezier_derivative(&segment_hit.points, curve_hit.u);
+        let tangent = derivative.normalize();
+        let width = segment_hit.width(curve_hit.u);
+
+        // ribbons face back along the ray, tubes out from the center line
+        let facing = match self.kind {
+            CurveKind::Ribbon => ray.dir().mult(-1.0),
+            CurveKind::Cylinder => point.add(&center.mult(-1.0)),
+        };
+        let across = facing.add(&tangent.mult(-facing.dot(&tangent)));
+        let normal = if across.abs() > 0.0 {
+            across.normalize()
+        }
+        else {
+            Frame::from_normal(&tangent).tangent
+        };
+
+        let u = segment_hit.u.0 + curve_hit.u * (segment_hit.u.1 - segment_hit.u.0);
+        let v = 0.5 + curve_hit.offset / width;
+        let mut hit = Hit::new(
+            t,
+            point,
+            normal.clone(),
+            ray.dir(),
+            (u, v.max(0.0).min(1.0)),
+            derivative.mult(1.0 / (segment_hit.u.1 - segment_hit.u.0)),
+            normal.cross(&tangent).mult(width),
+            Some(self.material));
+        hit.primitive = segment;
+        hit
+    }
+
+    // interval along the ray in ray space distances, starting past the
+    // strand the ray may have left
+    fn z_range(&self, ray: &LightRay, space: &RaySpace, segment: usize) -> (f64, f64) {
+        let widths = self.segments[segment].widths;
+        let z_min = (ray.ray.t_min * space.speed).max(widths.0.max(widths.1));
+        (z_min, ray.ray.t_max * space.speed)
+    }
+}
+
+impl Intersect for Curves {
+    fn intersect(&self, ray: &LightRay) -> Option<Hit> {
+        let space = RaySpace::new(ray);
+        self.bvh.closest(&ray.ray, |segment| {
+            self.segments[segment].intersect(&space, self.z_range(ray, &space, segment), self.kind)
+                .map(|curve_hit| (curve_hit.z / space.speed, (segment, curve_hit)))
+        }).map(|(segment, curve_hit)| self.hit(ray, segment, curve_hit, &space))
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.bvh.bounds()
+    }
+
+    fn occludes(&self, ray: &LightRay) -> bool {
+        let space = RaySpace::new(ray);
+        self.bvh.any(&ray.ray, |segment| {
+            self.segments[segment].intersect(&space, self.z_range(ray, &space, segment), self.kind).is_some()
+        })
+    }
+}
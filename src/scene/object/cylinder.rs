@@ -0,0 +1,83 @@
+use std::f64::consts::PI;
+
+use crate::scene::object::{Intersect, Hit, Interval};
+use crate::scene::object::local_hit::{LocalHit, to_local, local_bounds, closest, intervals, azimuth, intersect_cap};
+use crate::scene::object::polynomial::solve_quadratic;
+use crate::scene::primitives::{Point, Frame, Bounds};
+use crate::scene::bsdf::MaterialId;
+use crate::scene::LightRay;
+
+// Closed cylinder standing on a base point, with a disk cap at either end.
+// On the side u goes around the axis and v up it, on the caps v goes out
+// from the center to the rim like on a Disk.
+pub struct Cylinder {
+    position: Point,
+    frame: Frame,
+    radius: f64,
+    height: f64,
+    material: MaterialId,
+}
+
+impl Cylinder {
+    pub fn new(base: Point, axis: Point, radius: f64, height: f64, material: MaterialId) -> Cylinder {
+        Cylinder {
+            position: base,
+            frame: Frame::from_normal(&axis.normalize()),
+            radius: radius,
+            height: height,
+            material: material,
+        }
+    }
+
+    // every hit along the ray's line, in local space
+    fn local_hits(&self, ray: &LightRay) -> Vec<LocalHit> {
+        let (origin, direction) = to_local(ray, &self.position, &self.frame);
+        let mut hits = Vec::new();
+
+        // x^2 + y^2 = radius^2 between the caps
+        let a = direction.x * direction.x + direction.y * direction.y;
+        let b = origin.x * direction.x + origin.y * direction.y;
+        let c = origin.x * origin.x + origin.y * origin.y - self.radius * self.radius;
+        for t in solve_quadratic(a, b, c).iter().flatten() {
+            let local = origin.add(&direction.mult(*t));
+            if !t.is_finite() || local.z < 0.0 || local.z > self.height {
+                continue;
+            }
+
+            // project back onto the side
+            let scale = self.radius / (local.x * local.x + local.y * local.y).sqrt();
+            let local = Point::from((local.x * scale, local.y * scale, local.z));
+            hits.push(LocalHit {
+                t: *t,
+                normal: Point::from((local.x / self.radius, local.y / self.radius, 0.0)),
+                uv: (azimuth(&local) / (2.0 * PI), local.z / self.height),
+                dpdu: Point::from((-2.0 * PI * local.y, 2.0 * PI * local.x, 0.0)),
+                dpdv: Point::from((0.0, 0.0, self.height)),
+                point: local,
+            });
+        }
+
+        hits.extend(intersect_cap(&origin, &direction, 0.0, false, self.radius));
+        hits.extend(intersect_cap(&origin, &direction, self.height, true, self.radius));
+        hits
+    }
+}
+
+impl Intersect for Cylinder {
+    fn intersect(&self, ray: &LightRay) -> Option<Hit> {
+        closest(ray, self.local_hits(ray))
+            .map(|hit| hit.to_world(ray, &self.position, &self.frame, self.material))
+    }
+
+    fn bounds(&self) -> Bounds {
+        local_bounds(
+            &Point::from((-self.radius, -self.radius, 0.0)),
+            &Point::from((self.radius, self.radius, self.height)),
+            &self.position,
+            &self.frame)
+    }
+
+    fn intervals(&self, ray: &LightRay) -> Option<Vec<Interval>> {
+        Some(intervals(ray, self.local_hits(ray), &self.position, &self.frame, self.material))
+    }
+}
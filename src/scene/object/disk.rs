@@ -0,0 +1,69 @@
+use std::f64::consts::PI;
+
+use crate::scene::object::{Intersect, Hit};
+use crate::scene::object::plane::{intersect_plane, plane_offset};
+use crate::scene::primitives::{Point, Frame, Bounds};
+use crate::scene::bsdf::MaterialId;
+use crate::scene::LightRay;
+
+// Flat circle, u goes around the center and v out from it to the edge
+pub struct Disk {
+    position: Point,
+    frame: Frame,
+    radius: f64,
+    material: MaterialId,
+}
+
+impl Disk {
+    pub fn new(pos: Point, normal: Point, radius: f64, material: MaterialId) -> Disk {
+        Disk {
+            position: pos,
+            frame: Frame::from_normal(&normal.normalize()),
+            radius: radius,
+            material: material,
+        }
+    }
+}
+
+impl Intersect for Disk {
+    fn intersect(&self, ray: &LightRay) -> Option<Hit> {
+        let normal = &self.frame.normal;
+        let t = intersect_plane(ray, &self.position, normal)?;
+        let offset = plane_offset(ray, t, &self.position, normal);
+        let distance = offset.abs();
+        if distance > self.radius {
+            return None;
+        }
+
+        let local = self.frame.to_local(&offset);
+        let phi = local.y.atan2(local.x);
+        let phi = if phi < 0.0 { phi + 2.0 * PI } else { phi };
+
+        // around the circle, and straight out from the center
+        let dpdu = self.frame.to_world(&Point::from((-2.0 * PI * local.y, 2.0 * PI * local.x, 0.0)));
+        let dpdv = if distance > 0.0 {
+            offset.mult(self.radius / distance)
+        }
+        else {
+            Point::from((0.0, 0.0, 0.0))
+        };
+
+        Some(Hit::new(
+            t,
+            self.position.add(&offset),
+            normal.clone(),
+            ray.dir(),
+            (phi / (2.0 * PI), distance / self.radius),
+            dpdu,
+            dpdv,
+            Some(self.material)))
+    }
+
+    // the circle reaches out along each axis as far as the plane allows
+    fn bounds(&self) -> Bounds {
+        let normal = &self.frame.normal;
+        let reach = |component: f64| self.radius * (1.0 - component * component).max(0.0).sqrt();
+        let extent = Point::from((reach(normal.x), reach(normal.y), reach(normal.z)));
+        Bounds::new(self.position.add(&extent.mult(-1.0)), self.position.add(&extent))
+    }
+}
@@ -0,0 +1,287 @@
+use crate::scene::primitives::Point;
+
+// Signed distance to a surface, negative inside it. Fields only have to
+// estimate it, but can never claim more than the true distance or tracing
+// steps through the surface. Everything here keeps that by changing by
+// at most one unit per unit moved, combinators that stretch space scale
+// their result back down.
+pub trait DistanceField {
+    fn distance(&self, point: &Point) -> f64;
+}
+
+pub struct SphereField {
+    radius: f64,
+}
+
+impl SphereField {
+    pub fn new(radius: f64) -> SphereField {
+        SphereField {
+            radius: radius,
+        }
+    }
+}
+
+impl DistanceField for SphereField {
+    fn distance(&self, point: &Point) -> f64 {
+        point.abs() - self.radius
+    }
+}
+
+// Box with its edges rounded off by the given radius, which is taken out
+// of the half extents so the box keeps its size
+pub struct BoxField {
+    half: Point,
+    rounding: f64,
+}
+
+impl BoxField {
+    pub fn new(half: Point, rounding: f64) -> BoxField {
+        BoxField {
+            half: half,
+            rounding: rounding,
+        }
+    }
+}
+
+impl DistanceField for BoxField {
+    fn distance(&self, point: &Point) -> f64 {
+        let q = Point::from((
+            point.x.abs() - self.half.x + self.rounding,
+            point.y.abs() - self.half.y + self.rounding,
+            point.z.abs() - self.half.z + self.rounding));
+        let outside = Point::from((q.x.max(0.0), q.y.max(0.0), q.z.max(0.0))).abs();
+        let inside = q.x.max(q.y).max(q.z).min(0.0);
+        outside + inside - self.rounding
+    }
+}
+
+// torus around the z axis
+pub struct TorusField {
+    major: f64,
+    minor: f64,
+}
+
+impl TorusField {
+    pub fn new(major: f64, minor: f64) -> TorusField {
+        TorusField {
+            major: major,
+            minor: minor,
+        }
+    }
+}
+
+impl DistanceField for TorusField {
+    fn distance(&self, point: &Point) -> f64 {
+        let ring = (point.x * point.x + point.y * point.y).sqrt() - self.major;
+        (ring * ring + point.z * point.z).sqrt() - self.minor
+    }
+}
+
+// everything within radius of the segment between two points
+pub struct CapsuleField {
+    start: Point,
+    end: Point,
+    radius: f64,
+}
+
+impl CapsuleField {
+    pub fn new(start: Point, end: Point, radius: f64) -> CapsuleField {
+        CapsuleField {
+            start: start,
+            end: end,
+            radius: radius,
+        }
+    }
+}
+
+impl DistanceField for CapsuleField {
+    fn distance(&self, point: &Point) -> f64 {
+        let offset = point.add(&self.start.mult(-1.0));
+        let segment = self.end.add(&self.start.mult(-1.0));
+        let along = (offset.dot(&segment) / segment.dot(&segment)).max(0.0).min(1.0);
+        offset.add(&segment.mult(-along)).abs() - self.radius
+    }
+}
+
+// Power 8 Mandelbulb, within a radius of about 1.2 of the origin. The
+// distance is estimated from how fast the iteration escapes, following
+// the running derivative alongside it.
+pub struct Mandelbulb {
+    power: f64,
+    iterations: u32,
+}
+
+impl Mandelbulb {
+    pub fn new(power: f64, iterations: u32) -> Mandelbulb {
+        Mandelbulb {
+            power: power,
+            iterations: iterations,
+        }
+    }
+}
+
+impl DistanceField for Mandelbulb {
+    fn distance(&self, point: &Point) -> f64 {
+        let mut z = point.clone();
+        let mut derivative = 1.0;
+        let mut radius = z.abs();
+        for _ in 0..self.iterations {
+            if radius > 2.0 || radius == 0.0 {
+                break;
+            }
+
+            // raise to the power in spherical coordinates and add the point
+            let theta = (z.z / radius).acos() * self.power;
+            let phi = z.y.atan2(z.x) * self.power;
+            derivative = radius.powf(self.power - 1.0) * self.power * derivative + 1.0;
+            let scaled = radius.powf(self.power);
+            z = Point::from((
+                scaled * theta.sin() * phi.cos(),
+                scaled * theta.sin() * phi.sin(),
+                scaled * theta.cos())).add(point);
+            radius = z.abs();
+        }
+
+        if radius == 0.0 {
+            0.0
+        }
+        else {
+            0.5 * radius.ln() * radius / derivative
+        }
+    }
+}
+
+pub struct Translate {
+    field: Box<dyn DistanceField>,
+    offset: Point,
+}
+
+impl Translate {
+    pub fn new(field: Box<dyn DistanceField>, offset: Point) -> Translate {
+        Translate {
+            field: field,
+            offset: offset,
+        }
+    }
+}
+
+impl DistanceField for Translate {
+    fn distance(&self, point: &Point) -> f64 {
+        self.field.distance(&point.add(&self.offset.mult(-1.0)))
+    }
+}
+
+// Union blending the two surfaces together where they come within
+// smoothness of each other, rather than meeting at a crease
+pub struct SmoothUnion {
+    left: Box<dyn DistanceField>,
+    right: Box<dyn DistanceField>,
+    smoothness: f64,
+}
+
+impl SmoothUnion {
+    pub fn new(left: Box<dyn DistanceField>, right: Box<dyn DistanceField>, smoothness: f64) -> SmoothUnion {
+        SmoothUnion {
+            left: left,
+            right: right,
+            smoothness: smoothness,
+        }
+    }
+}
+
+impl DistanceField for SmoothUnion {
+    // polynomial smooth minimum
+    fn distance(&self, point: &Point) -> f64 {
+        let left = self.left.distance(point);
+        let right = self.right.distance(point);
+        if self.smoothness <= 0.0 {
+            return left.min(right);
+        }
+
+        let h = (0.5 + 0.5 * (right - left) / self.smoothness).max(0.0).min(1.0);
+        right + (left - right) * h - self.smoothness * h * (1.0 - h)
+    }
+}
+
+// Copies of the field repeated forever on a grid with the given spacing
+// along each axis, zero for no repetition along it. Each copy has to fit
+// inside its own cell.
+pub struct Repeat {
+    field: Box<dyn DistanceField>,
+    period: Point,
+}
+
+impl Repeat {
+    pub fn new(field: Box<dyn DistanceField>, period: Point) -> Repeat {
+        Repeat {
+            field: field,
+            period: period,
+        }
+    }
+}
+
+impl DistanceField for Repeat {
+    fn distance(&self, point: &Point) -> f64 {
+        let wrap = |value: f64, period: f64| {
+            if period > 0.0 { value - period * (value / period).round() } else { value }
+        };
+        self.field.distance(&Point::from((
+            wrap(point.x, self.period.x),
+            wrap(point.y, self.period.y),
+            wrap(point.z, self.period.z))))
+    }
+}
+
+// Twists the field around the z axis by rate radians per unit up it. That
+// stretches space more the further out from the axis, so the distance is
+// scaled down by how much.
+pub struct Twist {
+    field: Box<dyn DistanceField>,
+    rate: f64,
+}
+
+impl Twist {
+    pub fn new(field: Box<dyn DistanceField>, rate: f64) -> Twist {
+        Twist {
+            field: field,
+            rate: rate,
+        }
+    }
+}
+
+impl DistanceField for Twist {
+    fn distance(&self, point: &Point) -> f64 {
+        let angle = -self.rate * point.z;
+        let (sin, cos) = angle.sin_cos();
+        let untwisted = Point::from((cos * point.x - sin * point.y, sin * point.x + cos * point.y, point.z));
+        let radius = (point.x * point.x + point.y * point.y).sqrt();
+        self.field.distance(&untwisted) / (1.0 + self.rate * self.rate * radius * radius).sqrt()
+    }
+}
+
+// Ripples the surface in and out by up to amplitude, with the given number
+// of waves per unit along each axis
+pub struct Displace {
+    field: Box<dyn DistanceField>,
+    amplitude: f64,
+    frequency: f64,
+}
+
+impl Displace {
+    pub fn new(field: Box<dyn DistanceField>, amplitude: f64, frequency: f64) -> Displace {
+        Displace {
+            field: field,
+            amplitude: amplitude,
+            frequency: frequency,
+        }
+    }
+}
+
+impl DistanceField for Displace {
+    fn distance(&self, point: &Point) -> f64 {
+        let f = 2.0 * std::f64::consts::PI * self.frequency;
+        let ripple = self.amplitude * (f * point.x).sin() * (f * point.y).sin() * (f * point.z).sin();
+        // the ripple changes by at most amplitude f sqrt(3) per unit moved
+        (self.field.distance(point) + ripple) / (1.0 + self.amplitude * f * 3f64.sqrt())
+    }
+}
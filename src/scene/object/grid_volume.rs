@@ -0,0 +1,407 @@
+// Heterogeneous volume described by a dense grid of densities.
+//
+// Density grids are read from one of two formats, told apart by the
+// first four bytes of the file.
+//
+// Text format:
+//     # comments run to the end of the line
+//     nx ny nz
+//     d0 d1 d2 ...
+// followed by nx * ny * nz whitespace separated densities.
+//
+// Binary format:
+//     b"DGRD", then nx, ny, nz as little endian u32,
+//     then nx * ny * nz little endian f32 densities.
+//
+// In both the x index varies fastest, then y, then z. Densities are
+// scaled by the volume's density scale to get the extinction coefficient.
+
+use std::fs;
+use std::path::Path;
+
+use crate::scene::object::{Intersect, Hit};
+use crate::scene::object::cuboid::{intersect_slabs, box_face_hit};
+use crate::scene::primitives::{Point, Ray, Bounds};
+use crate::scene::medium::{Medium, MediumEvent, HenyeyGreenstein};
+use crate::scene::sampler::Sampler;
+use crate::scene::load_err::LoadErr;
+use crate::scene::LightRay;
+
+const BINARY_MAGIC: &[u8; 4] = b"DGRD";
+
+pub struct DensityGrid {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    densities: Vec<f64>,
+}
+
+impl DensityGrid {
+    pub fn new(nx: usize, ny: usize, nz: usize, densities: Vec<f64>) -> Result<Self, LoadErr> {
+        if nx == 0 || ny == 0 || nz == 0 {
+            return Err(LoadErr::from(format!("density grid has empty dimension {}x{}x{}", nx, ny, nz)));
+        }
+        let count = nx.checked_mul(ny).and_then(|count| count.checked_mul(nz))
+            .ok_or_else(|| LoadErr::from(format!("density grid {}x{}x{} is too large", nx, ny, nz)))?;
+        if densities.len() != count {
+            return Err(LoadErr::from(format!(
+                "density grid is {}x{}x{} but has {} values",
+                nx, ny, nz, densities.len())));
+        }
+        if let Some(bad) = densities.iter().find(|d| !(**d >= 0.0) || d.is_infinite()) {
+            return Err(LoadErr::from(format!("density grid has invalid density {}", bad)));
+        }
+
+        Ok(DensityGrid {
+            nx: nx,
+            ny: ny,
+            nz: nz,
+            densities: densities,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LoadErr> {
+        let bytes = fs::read(path.as_ref())?;
+        let grid = if bytes.starts_with(BINARY_MAGIC) {
+            Self::parse_binary(&bytes)
+        }
+        else {
+            match std::str::from_utf8(&bytes) {
+                Ok(text) => Self::parse_text(text),
+                Err(_) => Err(LoadErr::from(String::from("not a text or binary density grid"))),
+            }
+        };
+
+        grid.map_err(|err| LoadErr::from(format!("{}: {}", path.as_ref().display(), err)))
+    }
+
+    fn parse_text(text: &str) -> Result<Self, LoadErr> {
+        let mut tokens = text.lines().enumerate().flat_map(|(line_index, line)| {
+            let content = match line.find('#') {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            content.split_whitespace().map(move |token| (line_index + 1, token))
+        });
+
+        let mut dimensions = [0usize; 3];
+        for dimension in dimensions.iter_mut() {
+            let (line, token) = tokens.next()
+                .ok_or_else(|| String::from("missing grid dimensions"))?;
+            *dimension = token.parse()
+                .map_err(|_| format!("line {}: bad grid dimension '{}'", line, token))?;
+        }
+
+        let densities = tokens.map(|(line, token)| {
+            token.parse::<f64>()
+                .map_err(|_| LoadErr::from(format!("line {}: bad density '{}'", line, token)))
+        }).collect::<Result<Vec<f64>, LoadErr>>()?;
+
+        Self::new(dimensions[0], dimensions[1], dimensions[2], densities)
+    }
+
+    fn parse_binary(bytes: &[u8]) -> Result<Self, LoadErr> {
+        let read_u32 = |offset: usize| -> Result<u32, LoadErr> {
+            bytes.get(offset..offset + 4)
+                .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+                .ok_or_else(|| LoadErr::from(String::from("truncated header")))
+        };
+
+        let nx = read_u32(4)? as usize;
+        let ny = read_u32(8)? as usize;
+        let nz = read_u32(12)? as usize;
+
+        let body = &bytes[16..];
+        let size = nx.checked_mul(ny).and_then(|count| count.checked_mul(nz)).and_then(|count| count.checked_mul(4))
+            .ok_or_else(|| LoadErr::from(format!("density grid {}x{}x{} is too large", nx, ny, nz)))?;
+        if body.len() != size {
+            return Err(LoadErr::from(format!(
+                "expected {} bytes of densities, found {}",
+                size, body.len())));
+        }
+
+        let densities = body.chunks(4).map(|word| {
+            f32::from_le_bytes([word[0], word[1], word[2], word[3]]) as f64
+        }).collect();
+
+        Self::new(nx, ny, nz, densities)
+    }
+
+    fn max_density(&self) -> f64 {
+        self.densities.iter().fold(0.0, |max, &density| max.max(density))
+    }
+
+    fn at(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.densities[(z * self.ny + y) * self.nx + x]
+    }
+
+    // trilinearly interpolated density, coordinates in [0, 1] across the grid
+    fn lookup(&self, u: f64, v: f64, w: f64) -> f64 {
+        let cell = |coord: f64, n: usize| {
+            let scaled = (coord * n as f64 - 0.5).max(0.0).min((n - 1) as f64);
+            let low = (scaled.floor() as usize).min(n - 1);
+            let high = (low + 1).min(n - 1);
+            (low, high, scaled - low as f64)
+        };
+
+        let (x0, x1, fx) = cell(u, self.nx);
+        let (y0, y1, fy) = cell(v, self.ny);
+        let (z0, z1, fz) = cell(w, self.nz);
+
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let near = lerp(
+            lerp(self.at(x0, y0, z0), self.at(x1, y0, z0), fx),
+            lerp(self.at(x0, y1, z0), self.at(x1, y1, z0), fx),
+            fy);
+        let far = lerp(
+            lerp(self.at(x0, y0, z1), self.at(x1, y0, z1), fx),
+            lerp(self.at(x0, y1, z1), self.at(x1, y1, z1), fx),
+            fy);
+        lerp(near, far, fz)
+    }
+}
+
+// Density grid stretched over an axis aligned box. The box surface is a
+// boundary like Sphere::with_medium, the grid is the medium inside. An
+// Instance turns or shears the box.
+pub struct GridVolume {
+    min: Point,
+    max: Point,
+    grid: DensityGrid,
+    density_scale: f64,
+    albedo: f64,
+    // upper bound on extinction anywhere in the grid, for delta tracking
+    majorant: f64,
+    phase: HenyeyGreenstein,
+}
+
+impl GridVolume {
+    pub fn new(min: Point, max: Point, grid: DensityGrid, density_scale: f64, albedo: f64, g: f64) -> Self {
+        let majorant = grid.max_density() * density_scale;
+        GridVolume {
+            min: min,
+            max: max,
+            grid: grid,
+            density_scale: density_scale,
+            albedo: albedo,
+            majorant: majorant,
+            phase: HenyeyGreenstein::new(g),
+        }
+    }
+
+    fn extinction(&self, point: &Point) -> f64 {
+        let u = (point.x - self.min.x) / (self.max.x - self.min.x);
+        let v = (point.y - self.min.y) / (self.max.y - self.min.y);
+        let w = (point.z - self.min.z) / (self.max.z - self.min.z);
+        self.grid.lookup(u, v, w) * self.density_scale
+    }
+
+    // next tentative collision past t, sampled against the majorant
+    fn step(&self, t: f64, sampler: &mut Sampler) -> f64 {
+        t - (1.0 - sampler.next_f64()).ln() / self.majorant
+    }
+
+    // Tracking never needs to go past the far side of the box. Bounding it
+    // here keeps rays that graze an edge, and so lose their exit hit, from
+    // tracking forever through empty space.
+    fn clip(&self, ray: &Ray, t_max: f64) -> f64 {
+        match intersect_slabs(&ray.position, &ray.direction, &self.min, &self.max) {
+            Some((_, (t_far, _))) => t_max.min(t_far),
+            None => 0.0,
+        }
+    }
+}
+
+impl Intersect for GridVolume {
+    fn intersect(&self, ray: &LightRay) -> Option<Hit> {
+        let (t_near, t_far) = intersect_slabs(ray.pos(), ray.dir(), &self.min, &self.max)?;
+        let (t, axis) = if ray.ray.contains(t_near.0) {
+            t_near
+        }
+        else if ray.ray.contains(t_far.0) {
+            t_far
+        }
+        else {
+            return None;
+        };
+
+        Some(box_face_hit(ray, t, axis, &self.min, &self.max, None))
+    }
+
+    fn bounds(&self) -> Bounds {
+        Bounds::new(self.min.clone(), self.max.clone())
+    }
+
+    fn medium(&self) -> Option<&dyn Medium> {
+        Some(self)
+    }
+}
+
+impl Medium for GridVolume {
+    // delta tracking, collisions with the fictitious medium that pads the
+    // real density up to the majorant are rejected and tracking continues
+    fn sample_distance(&self, ray: &Ray, t_max: f64, sampler: &mut Sampler) -> MediumEvent {
+        if self.majorant <= 0.0 {
+            return MediumEvent::Pass { weight: 1.0 };
+        }
+
+        let t_max = self.clip(ray, t_max);
+        let mut t = 0.0;
+        loop {
+            t = self.step(t, sampler);
+            if t >= t_max {
+                return MediumEvent::Pass { weight: 1.0 };
+            }
+
+            let point = ray.position.add(&ray.direction.mult(t));
+            if sampler.next_f64() < self.extinction(&point) / self.majorant {
+                return MediumEvent::Scatter { t: t, weight: self.albedo };
+            }
+        }
+    }
+
+    // ratio tracking, an unbiased estimate with less variance than
+    // counting delta tracking escapes
+    fn transmittance(&self, ray: &Ray, t_max: f64, sampler: &mut Sampler) -> f64 {
+        if self.majorant <= 0.0 {
+            return 1.0;
+        }
+
+        let t_max = self.clip(ray, t_max);
+        let mut transmittance = 1.0;
+        let mut t = 0.0;
+        loop {
+            t = self.step(t, sampler);
+            if t >= t_max {
+                return transmittance;
+            }
+
+            let point = ray.position.add(&ray.direction.mult(t));
+            transmittance *= 1.0 - self.extinction(&point) / self.majorant;
+        }
+    }
+
+    fn phase(&self) -> &HenyeyGreenstein {
+        &self.phase
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::f64::consts::PI;
+
+    use super::*;
+    use crate::scene::object::{Instance, Sphere};
+    use crate::scene::primitives::Transform;
+
+    fn binary(dimensions: [u32; 3], densities: &[f32]) -> Vec<u8> {
+        let mut bytes = BINARY_MAGIC.to_vec();
+        for dimension in dimensions.iter() {
+            bytes.extend(&dimension.to_le_bytes());
+        }
+        for density in densities.iter() {
+            bytes.extend(&density.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn fails(grid: Result<DensityGrid, LoadErr>, message: &str) {
+        match grid {
+            Ok(_) => panic!("parsed, expected '{}'", message),
+            Err(err) => assert_eq!(format!("{}", err), message),
+        }
+    }
+
+    #[test]
+    fn parses_text() {
+        let grid = DensityGrid::parse_text("# a cloud\n2 1 2 # x y z\n0 0.5\n1\n2e0\n").unwrap();
+        assert_eq!((grid.nx, grid.ny, grid.nz), (2, 1, 2));
+        assert_eq!(grid.densities, vec![0.0, 0.5, 1.0, 2.0]);
+        // x fastest, then y, then z
+        assert_eq!(grid.at(1, 0, 0), 0.5);
+        assert_eq!(grid.at(0, 0, 1), 1.0);
+        assert_eq!(grid.max_density(), 2.0);
+    }
+
+    #[test]
+    fn parses_binary() {
+        let grid = DensityGrid::parse_binary(&binary([1, 2, 2], &[0.0, 0.25, 1.5, 3.0])).unwrap();
+        assert_eq!((grid.nx, grid.ny, grid.nz), (1, 2, 2));
+        assert_eq!(grid.densities, vec![0.0, 0.25, 1.5, 3.0]);
+        assert_eq!(grid.at(0, 1, 0), 0.25);
+        assert_eq!(grid.at(0, 0, 1), 1.5);
+    }
+
+    #[test]
+    fn rejects_size_mismatch() {
+        fails(DensityGrid::parse_text("2 2 1\n1 2 3\n"), "density grid is 2x2x1 but has 3 values");
+        fails(DensityGrid::parse_text("1 1 1\n1 2\n"), "density grid is 1x1x1 but has 2 values");
+        fails(DensityGrid::parse_binary(&binary([2, 2, 1], &[1.0, 2.0, 3.0])), "expected 16 bytes of densities, found 12");
+        fails(DensityGrid::parse_binary(&binary([1, 1, 1], &[1.0, 2.0])), "expected 4 bytes of densities, found 8");
+        fails(DensityGrid::parse_binary(&BINARY_MAGIC[..]), "truncated header");
+    }
+
+    #[test]
+    fn rejects_overflowing_sizes() {
+        let huge = u32::max_value();
+        fails(DensityGrid::parse_binary(&binary([huge, huge, huge], &[])),
+            &format!("density grid {}x{}x{} is too large", huge, huge, huge));
+        let huge = usize::max_value();
+        fails(DensityGrid::parse_text(&format!("{} {} 2\n1\n", huge, huge)),
+            &format!("density grid {}x{}x2 is too large", huge, huge));
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        fails(DensityGrid::parse_text("2 1\n"), "missing grid dimensions");
+        fails(DensityGrid::parse_text("2 -1 1\n"), "line 1: bad grid dimension '-1'");
+        fails(DensityGrid::parse_text("1 1 2\n1\nfog\n"), "line 3: bad density 'fog'");
+        fails(DensityGrid::parse_text("0 1 1\n"), "density grid has empty dimension 0x1x1");
+        fails(DensityGrid::parse_text("1 1 1\n-1\n"), "density grid has invalid density -1");
+        fails(DensityGrid::parse_binary(&binary([1, 1, 1], &[std::f32::NAN])), "density grid has invalid density NaN");
+    }
+
+    // unit box of density 1, stretched to 2 along x and stood on end along
+    // y, centered at (5, 0, 0)
+    fn stretched_volume() -> Instance {
+        let grid = DensityGrid::new(1, 1, 1, vec![1.0]).unwrap();
+        let volume = GridVolume::new(Point::from((-0.5, -0.5, -0.5)), Point::from((0.5, 0.5, 0.5)), grid, 1.0, 1.0, 0.0);
+        let transform = Transform::scaling(2.0, 1.0, 1.0)
+            .then(&Transform::rotation(&Point::from((0.0, 0.0, 1.0)), PI / 2.0))
+            .then(&Transform::translation(&Point::from((5.0, 0.0, 0.0))));
+        Instance::new(Arc::new(volume), transform)
+    }
+
+    #[test]
+    fn instances_carry_the_medium() {
+        let volume = stretched_volume();
+        assert!(volume.medium().is_some());
+        let sphere = Instance::new(Arc::new(Sphere::new(Point::from((0.0, 0.0, 0.0)), 0, 1.0)), Transform::scaling(2.0, 2.0, 2.0));
+        assert!(sphere.medium().is_none());
+
+        // from where rays enter, the instance's box is 1 across along x and
+        // 2 along y, densities per world distance leave exp(-length) of the
+        // light
+        let mut sampler = Sampler::new(12);
+        let medium = volume.medium().unwrap();
+        let crossings: [(Ray, f64, f64); 4] = [
+            (Ray::new(Point::from((4.5, 0.0, 0.0)), Point::from((1.0, 0.0, 0.0))), 10.0, 1.0),
+            (Ray::new(Point::from((5.0, -1.0, 0.0)), Point::from((0.0, 1.0, 0.0))), 10.0, 2.0),
+            // stopping halfway through
+            (Ray::new(Point::from((5.0, -1.0, 0.0)), Point::from((0.0, 1.0, 0.0))), 1.0, 1.0),
+            // missing the box altogether
+            (Ray::new(Point::from((0.0, 3.0, 0.0)), Point::from((1.0, 0.0, 0.0))), 10.0, 0.0)];
+        for (ray, t_max, length) in crossings.iter() {
+            let samples = 20000;
+            let mean = (0..samples).map(|_| medium.transmittance(ray, *t_max, &mut sampler)).sum::<f64>() / samples as f64;
+            assert!((mean - (-length).exp()).abs() < 0.01, "transmittance {} through {}", mean, length);
+
+            let scattered = (0..samples).filter(|_| match medium.sample_distance(ray, *t_max, &mut sampler) {
+                MediumEvent::Scatter { .. } => true,
+                MediumEvent::Pass { .. } => false,
+            }).count() as f64 / samples as f64;
+            assert!((scattered - (1.0 - (-length).exp())).abs() < 0.01, "scattered {} through {}", scattered, length);
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use std::fs;
+use std::path::Path;
+
+use crate::scene::object::{Intersect, Hit};
+use crate::scene::object::cuboid::intersect_slabs;
+use crate::scene::object::triangle::{intersect_triangle, triangle_derivatives, triangle_normal};
+use crate::scene::primitives::{Point, Frame, Bounds};
+use crate::scene::bsdf::MaterialId;
+use crate::scene::sampler::Sampler;
+use crate::scene::texture::read_netpbm;
+use crate::scene::load_err::LoadErr;
+use crate::scene::LightRay;
+
+// Elevations in [0, 1] on a grid of nx by ny samples, row major with the
+// first row at the low y edge
+pub struct ElevationGrid {
+    nx: usize,
+    ny: usize,
+    heights: Vec<f64>,
+}
+
+impl ElevationGrid {
+    pub fn new(nx: usize, ny: usize, heights: Vec<f64>) -> Result<Self, LoadErr> {
+        if nx < 2 || ny < 2 {
+            return Err(LoadErr::from(format!("elevation grid {}x{} has no cells", nx, ny)));
+        }
+        if heights.len() != nx * ny {
+            return Err(LoadErr::from(format!(
+                "elevation grid is {}x{} but has {} values",
+                nx, ny, heights.len())));
+        }
+        if let Some(bad) = heights.iter().find(|h| !(**h >= 0.0 && **h <= 1.0)) {
+            return Err(LoadErr::from(format!("elevation grid has height {} outside [0, 1]", bad)));
+        }
+
+        Ok(ElevationGrid {
+            nx: nx,
+            ny: ny,
+            heights: heights,
+        })
+    }
+
+    // Grayscale PGM, or PPM averaged over its channels, white being the
+    // highest. The top of the image is the high y edge, like a map. PNG
+    // isn't read, it would need a deflate decoder, convert to PGM instead.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LoadErr> {
+        let bytes = fs::read(path.as_ref())?;
+        Self::parse(&bytes).map_err(|err| LoadErr::from(format!("{}: {}", path.as_ref().display(), err)))
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, LoadErr> {
+        let image = read_netpbm(bytes)?;
+        let mut heights = Vec::with_capacity(image.width * image.height);
+        for row in image.samples.chunks(image.width * image.channels).rev() {
+            heights.extend(row.chunks(image.channels)
+                .map(|pixel| pixel.iter().sum::<f64>() / image.channels as f64));
+        }
+        ElevationGrid::new(image.width, image.height, heights)
+    }
+
+    // Fractal value noise, octaves of random values on a lattice that
+    // doubles in resolution and halves in amplitude each time, stretched to
+    // fill [0, 1]. Octaves stop once the lattice is as fine as the grid,
+    // past that they only add noise between neighbouring samples.
+    pub fn fractal(nx: usize, ny: usize, octaves: u32, seed: u64) -> Result<Self, LoadErr> {
+        if nx < 2 || ny < 2 {
+            return ElevationGrid::new(nx, ny, Vec::new());
+        }
+
+        // ceil(log2) of the longer side, the lattice of the last octave has
+        // at least as many cells as the grid has samples
+        let finest = usize::BITS - (nx.max(ny) - 1).leading_zeros();
+        let octaves = octaves.min(finest);
+
+        let count = nx.checked_mul(ny)
+            .ok_or_else(|| LoadErr::from(format!("elevation grid {}x{} is too large", nx, ny)))?;
+        let mut sampler = Sampler::new(seed);
+        let mut heights = vec![0.0; count];
+        for octave in 0..octaves {
+            let cells = 2usize << octave;
+            let lattice = (0..(cells + 1) * (cells + 1)).map(|_| sampler.next_f64()).collect::<Vec<f64>>();
+            let amplitude = 0.5f64.powi(octave as i32);
+
+            // lattice coordinate, cell and smoothed offset in it
+            let locate = |index: usize, count: usize| {
+                let coordinate = index as f64 / (count - 1) as f64 * cells as f64;
+                let cell = (coordinate.floor() as usize).min(cells - 1);
+                let offset = coordinate - cell as f64;
+                (cell, offset * offset * (3.0 - 2.0 * offset))
+            };
+            for y in 0..ny {
+                let (row, fy) = locate(y, ny);
+                for x in 0..nx {
+                    let (column, fx) = locate(x, nx);
+                    let value = |dx: usize, dy: usize| lattice[(row + dy) * (cells + 1) + column + dx];
+                    let bottom = value(0, 0) * (1.0 - fx) + value(1, 0) * fx;
+                    let top = value(0, 1) * (1.0 - fx) + value(1, 1) * fx;
+                    heights[y * nx + x] += amplitude * (bottom * (1.0 - fy) + top * fy);
+                }
+            }
+        }
+
+        let low = heights.iter().cloned().fold(std::f64::INFINITY, f64::min);
+        let high = heights.iter().cloned().fold(std::f64::NEG_INFINITY, f64::max);
+        let range = if high > low { high - low } else { 1.0 };
+        ElevationGrid::new(nx, ny, heights.iter().map(|h| (h - low) / range).collect())
+    }
+}
+
+// Terrain over the box from min to max, the grid spread across x and y and
+// its heights from min.z up to max.z. Each cell is split into two
+// triangles, shaded with normals interpolated from the slopes at the
+// samples. uv runs across the grid.
+//
+// Rays walk the cells they pass over in order, skipping any the ray stays
+// above or below, so the first hit found is the closest.
+pub struct Heightfield {
+    nx: usize,
+    ny: usize,
+    positions: Vec<Point>,
+    normals: Vec<Point>,
+    // lowest and highest point of each cell
+    ranges: Vec<(f64, f64)>,
+    min: Point,
+    // size of a cell along x and y
+    cell: (f64, f64),
+    bounds: Bounds,
+    material: MaterialId,
+}
+
+impl Heightfield {
+    pub fn new(grid: ElevationGrid, min: Point, max: Point, material: MaterialId) -> Heightfield {
+        let (nx, ny) = (grid.nx, grid.ny);
+        let cell = ((max.x - min.x) / (nx - 1) as f64, (max.y - min.y) / (ny - 1) as f64);
+        let height = |x: usize, y: usize| min.z + grid.heights[y * nx + x] * (max.z - min.z);
+
+        let mut positions = Vec::with_capacity(nx * ny);
+        let mut normals = Vec::with_capacity(nx * ny);
+        for y in 0..ny {
+            for x in 0..nx {
+                positions.push(Point::from((min.x + x as f64 * cell.0, min.y + y as f64 * cell.1, height(x, y))));
+
+                // slopes by differences to the neighbours, one sided at the edges
+                let (left, right) = (x.saturating_sub(1), (x + 1).min(nx - 1));
+                let (down, up) = (y.saturating_sub(1), (y + 1).min(ny - 1));
+                let slope_x = (height(right, y) - height(left, y)) / ((right - left) as f64 * cell.0);
+                let slope_y = (height(x, up) - height(x, down)) / ((up - down) as f64 * cell.1);
+                normals.push(Point::from((-slope_x, -slope_y, 1.0)).normalize());
+            }
+        }
+
+        let mut ranges = Vec::with_capacity((nx - 1) * (ny - 1));
+        for y in 0..ny - 1 {
+            for x in 0..nx - 1 {
+                let corners = [height(x, y), height(x + 1, y), height(x, y + 1), height(x + 1, y + 1)];
+                ranges.push((
+                    corners.iter().cloned().fold(std::f64::INFINITY, f64::min),
+                    corners.iter().cloned().fold(std::f64::NEG_INFINITY, f64::max)));
+            }
+        }
+
+        let low = ranges.iter().map(|range| range.0).fold(std::f64::INFINITY, f64::min);
+        let high = ranges.iter().map(|range| range.1).fold(std::f64::NEG_INFINITY, f64::max);
+        Heightfield {
+            nx: nx,
+            ny: ny,
+            positions: positions,
+            normals: normals,
+            ranges: ranges,
+            bounds: Bounds::new(Point::from((min.x, min.y, low)), Point::from((max.x, max.y, high))),
+            min: min,
+            cell: cell,
+            material: material,
+        }
+    }
+
+    // sample indices of a triangle, two per cell, wound counterclockwise
+    // seen from above
+    fn triangle(&self, triangle: usize) -> [usize; 3] {
+        let cell = triangle / 2;
+        let corner = cell / (self.nx - 1) * self.nx + cell % (self.nx - 1);
+        if triangle % 2 == 0 {
+            [corner, corner + 1, corner + self.nx + 1]
+        }
+        else {
+            [corner, corner + self.nx + 1, corner + self.nx]
+        }
+    }
+
+    fn vertices(&self, indices: [usize; 3]) -> [&Point; 3] {
+        [&self.positions[indices[0]], &self.positions[indices[1]], &self.positions[indices[2]]]
+    }
+
+    // Closest triangle hit, by walking the cells under the ray (Amanatides
+    // and Woo 1987)
+    fn walk(&self, ray: &LightRay) -> Option<(usize, f64, [f64; 3])> {
+        let (t_near, t_far) = intersect_slabs(ray.pos(), ray.dir(), &self.bounds.min, &self.bounds.max)?;
+        let mut t = t_near.0.max(ray.ray.t_min);
+        let t_end = t_far.0.min(ray.ray.t_max);
+        if t > t_end {
+            return None;
+        }
+
+        let (columns, rows) = (self.nx as i64 - 1, self.ny as i64 - 1);
+        let start = ray.ray.at(t);
+        let mut x = (((start.x - self.min.x) / self.cell.0).floor() as i64).max(0).min(columns - 1);
+        let mut y = (((start.y - self.min.y) / self.cell.1).floor() as i64).max(0).min(rows - 1);
+
+        // direction to step in, distance across a cell and to the first
+        // boundary crossed, along one axis
+        let axis = |position: f64, low: f64, cell: i64, size: f64, direction: f64| {
+            if direction > 0.0 {
+                (1, size / direction, t + (low + (cell + 1) as f64 * size - position) / direction)
+            }
+            else if direction < 0.0 {
+                (-1, -size / direction, t + (low + cell as f64 * size - position) / direction)
+            }
+            else {
+                (0, std::f64::INFINITY, std::f64::INFINITY)
+            }
+        };
+        let (step_x, delta_x, mut next_x) = axis(start.x, self.min.x, x, self.cell.0, ray.dir().x);
+        let (step_y, delta_y, mut next_y) = axis(start.y, self.min.y, y, self.cell.1, ray.dir().y);
+
+        loop {
+            let t_exit = next_x.min(next_y).min(t_end);
+            let cell = (y * columns + x) as usize;
+            let (low, high) = self.ranges[cell];
+            let z_enter = ray.pos().z + ray.dir().z * t;
+            let z_exit = ray.pos().z + ray.dir().z * t_exit;
+            if z_enter.min(z_exit) <= high && z_enter.max(z_exit) >= low {
+                let closest = (2 * cell..2 * cell + 2)
+                    .filter_map(|triangle| {
+                        intersect_triangle(ray, self.vertices(self.triangle(triangle))).map(|(t, b)| (triangle, t, b))
+                    })
+                    .fold(None, |closest: Option<(usize, f64, [f64; 3])>, hit| match closest {
+                        Some(closest) if closest.1 <= hit.1 => Some(closest),
+                        _ => Some(hit),
+                    });
+                if closest.is_some() {
+                    return closest;
+                }
+            }
+
+            if t_exit >= t_end {
+                return None;
+            }
+            if next_x < next_y {
+                x += step_x;
+                t = next_x;
+                next_x += delta_x;
+            }
+            else {
+                y += step_y;
+                t = next_y;
+                next_y += delta_y;
+            }
+            if x < 0 || x >= columns || y < 0 || y >= rows {
+                return None;
+            }
+        }
+    }
+
+    fn hit(&self, ray: &LightRay, triangle: usize, t: f64, b: [f64; 3]) -> Hit {
+        let indices = self.triangle(triangle);
+        let vertices = self.vertices(indices);
+        let point = vertices[0].mult(b[0])
+            .add(&vertices[1].mult(b[1]))
+            .add(&vertices[2].mult(b[2]));
+
+        let uvs = [self.uv(indices[0]), self.uv(indices[1]), self.uv(indices[2])];
+        let uv = (
+            b[0] * uvs[0].0 + b[1] * uvs[1].0 + b[2] * uvs[2].0,
+            b[0] * uvs[0].1 + b[1] * uvs[1].1 + b[2] * uvs[2].1);
+        let geometric_normal = triangle_normal(vertices);
+        // the uvs are spread evenly over the grid, but on a grid fine enough
+        // a cell's uv area is too small to solve for the derivatives
+        let (dpdu, dpdv) = triangle_derivatives(vertices, uvs).unwrap_or_else(|| {
+            let frame = Frame::from_normal(&geometric_normal);
+            (frame.tangent, frame.bitangent)
+        });
+
+        let shading_normal = self.normals[indices[0]].mult(b[0])
+            .add(&self.normals[indices[1]].mult(b[1]))
+            .add(&self.normals[indices[2]].mult(b[2]))
+            .normalize();
+
+        let mut hit = Hit::new(t, point, geometric_normal, ray.dir(), uv, dpdu, dpdv, Some(self.material));
+        hit.shading_normal = shading_normal;
+        hit.primitive = triangle;
+        hit
+    }
+
+    fn uv(&self, index: usize) -> (f64, f64) {
+        ((index % self.nx) as f64 / (self.nx - 1) as f64, (index / self.nx) as f64 / (self.ny - 1) as f64)
+    }
+}
+
+impl Intersect for Heightfield {
+    fn intersect(&self, ray: &LightRay) -> Option<Hit> {
+        self.walk(ray).map(|(triangle, t, b)| self.hit(ray, triangle, t, b))
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.bounds.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::primitives::Ray;
+
+    fn fails(grid: Result<ElevationGrid, LoadErr>, message: &str) {
+        match grid {
+            Ok(_) => panic!("parsed, expected '{}'", message),
+            Err(err) => assert_eq!(format!("{}", err), message),
+        }
+    }
+
+    #[test]
+    fn parses_ascii_pgm() {
+        // the bottom row of the image is the low y edge
+        let grid = ElevationGrid::parse(b"P2\n# tiny\n3 2\n4\n0 1 2\n4 3 0\n").unwrap();
+        assert_eq!((grid.nx, grid.ny), (3, 2));
+        assert_eq!(grid.heights, vec![1.0, 0.75, 0.0, 0.0, 0.25, 0.5]);
+    }
+
+    #[test]
+    fn parses_binary_pgm() {
+        let grid = ElevationGrid::parse(&[&b"P5 2 2 255\n"[..], &[0, 255, 51, 102]].concat()).unwrap();
+        assert_eq!((grid.nx, grid.ny), (2, 2));
+        assert_eq!(grid.heights, vec![0.2, 0.4, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn averages_ppm_channels() {
+        let grid = ElevationGrid::parse(b"P3 2 2 3\n3 0 0  0 3 3\n0 0 0  3 3 3\n").unwrap();
+        assert_eq!(grid.heights, vec![0.0, 1.0, 1.0 / 3.0, 2.0 / 3.0]);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        fails(ElevationGrid::parse(b""), "empty file");
+        fails(ElevationGrid::parse(b"P4 2 2\n"), "expected a P2, P3, P5 or P6 image, found 'P4'");
+        fails(ElevationGrid::parse(b"P2 2\n"), "missing height");
+        fails(ElevationGrid::parse(b"P2 2 x 255\n"), "bad height 'x'");
+        fails(ElevationGrid::parse(b"P2 0 2 255\n"), "bad image header 0 2 255");
+        fails(ElevationGrid::parse(b"P2 2 2 0\n"), "bad image header 2 2 0");
+        fails(ElevationGrid::parse(b"P2 2 2 70000\n"), "bad image header 2 2 70000");
+    }
+
+    #[test]
+    fn rejects_bad_bodies() {
+        fails(ElevationGrid::parse(b"P2 2 2 255\n1 2 3\n"), "not enough pixel values");
+        fails(ElevationGrid::parse(&[&b"P5 2 2 255\n"[..], &[0, 1, 2]].concat()), "expected 4 bytes of pixels, found 3");
+        fails(ElevationGrid::parse(b"P2 1 3 255\n1 2 3\n"), "elevation grid 1x3 has no cells");
+        // values past the maximum are brighter than white
+        fails(ElevationGrid::parse(b"P2 2 2 4\n0 1 2 5\n"), "elevation grid has height 1.25 outside [0, 1]");
+    }
+
+    #[test]
+    fn hits_flat_terrain() {
+        let grid = ElevationGrid::new(3, 3, vec![0.5; 9]).unwrap();
+        let terrain = Heightfield::new(grid, Point::from((0.0, 0.0, 0.0)), Point::from((2.0, 2.0, 2.0)), 0);
+
+        let ray = LightRay::new(Ray::new(Point::from((1.5, 0.5, 5.0)), Point::from((0.0, 0.0, -1.0))));
+        let hit = terrain.intersect(&ray).unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-9, "t {}", hit.t);
+        assert!((hit.uv.0 - 0.75).abs() < 1e-9 && (hit.uv.1 - 0.25).abs() < 1e-9, "uv {:?}", hit.uv);
+        assert!(hit.geometric_normal.add(&Point::from((0.0, 0.0, -1.0))).abs() < 1e-9);
+        assert!(hit.dpdu.dot(&hit.geometric_normal).abs() < 1e-9);
+        assert!(hit.dpdv.dot(&hit.geometric_normal).abs() < 1e-9);
+
+        let ray = LightRay::new(Ray::new(Point::from((3.0, 0.5, 5.0)), Point::from((0.0, 0.0, -1.0))));
+        assert!(terrain.intersect(&ray).is_none());
+    }
+}
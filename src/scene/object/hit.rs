@@ -0,0 +1,60 @@
+use crate::scene::primitives::{Point, Frame, Spectrum};
+use crate::scene::bsdf::MaterialId;
+
+// Everything known about where a ray hit an object
+#[derive(Clone, Debug)]
+pub struct Hit {
+    // distance along the ray
+    pub t: f64,
+    pub point: Point,
+    // normal of the actual surface, always facing out of the object
+    pub geometric_normal: Point,
+    // normal used for shading, may be interpolated or perturbed but is kept
+    // on the same side as the geometric normal
+    pub shading_normal: Point,
+    // whether the ray arrived from outside, against the geometric normal
+    pub front_face: bool,
+    pub uv: (f64, f64),
+    // how the point moves as u and v change, zero where undefined
+    pub dpdu: Point,
+    pub dpdv: Point,
+    // None for volume boundaries that have no surface of their own
+    pub material: Option<MaterialId>,
+    // which part of the object was hit, such as a triangle of a mesh
+    pub primitive: usize,
+    // color painted onto the surface, such as mesh vertex colors
+    pub color: Option<Spectrum>,
+}
+
+impl Hit {
+    // Hit where shading uses the geometric normal, front_face is worked out
+    // from the ray direction
+    pub fn new(t: f64,
+        point: Point,
+        normal: Point,
+        direction: &Point,
+        uv: (f64, f64),
+        dpdu: Point,
+        dpdv: Point,
+        material: Option<MaterialId>) -> Self {
+
+        Hit {
+            t: t,
+            point: point,
+            front_face: direction.dot(&normal) < 0.0,
+            shading_normal: normal.clone(),
+            geometric_normal: normal,
+            uv: uv,
+            dpdu: dpdu,
+            dpdv: dpdv,
+            material: material,
+            primitive: 0,
+            color: None,
+        }
+    }
+
+    // shading frame, with its tangent along dpdu where there is one
+    pub fn frame(&self) -> Frame {
+        Frame::from_normal_tangent(&self.shading_normal, &self.dpdu)
+    }
+}
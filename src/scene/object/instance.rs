@@ -0,0 +1,159 @@
+use std::sync::Arc;
+
+use crate::scene::object::{Intersect, Hit, Interval};
+use crate::scene::primitives::{Ray, Transform, Bounds};
+use crate::scene::medium::{Medium, MediumEvent, HenyeyGreenstein};
+use crate::scene::sampler::Sampler;
+use crate::scene::LightRay;
+
+// phase of an instance without a medium, which is never tracked through
+const NO_PHASE: HenyeyGreenstein = HenyeyGreenstein { g: 0.0 };
+
+// Shared object placed in the scene by a transform from its own space.
+// Any number of instances can point at the same object, a mesh is only
+// stored once however often it appears. A medium inside the object is
+// tracked along the ray taken into object space, so a grid volume can be
+// rotated or stretched like any other shape. Its densities stay per unit
+// of world distance.
+pub struct Instance {
+    object: Arc<dyn Intersect>,
+    // object space to world space
+    transform: Transform,
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Intersect>, transform: Transform) -> Instance {
+        Instance {
+            object: object,
+            transform: transform,
+        }
+    }
+
+    // The ray in object space. The direction is left unnormalized, unlike
+    // the Ray constructors would, so distances along it and so the ray's
+    // interval stay the same.
+    fn object_ray(&self, ray: &LightRay) -> LightRay {
+        LightRay::new(self.object_space(&ray.ray))
+    }
+
+    fn object_space(&self, ray: &Ray) -> Ray {
+        let inverse = self.transform.inverse();
+        Ray {
+            position: inverse.point(&ray.position),
+            direction: inverse.vector(&ray.direction),
+            t_min: ray.t_min,
+            t_max: ray.t_max,
+        }
+    }
+
+    fn world_hit(&self, hit: Hit) -> Hit {
+        Hit {
+            point: self.transform.point(&hit.point),
+            geometric_normal: self.transform.normal(&hit.geometric_normal),
+            shading_normal: self.transform.normal(&hit.shading_normal),
+            dpdu: self.transform.vector(&hit.dpdu),
+            dpdv: self.transform.vector(&hit.dpdv),
+            ..hit
+        }
+    }
+}
+
+impl Intersect for Instance {
+    fn intersect(&self, ray: &LightRay) -> Option<Hit> {
+        self.object.intersect(&self.object_ray(ray)).map(|hit| self.world_hit(hit))
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.object.bounds().transform(&self.transform)
+    }
+
+    fn occludes(&self, ray: &LightRay) -> bool {
+        self.object.occludes(&self.object_ray(ray))
+    }
+
+    fn intervals(&self, ray: &LightRay) -> Option<Vec<Interval>> {
+        self.object.intervals(&self.object_ray(ray)).map(|intervals| {
+            intervals.into_iter().map(|interval| Interval {
+                enter: self.world_hit(interval.enter),
+                exit: self.world_hit(interval.exit),
+            }).collect()
+        })
+    }
+
+    fn medium(&self) -> Option<&dyn Medium> {
+        self.object.medium().map(|_| self as &dyn Medium)
+    }
+}
+
+// Distances along the object space ray are the same as along the world
+// ray, so events and transmittance carry straight over
+impl Medium for Instance {
+    fn sample_distance(&self, ray: &Ray, t_max: f64, sampler: &mut Sampler) -> MediumEvent {
+        match self.object.medium() {
+            Some(medium) => medium.sample_distance(&self.object_space(ray), t_max, sampler),
+            None => MediumEvent::Pass { weight: 1.0 },
+        }
+    }
+
+    fn transmittance(&self, ray: &Ray, t_max: f64, sampler: &mut Sampler) -> f64 {
+        match self.object.medium() {
+            Some(medium) => medium.transmittance(&self.object_space(ray), t_max, sampler),
+            None => 1.0,
+        }
+    }
+
+    fn phase(&self) -> &HenyeyGreenstein {
+        self.object.medium().map_or(&NO_PHASE, |medium| medium.phase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::object::Sphere;
+    use crate::scene::primitives::Point;
+
+    // unit sphere stretched to twice as long along x, centered at (5, 0, 0)
+    fn ellipsoid() -> Instance {
+        Instance::new(
+            Arc::new(Sphere::new(Point::from((0.0, 0.0, 0.0)), 0, 1.0)),
+            Transform::scaling(2.0, 1.0, 1.0).then(&Transform::translation(&Point::from((5.0, 0.0, 0.0)))))
+    }
+
+    fn assert_near(point: &Point, expected: &Point) {
+        assert!(point.add(&expected.mult(-1.0)).abs() < 1e-9, "{:?}, expected {:?}", point, expected);
+    }
+
+    #[test]
+    fn hits_scaled_sphere() {
+        let instance = ellipsoid();
+
+        // along the stretched axis the surface is 2 from the center
+        let ray = LightRay::new(Ray::new(Point::from((0.0, 0.0, 0.0)), Point::from((1.0, 0.0, 0.0))));
+        let hit = instance.intersect(&ray).unwrap();
+        assert!((hit.t - 3.0).abs() < 1e-9, "t {}", hit.t);
+        assert_near(&hit.point, &Point::from((3.0, 0.0, 0.0)));
+        assert_near(&hit.geometric_normal, &Point::from((-1.0, 0.0, 0.0)));
+
+        let ray = LightRay::new(Ray::new(Point::from((5.0, 0.0, 5.0)), Point::from((0.0, 0.0, -1.0))));
+        let hit = instance.intersect(&ray).unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-9, "t {}", hit.t);
+        assert_near(&hit.geometric_normal, &Point::from((0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn scaled_normals_are_unit_and_perpendicular() {
+        let instance = ellipsoid();
+
+        // off axis the normal of x^2 / 4 + y^2 + z^2 = 1 is (x / 4, y, z)
+        let ray = LightRay::new(Ray::new(Point::from((6.0, 0.0, 5.0)), Point::from((0.0, 0.0, -1.0))));
+        let hit = instance.intersect(&ray).unwrap();
+        let z = (1.0 - 0.25f64).sqrt();
+        assert!((hit.t - (5.0 - z)).abs() < 1e-9, "t {}", hit.t);
+        assert!((hit.geometric_normal.abs() - 1.0).abs() < 1e-12);
+        assert!((hit.shading_normal.abs() - 1.0).abs() < 1e-12);
+        assert_near(&hit.geometric_normal, &Point::from((0.25, 0.0, z)).normalize());
+        assert!(hit.geometric_normal.dot(&hit.dpdu).abs() < 1e-9);
+        assert!(hit.geometric_normal.dot(&hit.dpdv).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,115 @@
+use std::f64::consts::PI;
+
+use crate::scene::object::{Hit, Interval};
+use crate::scene::primitives::{Point, Frame, Bounds};
+use crate::scene::bsdf::MaterialId;
+use crate::scene::LightRay;
+
+// Hit worked out in the local space of a shape built around the z axis,
+// for shapes placed by a position and a frame
+pub struct LocalHit {
+    pub t: f64,
+    pub point: Point,
+    pub normal: Point,
+    pub uv: (f64, f64),
+    pub dpdu: Point,
+    pub dpdv: Point,
+}
+
+impl LocalHit {
+    // Hit on a disk cap in the plane z = height, facing up or down the
+    // axis, with uvs like a Disk of the given radius
+    pub fn cap(t: f64, local: &Point, height: f64, up: bool, radius: f64) -> LocalHit {
+        let distance = (local.x * local.x + local.y * local.y).sqrt();
+        let dpdv = if distance > 0.0 {
+            Point::from((local.x * radius / distance, local.y * radius / distance, 0.0))
+        }
+        else {
+            Point::from((0.0, 0.0, 0.0))
+        };
+
+        LocalHit {
+            t: t,
+            point: Point::from((local.x, local.y, height)),
+            normal: Point::from((0.0, 0.0, if up { 1.0 } else { -1.0 })),
+            uv: (azimuth(local) / (2.0 * PI), distance / radius),
+            dpdu: Point::from((-2.0 * PI * local.y, 2.0 * PI * local.x, 0.0)),
+            dpdv: dpdv,
+        }
+    }
+
+    pub fn to_world(self, ray: &LightRay, position: &Point, frame: &Frame, material: MaterialId) -> Hit {
+        Hit::new(
+            self.t,
+            position.add(&frame.to_world(&self.point)),
+            frame.to_world(&self.normal),
+            ray.dir(),
+            self.uv,
+            frame.to_world(&self.dpdu),
+            frame.to_world(&self.dpdv),
+            Some(material))
+    }
+}
+
+// world space box around a box given in the local space of a shape
+pub fn local_bounds(min: &Point, max: &Point, position: &Point, frame: &Frame) -> Bounds {
+    let corners = (0..8).map(|corner| position.add(&frame.to_world(&Point::from((
+        if corner & 1 == 0 { min.x } else { max.x },
+        if corner & 2 == 0 { min.y } else { max.y },
+        if corner & 4 == 0 { min.z } else { max.z }))))).collect::<Vec<Point>>();
+    Bounds::from_points(corners.iter())
+}
+
+// ray origin and direction in the local space of a shape
+pub fn to_local(ray: &LightRay, position: &Point, frame: &Frame) -> (Point, Point) {
+    (frame.to_local(&ray.pos().add(&position.mult(-1.0))), frame.to_local(ray.dir()))
+}
+
+// nearest of the hits found along the ray's line that is inside its interval
+pub fn closest(ray: &LightRay, hits: Vec<LocalHit>) -> Option<LocalHit> {
+    hits.into_iter()
+        .filter(|hit| ray.ray.contains(hit.t))
+        .fold(None, |closest: Option<LocalHit>, hit| match closest {
+            Some(closest) if closest.t <= hit.t => Some(closest),
+            _ => Some(hit),
+        })
+}
+
+// Every hit along the line of a closed shape in order, paired up into the
+// spans inside it. A ray only grazing the shape gives an empty span.
+pub fn intervals(ray: &LightRay, mut hits: Vec<LocalHit>, position: &Point, frame: &Frame, material: MaterialId) -> Vec<Interval> {
+    hits.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap_or(std::cmp::Ordering::Equal));
+    let mut hits = hits.into_iter().map(|hit| hit.to_world(ray, position, frame, material));
+
+    let mut intervals = Vec::new();
+    while let (Some(enter), Some(exit)) = (hits.next(), hits.next()) {
+        intervals.push(Interval {
+            enter: enter,
+            exit: exit,
+        });
+    }
+    intervals
+}
+
+// angle around the z axis in [0, 2 pi)
+pub fn azimuth(local: &Point) -> f64 {
+    let phi = local.y.atan2(local.x);
+    if phi < 0.0 { phi + 2.0 * PI } else { phi }
+}
+
+// Hit on the disk cap in the plane z = height, facing up or down the axis,
+// None when the line misses it
+pub fn intersect_cap(origin: &Point, direction: &Point, height: f64, up: bool, radius: f64) -> Option<LocalHit> {
+    if direction.z == 0.0 {
+        return None;
+    }
+
+    let t = (height - origin.z) / direction.z;
+    let local = origin.add(&direction.mult(t));
+    if local.x * local.x + local.y * local.y <= radius * radius {
+        Some(LocalHit::cap(t, &local, height, up, radius))
+    }
+    else {
+        None
+    }
+}
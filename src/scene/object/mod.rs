@@ -1,10 +1,82 @@
+mod hit;
 mod sphere;
+mod plane;
+mod disk;
+mod cuboid;
+mod cylinder;
+mod cone;
+mod torus;
+mod csg;
+mod bvh;
+mod instance;
+mod local_hit;
+mod polynomial;
+mod distance_field;
+mod sdf;
+mod triangle;
+mod triangle_mesh;
+mod subdivision;
+mod grid_volume;
+mod heightfield;
+mod curves;
+pub use hit::Hit;
 pub use sphere::Sphere;
+pub use plane::Plane;
+pub use disk::Disk;
+pub use cuboid::Cuboid;
+pub use cylinder::Cylinder;
+pub use cone::Cone;
+pub use torus::Torus;
+pub use csg::{Csg, Interval};
+pub use instance::Instance;
+pub use bvh::Bvh;
+pub use distance_field::{DistanceField, SphereField, BoxField, TorusField, CapsuleField, Mandelbulb, Translate, SmoothUnion, Repeat, Twist, Displace};
+pub use sdf::Sdf;
+pub use triangle_mesh::TriangleMesh;
+pub use subdivision::{SubdivisionSurface, ControlMesh};
+pub use grid_volume::{GridVolume, DensityGrid};
+pub use heightfield::{Heightfield, ElevationGrid};
+pub use curves::{Curves, CurveKind, Strand};
 
-use crate::scene::primitives::{Ray, Color};
+use crate::scene::medium::Medium;
+use crate::scene::primitives::Bounds;
 use crate::scene::LightRay;
+use crate::scene::packet::{RayPacket, Mask, PACKET_SIZE};
 
 pub trait Intersect {
-    // returns normal at intersection, distance, and color picked up
-    fn intersect(&self, ray: &LightRay) -> Option<(Ray, f64, Color)>;
+    // closest hit strictly inside the ray's [t_min, t_max]
+    fn intersect(&self, ray: &LightRay) -> Option<Hit>;
+
+    // box around the whole object, infinite for ones without bounds
+    fn bounds(&self) -> Bounds;
+
+    // whether anything is hit inside the ray's interval, for shadow rays.
+    // Doesn't need the closest hit, so can stop at the first one found.
+    fn occludes(&self, ray: &LightRay) -> bool {
+        self.intersect(ray).is_some()
+    }
+
+    // closest hits for the rays of a packet in the given lanes, None in the
+    // others. Shapes that can test a whole packet at once override this,
+    // the rest take its rays one at a time.
+    fn intersect_packet(&self, _packet: &RayPacket, rays: &[LightRay], lanes: Mask) -> [Option<Hit>; PACKET_SIZE] {
+        let mut hits: [Option<Hit>; PACKET_SIZE] = Default::default();
+        for lane in lanes.lanes() {
+            hits[lane] = self.intersect(&rays[lane]);
+        }
+        hits
+    }
+
+    // every span of the ray's whole line inside the object, in order and
+    // regardless of the ray's interval. None for objects that do not
+    // enclose a volume, which cannot take part in CSG.
+    fn intervals(&self, _ray: &LightRay) -> Option<Vec<Interval>> {
+        None
+    }
+
+    // closed objects can be filled with a medium, in which case their
+    // surface is only a boundary rays pass through
+    fn medium(&self) -> Option<&dyn Medium> {
+        None
+    }
 }
@@ -0,0 +1,69 @@
+use crate::scene::object::{Intersect, Hit};
+use crate::scene::primitives::{Point, Frame, Bounds};
+use crate::scene::bsdf::MaterialId;
+use crate::scene::LightRay;
+
+// Infinite plane through a point. UV coordinates are distances along the
+// plane in world units, so textures repeat every unit.
+pub struct Plane {
+    position: Point,
+    frame: Frame,
+    material: MaterialId,
+}
+
+impl Plane {
+    pub fn new(pos: Point, normal: Point, material: MaterialId) -> Plane {
+        Plane {
+            position: pos,
+            frame: Frame::from_normal(&normal.normalize()),
+            material: material,
+        }
+    }
+}
+
+// Distance along the ray to the plane through position with the given
+// normal, None when the ray is parallel to it or the hit is outside the
+// ray's interval
+pub fn intersect_plane(ray: &LightRay, position: &Point, normal: &Point) -> Option<f64> {
+    let denominator = ray.dir().dot(normal);
+    if denominator == 0.0 {
+        return None;
+    }
+
+    let t = position.add(&ray.pos().mult(-1.0)).dot(normal) / denominator;
+    if ray.ray.contains(t) {
+        Some(t)
+    }
+    else {
+        None
+    }
+}
+
+// hit point relative to the plane position, projected back onto the plane
+// to stay within the error bound rays are spawned with
+pub fn plane_offset(ray: &LightRay, t: f64, position: &Point, normal: &Point) -> Point {
+    let offset = ray.ray.at(t).add(&position.mult(-1.0));
+    offset.add(&normal.mult(-offset.dot(normal)))
+}
+
+impl Intersect for Plane {
+    fn intersect(&self, ray: &LightRay) -> Option<Hit> {
+        let normal = &self.frame.normal;
+        let t = intersect_plane(ray, &self.position, normal)?;
+        let offset = plane_offset(ray, t, &self.position, normal);
+
+        Some(Hit::new(
+            t,
+            self.position.add(&offset),
+            normal.clone(),
+            ray.dir(),
+            (offset.dot(&self.frame.tangent), offset.dot(&self.frame.bitangent)),
+            self.frame.tangent.clone(),
+            self.frame.bitangent.clone(),
+            Some(self.material)))
+    }
+
+    fn bounds(&self) -> Bounds {
+        Bounds::infinite()
+    }
+}
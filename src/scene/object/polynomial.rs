@@ -0,0 +1,191 @@
+// Real roots of the polynomials ray tests end up with.
+
+// Roots of a t^2 + 2 b t + c without cancellation, in no particular order.
+// A zero a leaves the single root of the linear equation and an infinite
+// one, which no ray interval contains. None when there are no real roots,
+// or a and b are both zero and there is no equation in t left.
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<[f64; 2]> {
+    if a == 0.0 && b == 0.0 {
+        return None;
+    }
+
+    let discriminant = b * b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let q = -(b + b.signum() * discriminant.sqrt());
+    if q == 0.0 {
+        Some([0.0, 0.0])
+    }
+    else {
+        Some([q / a, c / q])
+    }
+}
+
+// Largest real root of t^3 + a t^2 + b t + c, which always has one
+fn largest_cubic_root(a: f64, b: f64, c: f64) -> f64 {
+    // depressed to s^3 + p s + q with t = s - a / 3
+    let p = b - a * a / 3.0;
+    let q = 2.0 * a * a * a / 27.0 - a * b / 3.0 + c;
+    let discriminant = q * q / 4.0 + p * p * p / 27.0;
+
+    let s = if discriminant > 0.0 {
+        // one real root, Cardano
+        let root = discriminant.sqrt();
+        (-q / 2.0 + root).cbrt() + (-q / 2.0 - root).cbrt()
+    }
+    else {
+        // three real roots, the trigonometric form gives the largest at k = 0
+        let radius = (-p / 3.0).sqrt();
+        let cos = if radius > 0.0 { (-q / (2.0 * radius * radius * radius)).max(-1.0).min(1.0) } else { 0.0 };
+        2.0 * radius * (cos.acos() / 3.0).cos()
+    };
+
+    polish(&[1.0, a, b, c], s - a / 3.0)
+}
+
+// Real roots of c[0] t^4 + c[1] t^3 + c[2] t^2 + c[3] t + c[4], by
+// Ferrari's method, each refined with Newton steps on the quartic itself
+// since the closed form loses precision when the roots are far apart.
+pub fn solve_quartic(coefficients: &[f64; 5]) -> Vec<f64> {
+    if coefficients[0] == 0.0 {
+        return Vec::new();
+    }
+
+    let a = coefficients[1] / coefficients[0];
+    let b = coefficients[2] / coefficients[0];
+    let c = coefficients[3] / coefficients[0];
+    let d = coefficients[4] / coefficients[0];
+
+    // depressed to y^4 + p y^2 + q y + r with t = y - a / 4
+    let a2 = a * a;
+    let p = b - 3.0 * a2 / 8.0;
+    let q = c - a * b / 2.0 + a2 * a / 8.0;
+    let r = d - a * c / 4.0 + a2 * b / 16.0 - 3.0 * a2 * a2 / 256.0;
+
+    let mut roots = Vec::with_capacity(4);
+    if q.abs() < 1e-12 {
+        // biquadratic, a quadratic in y^2
+        for z in solve_quadratic(1.0, p / 2.0, r).iter().flatten() {
+            if *z >= 0.0 {
+                roots.push(z.sqrt());
+                roots.push(-z.sqrt());
+            }
+        }
+    }
+    else {
+        // with m a root of the resolvent cubic the quartic is a difference
+        // of squares, (y^2 + p/2 + m)^2 = (s y - q / 2s)^2 with s^2 = 2m
+        let m = largest_cubic_root(p, p * p / 4.0 - r, -q * q / 8.0);
+        if m <= 0.0 {
+            return Vec::new();
+        }
+        let s = (2.0 * m).sqrt();
+        for (sign, offset) in [(-1.0, q / (2.0 * s)), (1.0, -q / (2.0 * s))].iter() {
+            for y in solve_quadratic(1.0, sign * s / 2.0, p / 2.0 + m + offset).iter().flatten() {
+                roots.push(*y);
+            }
+        }
+    }
+
+    roots.into_iter()
+        .map(|y| polish(coefficients, y - a / 4.0))
+        .filter(|t| t.is_finite())
+        .collect()
+}
+
+// a few Newton steps on the polynomial with the given coefficients,
+// highest power first
+fn polish(coefficients: &[f64], mut t: f64) -> f64 {
+    for _step in 0..3 {
+        let (value, derivative) = coefficients.iter().fold((0.0, 0.0), |(value, derivative), coefficient| {
+            (value * t + coefficient, derivative * t + value)
+        });
+        if derivative == 0.0 {
+            break;
+        }
+        t -= value / derivative;
+    }
+    t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roots(mut roots: Vec<f64>, expected: &[f64], tolerance: f64) {
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(roots.len(), expected.len(), "roots {:?}, expected {:?}", roots, expected);
+        for (root, expected) in roots.iter().zip(expected.iter()) {
+            assert!((root - expected).abs() < tolerance, "roots {:?}, expected {:?}", roots, expected);
+        }
+    }
+
+    #[test]
+    fn quadratic_two_roots() {
+        // t^2 - 5 t + 6
+        let roots = solve_quadratic(1.0, -2.5, 6.0).unwrap();
+        assert_roots(roots.to_vec(), &[2.0, 3.0], 1e-12);
+    }
+
+    #[test]
+    fn quadratic_double_root() {
+        // t^2 - 2 t + 1
+        let roots = solve_quadratic(1.0, -1.0, 1.0).unwrap();
+        assert_roots(roots.to_vec(), &[1.0, 1.0], 1e-12);
+    }
+
+    #[test]
+    fn quadratic_no_real_roots() {
+        assert!(solve_quadratic(1.0, 0.0, 1.0).is_none());
+    }
+
+    #[test]
+    fn quadratic_linear() {
+        // 2 t - 4, the other root is infinite
+        let roots = solve_quadratic(0.0, 1.0, -4.0).unwrap();
+        let finite = roots.iter().cloned().filter(|t| t.is_finite()).collect::<Vec<f64>>();
+        assert_roots(finite, &[2.0], 1e-12);
+    }
+
+    #[test]
+    fn quadratic_constant() {
+        assert!(solve_quadratic(0.0, 0.0, 1.0).is_none());
+        assert!(solve_quadratic(0.0, 0.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn quartic_four_roots() {
+        // (t - 1)(t - 2)(t - 3)(t - 4)
+        assert_roots(solve_quartic(&[1.0, -10.0, 35.0, -50.0, 24.0]), &[1.0, 2.0, 3.0, 4.0], 1e-9);
+    }
+
+    #[test]
+    fn quartic_double_roots() {
+        // (t - 1)^2 (t - 3)^2
+        assert_roots(solve_quartic(&[1.0, -8.0, 22.0, -24.0, 9.0]), &[1.0, 1.0, 3.0, 3.0], 1e-6);
+    }
+
+    #[test]
+    fn quartic_no_real_roots() {
+        // (t^2 + 1)(t^2 + 4)
+        assert!(solve_quartic(&[1.0, 0.0, 5.0, 0.0, 4.0]).is_empty());
+    }
+
+    #[test]
+    fn quartic_zero_leading_coefficient() {
+        assert!(solve_quartic(&[0.0, 1.0, 2.0, 3.0, 4.0]).is_empty());
+    }
+
+    #[test]
+    fn quartic_grazing_torus() {
+        // ray along x through the top of a torus with radii 2 and 0.5,
+        // touching the tube where it passes over the ring, t^4 - 8 t^2 + 16
+        // as Torus sets it up from the ray's closest approach to the center
+        let (major2, minor2, z) = (4.0, 0.25, 0.5);
+        let e = z * z - major2 - minor2;
+        let coefficients = [1.0, 0.0, 2.0 * e, 0.0, e * e - 4.0 * major2 * (minor2 - z * z)];
+        assert_roots(solve_quartic(&coefficients), &[-2.0, -2.0, 2.0, 2.0], 1e-6);
+    }
+}
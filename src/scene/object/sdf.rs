@@ -0,0 +1,112 @@
+use crate::scene::object::{Intersect, Hit, DistanceField};
+use crate::scene::object::cuboid::intersect_slabs;
+use crate::scene::primitives::{Point, Frame, Bounds};
+use crate::scene::bsdf::MaterialId;
+use crate::scene::LightRay;
+
+// steps before a ray is taken to have missed
+const MAX_STEPS: u32 = 512;
+// closer than this to the surface counts as on it
+const HIT_DISTANCE: f64 = 1e-4;
+// spacing of the samples the normal is estimated from
+const NORMAL_OFFSET: f64 = 1e-4;
+const BISECTIONS: u32 = 32;
+
+// Implicit surface where a distance field is zero, found by sphere tracing:
+// stepping along the ray by the distance to the nearest surface, which
+// can't be passed on the way. Only traced inside the given bounds, which
+// have to hold the whole surface. Has no uv parameterization.
+pub struct Sdf {
+    field: Box<dyn DistanceField>,
+    bounds: Bounds,
+    material: MaterialId,
+}
+
+impl Sdf {
+    pub fn new(field: Box<dyn DistanceField>, bounds: Bounds, material: MaterialId) -> Sdf {
+        Sdf {
+            field: field,
+            bounds: bounds,
+            material: material,
+        }
+    }
+
+    // Distance along the ray to the surface. A ray starting on the surface,
+    // as every ray leaving a hit does, has to get clear of it before hits
+    // count, so it can't find the surface it left again.
+    fn trace(&self, ray: &LightRay) -> Option<f64> {
+        let (t_near, t_far) = intersect_slabs(ray.pos(), ray.dir(), &self.bounds.min, &self.bounds.max)?;
+        let t_end = t_far.0.min(ray.ray.t_max);
+        let mut t = t_near.0.max(ray.ray.t_min);
+        // directions of rays into instances aren't normalized
+        let speed = ray.dir().abs();
+
+        // which side of the surface the ray is on once clear of it
+        let mut side: Option<f64> = if t_near.0 > ray.ray.t_min { Some(1.0) } else { None };
+        let mut previous = (t, std::f64::INFINITY);
+        for _ in 0..MAX_STEPS {
+            if t > t_end {
+                return None;
+            }
+
+            let distance = self.field.distance(&ray.ray.at(t));
+            match side {
+                Some(side) if distance * side < 0.0 => {
+                    return Some(self.bisect(ray, previous.0, t, side));
+                },
+                Some(_) if distance.abs() < HIT_DISTANCE && ray.ray.contains(t) => {
+                    return Some(t);
+                },
+                None if distance.abs() >= HIT_DISTANCE => {
+                    side = Some(distance.signum());
+                },
+                _ => (),
+            }
+
+            previous = (t, distance);
+            t += distance.abs().max(HIT_DISTANCE) / speed;
+        }
+
+        None
+    }
+
+    // Crossing of the surface between a point on the given side of it and
+    // one past it, where a step overshot
+    fn bisect(&self, ray: &LightRay, mut before: f64, mut after: f64, side: f64) -> f64 {
+        for _ in 0..BISECTIONS {
+            let middle = 0.5 * (before + after);
+            if self.field.distance(&ray.ray.at(middle)) * side > 0.0 {
+                before = middle;
+            }
+            else {
+                after = middle;
+            }
+        }
+        after
+    }
+
+    // gradient of the field by central differences, pointing out of it
+    fn normal(&self, point: &Point) -> Point {
+        let difference = |offset: Point| {
+            self.field.distance(&point.add(&offset)) - self.field.distance(&point.add(&offset.mult(-1.0)))
+        };
+        Point::from((
+            difference(Point::from((NORMAL_OFFSET, 0.0, 0.0))),
+            difference(Point::from((0.0, NORMAL_OFFSET, 0.0))),
+            difference(Point::from((0.0, 0.0, NORMAL_OFFSET))))).normalize()
+    }
+}
+
+impl Intersect for Sdf {
+    fn intersect(&self, ray: &LightRay) -> Option<Hit> {
+        let t = self.trace(ray)?;
+        let point = ray.ray.at(t);
+        let normal = self.normal(&point);
+        let frame = Frame::from_normal(&normal);
+        Some(Hit::new(t, point, normal, ray.dir(), (0.0, 0.0), frame.tangent, frame.bitangent, Some(self.material)))
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.bounds.clone()
+    }
+}
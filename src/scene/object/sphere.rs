@@ -1,41 +1,152 @@
-use crate::scene::object::{Intersect};
-use crate::scene::primitives::{Point, Ray, Color};
+use std::f64::consts::PI;
+
+use crate::scene::object::{Intersect, Hit, Interval};
+use crate::scene::primitives::{Point, Bounds};
+use crate::scene::medium::Medium;
+use crate::scene::bsdf::MaterialId;
 use crate::scene::LightRay;
+use crate::scene::packet::{self, RayPacket, Mask, F64x4, PACKET_SIZE};
 
 pub struct Sphere {
     position: Point,
-    color: Color,
+    material: Option<MaterialId>,
     radius: f64,
+    interior: Option<Box<dyn Medium>>,
 }
 
 impl Sphere {
-    pub fn new(pos: Point, color: Color, radius: f64) -> Sphere {
+    pub fn new(pos: Point, material: MaterialId, radius: f64) -> Sphere {
+        Sphere {
+            position: pos,
+            material: Some(material),
+            radius: radius,
+            interior: None,
+        }
+    }
+
+    // invisible sphere whose volume is filled with a participating medium
+    pub fn with_medium(pos: Point, radius: f64, medium: Box<dyn Medium>) -> Sphere {
         Sphere {
             position: pos,
-            color: color,
+            material: None,
             radius: radius,
+            interior: Some(medium),
         }
     }
+
+    // both roots along the ray's line, nearest first
+    fn roots(&self, ray: &LightRay) -> Option<(f64, f64)> {
+        let offset = ray.pos().add(&self.position.mult(-1.0));
+        let a = ray.dir().dot(&ray.dir());
+        let b = ray.dir().dot(&offset);
+        let c = offset.dot(&offset) - self.radius.powi(2);
+
+        let discriminant = b * b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        // the roots without cancellation, so their signs are right even
+        // for rays starting just off the surface
+        let q = -(b + b.signum() * discriminant.sqrt());
+        let (t0, t1) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+        Some(if t0 < t1 { (t0, t1) } else { (t1, t0) })
+    }
+
+    fn hit(&self, ray: &LightRay, t: f64) -> Hit {
+        // project back onto the sphere, keeping the point within the error
+        // bound rays are spawned with
+        let local = ray.ray.at(t).add(&self.position.mult(-1.0));
+        let normal = local.normalize();
+        let local = normal.mult(self.radius);
+
+        // u goes around the z axis, v from the top pole to the bottom
+        let phi = local.y.atan2(local.x);
+        let phi = if phi < 0.0 { phi + 2.0 * PI } else { phi };
+        let theta = normal.z.max(-1.0).min(1.0).acos();
+        let dpdu = Point::from((-2.0 * PI * local.y, 2.0 * PI * local.x, 0.0));
+        let dpdv = Point::from((
+            PI * local.z * phi.cos(),
+            PI * local.z * phi.sin(),
+            -PI * self.radius * theta.sin()));
+
+        Hit::new(
+            t,
+            self.position.add(&local),
+            normal,
+            ray.dir(),
+            (phi / (2.0 * PI), theta / PI),
+            dpdu,
+            dpdv,
+            self.material)
+    }
 }
 
 impl Intersect for Sphere {
-    fn intersect(&self, ray: &LightRay) -> Option<(Ray, f64, Color)> {
-        let t = (ray.dir().dot(&self.position) - ray.dir().dot(&ray.pos())) / ray.dir().dot(&ray.dir());
-        let point_min_dist = ray.pos().add(&ray.dir().mult(t));
+    fn intersect(&self, ray: &LightRay) -> Option<Hit> {
+        let (t0, t1) = self.roots(ray)?;
 
-        let dist_vec = point_min_dist.add(&self.position.mult(-1.0));
-        let distance = dist_vec.abs();
+        // take the near hit, or the far one when starting inside the sphere
+        let t = if ray.ray.contains(t0) {
+            t0
+        }
+        else if ray.ray.contains(t1) {
+            t1
+        }
+        else {
+            return None;
+        };
 
-        if distance <= self.radius && (t > 0.0) {
-            let t = t - (self.radius.powi(2) - distance.powi(2)).sqrt();
-            let intersection = ray.pos().add(&ray.dir().mult(t));
-            let normal_dir = intersection.add(&self.position.mult(-1.0)).normalize();
-            let normal = Ray::new(intersection, normal_dir);
+        Some(self.hit(ray, t))
+    }
+
+    // the same sums as roots, on every lane at once
+    fn intersect_packet(&self, packet: &RayPacket, rays: &[LightRay], lanes: Mask) -> [Option<Hit>; PACKET_SIZE] {
+        let mut hits: [Option<Hit>; PACKET_SIZE] = Default::default();
+        let offset = packet.offset(&self.position);
+        let a = packet::dot(&packet.direction, &packet.direction);
+        let b = packet::dot(&packet.direction, &offset);
+        let c = packet::dot(&offset, &offset).sub(&F64x4::splat(self.radius.powi(2)));
 
-            Some((normal, t, self.color.clone()))
+        let discriminant = b.mult(&b).sub(&a.mult(&c));
+        let lanes = lanes.and(&discriminant.lt(&F64x4::splat(0.0)).invert());
+        if !lanes.any() {
+            return hits;
         }
-        else {
-            None
+
+        let q = F64x4::splat(0.0).sub(&b.add(&discriminant.sqrt().copysign(&b)));
+        let (near, far) = (q.div(&a).to_array(), c.div(&q).to_array());
+        let q = q.to_array();
+        for lane in lanes.lanes() {
+            let (t0, t1) = if q[lane] == 0.0 { (0.0, 0.0) } else { (near[lane], far[lane]) };
+            let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+            let ray = &rays[lane];
+            if ray.ray.contains(t0) {
+                hits[lane] = Some(self.hit(ray, t0));
+            }
+            else if ray.ray.contains(t1) {
+                hits[lane] = Some(self.hit(ray, t1));
+            }
         }
+        hits
+    }
+
+    fn bounds(&self) -> Bounds {
+        let extent = Point::from((self.radius, self.radius, self.radius));
+        Bounds::new(self.position.add(&extent.mult(-1.0)), self.position.add(&extent))
+    }
+
+    fn intervals(&self, ray: &LightRay) -> Option<Vec<Interval>> {
+        Some(match self.roots(ray) {
+            Some((t0, t1)) => vec![Interval {
+                enter: self.hit(ray, t0),
+                exit: self.hit(ray, t1),
+            }],
+            None => Vec::new(),
+        })
+    }
+
+    fn medium(&self) -> Option<&dyn Medium> {
+        self.interior.as_deref()
     }
 }
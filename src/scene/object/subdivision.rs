@@ -0,0 +1,533 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::scene::object::{Intersect, Hit, TriangleMesh};
+use crate::scene::object::triangle::triangle_normal;
+use crate::scene::primitives::{Point, Bounds};
+use crate::scene::bsdf::MaterialId;
+use crate::scene::LightRay;
+use crate::scene::import::{load_obj_polygons, load_ply_polygons};
+use crate::scene::load_err::LoadErr;
+use crate::scene::packet::{RayPacket, Mask, PACKET_SIZE};
+
+// Polygon mesh to be subdivided, with some of its edges marked as creases.
+// A crease stays sharp for as many levels as its sharpness and is then
+// smoothed like any other edge, infinity keeps it sharp for good, and
+// fractional sharpness blends between the two. Boundary edges are always
+// sharp.
+#[derive(Clone)]
+pub struct ControlMesh {
+    positions: Vec<Point>,
+    faces: Vec<Vec<usize>>,
+    // sharpness by edge, with the lower vertex index first
+    creases: HashMap<(usize, usize), f64>,
+}
+
+struct Edge {
+    vertices: [usize; 2],
+    faces: Vec<usize>,
+    sharpness: f64,
+}
+
+impl Edge {
+    fn is_sharp(&self) -> bool {
+        self.faces.len() != 2 || self.sharpness > 0.0
+    }
+
+    // how far to go towards the sharp rule, 1 for boundaries
+    fn sharp_weight(&self) -> f64 {
+        if self.faces.len() != 2 { 1.0 } else { self.sharpness.min(1.0) }
+    }
+
+    fn other(&self, vertex: usize) -> usize {
+        if self.vertices[0] == vertex { self.vertices[1] } else { self.vertices[0] }
+    }
+}
+
+// Edges and what touches each vertex, worked out from the faces
+struct Topology {
+    edges: Vec<Edge>,
+    // index into edges by vertex pair, the lower index first
+    lookup: HashMap<(usize, usize), usize>,
+    vertex_edges: Vec<Vec<usize>>,
+    vertex_faces: Vec<Vec<usize>>,
+}
+
+fn key(a: usize, b: usize) -> (usize, usize) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+// representative of the group a corner is in, flattening the way there
+fn find(groups: &mut Vec<usize>, corner: usize) -> usize {
+    let mut root = corner;
+    while groups[root] != root {
+        groups[root] = groups[groups[root]];
+        root = groups[root];
+    }
+    root
+}
+
+fn lerp(a: &Point, b: &Point, weight: f64) -> Point {
+    a.mult(1.0 - weight).add(&b.mult(weight))
+}
+
+fn average<'a, I: IntoIterator<Item = &'a Point>>(points: I) -> Point {
+    let mut count = 0;
+    let sum = points.into_iter().fold(Point::from((0.0, 0.0, 0.0)), |sum, point| {
+        count += 1;
+        sum.add(point)
+    });
+    sum.mult(1.0 / count.max(1) as f64)
+}
+
+impl ControlMesh {
+    // Fails when a face has fewer than 3 corners, repeats a corner or an
+    // index is out of range, and when the mesh isn't a consistently wound
+    // manifold, each edge shared by at most two faces going round it in
+    // opposite directions
+    pub fn new(positions: Vec<Point>, faces: Vec<Vec<usize>>, creases: Vec<([usize; 2], f64)>) -> Result<ControlMesh, LoadErr> {
+        let count = positions.len();
+        // the face running along each edge in the direction given
+        let mut sides = HashMap::<(usize, usize), usize>::new();
+        for (index, face) in faces.iter().enumerate() {
+            if face.len() < 3 {
+                return Err(LoadErr::from(format!("face {} has {} corners, needs at least 3", index, face.len())));
+            }
+            if let Some(vertex) = face.iter().find(|vertex| **vertex >= count) {
+                return Err(LoadErr::from(format!("face {} uses vertex {}, there are {}", index, vertex, count)));
+            }
+            for (corner, vertex) in face.iter().enumerate() {
+                if face[corner + 1..].contains(vertex) {
+                    return Err(LoadErr::from(format!("face {} uses vertex {} twice", index, vertex)));
+                }
+                let next = face[(corner + 1) % face.len()];
+                if let Some(other) = sides.insert((*vertex, next), index) {
+                    return Err(LoadErr::from(format!(
+                        "faces {} and {} both run from vertex {} to {}, the mesh is non-manifold or inconsistently wound",
+                        other, index, vertex, next)));
+                }
+            }
+        }
+        if let Some(([a, b], _)) = creases.iter().find(|([a, b], _)| *a >= count || *b >= count) {
+            return Err(LoadErr::from(format!("crease {} {} uses a missing vertex, there are {}", a, b, count)));
+        }
+
+        Ok(ControlMesh {
+            positions: positions,
+            faces: faces,
+            creases: creases.into_iter().map(|([a, b], sharpness)| (key(a, b), sharpness)).collect(),
+        })
+    }
+
+    // Control mesh from an OBJ or PLY model, going by its extension. Faces
+    // are kept as the polygons in the file so Catmull-Clark sees the quads
+    // as quads. Only positions and faces are read, and only boundary edges
+    // are sharp.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ControlMesh, LoadErr> {
+        let path = path.as_ref();
+        let extension = path.extension().map(|extension| extension.to_string_lossy().to_lowercase());
+        let (positions, faces) = match extension.as_deref() {
+            Some("obj") => load_obj_polygons(path)?,
+            Some("ply") => load_ply_polygons(path)?,
+            _ => return Err(LoadErr::from(format!("{}: expected an .obj or .ply model", path.display()))),
+        };
+        ControlMesh::new(positions, faces, Vec::new())
+            .map_err(|err| LoadErr::from(format!("{}: {}", path.display(), err)))
+    }
+
+    fn topology(&self) -> Topology {
+        let mut topology = Topology {
+            edges: Vec::new(),
+            lookup: HashMap::new(),
+            vertex_edges: vec![Vec::new(); self.positions.len()],
+            vertex_faces: vec![Vec::new(); self.positions.len()],
+        };
+
+        for (index, face) in self.faces.iter().enumerate() {
+            for (corner, vertex) in face.iter().enumerate() {
+                topology.vertex_faces[*vertex].push(index);
+
+                let next = face[(corner + 1) % face.len()];
+                let edges = &mut topology.edges;
+                let vertex_edges = &mut topology.vertex_edges;
+                let creases = &self.creases;
+                let edge = *topology.lookup.entry(key(*vertex, next)).or_insert_with(|| {
+                    vertex_edges[*vertex].push(edges.len());
+                    vertex_edges[next].push(edges.len());
+                    edges.push(Edge {
+                        vertices: [*vertex, next],
+                        faces: Vec::new(),
+                        sharpness: creases.get(&key(*vertex, next)).cloned().unwrap_or(0.0),
+                    });
+                    edges.len() - 1
+                });
+                topology.edges[edge].faces.push(index);
+            }
+        }
+
+        topology
+    }
+
+    // Refined mesh after the given number of steps
+    pub fn subdivide(&self, levels: u32) -> ControlMesh {
+        let mut mesh = self.clone();
+        for _ in 0..levels {
+            mesh = if mesh.faces.iter().all(|face| face.len() == 3) { mesh.loop_step() } else { mesh.catmull_clark_step() };
+        }
+        mesh
+    }
+
+    // New position of an original vertex. The smooth position comes from
+    // the scheme, one sharp edge leaves the smooth rule in place, two make
+    // it follow the crease and more pin it as a corner.
+    fn vertex_point(&self, topology: &Topology, vertex: usize, smooth: Point) -> Point {
+        let position = &self.positions[vertex];
+        let sharp = topology.vertex_edges[vertex].iter()
+            .map(|edge| &topology.edges[*edge])
+            .filter(|edge| edge.is_sharp())
+            .collect::<Vec<&Edge>>();
+        if sharp.len() < 2 {
+            return smooth;
+        }
+
+        let rule = if sharp.len() == 2 {
+            position.mult(0.75)
+                .add(&self.positions[sharp[0].other(vertex)].mult(0.125))
+                .add(&self.positions[sharp[1].other(vertex)].mult(0.125))
+        }
+        else {
+            position.clone()
+        };
+        let weight = sharp.iter().map(|edge| edge.sharp_weight()).sum::<f64>() / sharp.len() as f64;
+        lerp(&smooth, &rule, weight)
+    }
+
+    // Creases of the edges after a step, each edge split in two at the
+    // given new vertex, one level less sharp. Original vertices keep their
+    // indices.
+    fn child_creases(topology: &Topology, edge_vertex: &dyn Fn(usize) -> usize) -> HashMap<(usize, usize), f64> {
+        let mut creases = HashMap::new();
+        for (index, edge) in topology.edges.iter().enumerate() {
+            if edge.sharpness > 1.0 && edge.faces.len() == 2 {
+                let middle = edge_vertex(index);
+                creases.insert(key(edge.vertices[0], middle), edge.sharpness - 1.0);
+                creases.insert(key(middle, edge.vertices[1]), edge.sharpness - 1.0);
+            }
+        }
+        creases
+    }
+
+    // Catmull and Clark 1978, turning any polygons into quads, with the
+    // crease rules of DeRose et al. 1998. New vertices are the original
+    // ones, then one per edge, then one per face.
+    fn catmull_clark_step(&self) -> ControlMesh {
+        let topology = self.topology();
+        let face_points = self.faces.iter()
+            .map(|face| average(face.iter().map(|vertex| &self.positions[*vertex])))
+            .collect::<Vec<Point>>();
+
+        let edge_points = topology.edges.iter().map(|edge| {
+            let middle = lerp(&self.positions[edge.vertices[0]], &self.positions[edge.vertices[1]], 0.5);
+            if edge.faces.len() != 2 {
+                return middle;
+            }
+            let smooth = lerp(&middle, &lerp(&face_points[edge.faces[0]], &face_points[edge.faces[1]], 0.5), 0.5);
+            lerp(&smooth, &middle, edge.sharp_weight())
+        }).collect::<Vec<Point>>();
+
+        let vertex_points = (0..self.positions.len()).map(|vertex| {
+            let edges = &topology.vertex_edges[vertex];
+            let valence = edges.len() as f64;
+            if edges.is_empty() {
+                return self.positions[vertex].clone();
+            }
+
+            // (Q + 2R + (n - 3) P) / n, Q averaging the face points
+            // around and R the edge midpoints
+            let faces = average(topology.vertex_faces[vertex].iter().map(|face| &face_points[*face]));
+            let midpoints = edges.iter().map(|edge| {
+                let edge = &topology.edges[*edge];
+                lerp(&self.positions[edge.vertices[0]], &self.positions[edge.vertices[1]], 0.5)
+            }).collect::<Vec<Point>>();
+            let smooth = faces
+                .add(&average(midpoints.iter()).mult(2.0))
+                .add(&self.positions[vertex].mult(valence - 3.0))
+                .mult(1.0 / valence);
+            self.vertex_point(&topology, vertex, smooth)
+        }).collect::<Vec<Point>>();
+
+        let edge_base = vertex_points.len();
+        let face_base = edge_base + edge_points.len();
+        let edge_vertex = |edge: usize| edge_base + edge;
+        let faces = self.faces.iter().enumerate().flat_map(|(index, face)| {
+            let count = face.len();
+            let edge = |a: usize, b: usize| edge_vertex(topology.lookup[&key(a, b)]);
+            (0..count).map(|corner| {
+                let vertex = face[corner];
+                let next = face[(corner + 1) % count];
+                let previous = face[(corner + count - 1) % count];
+                vec![vertex, edge(vertex, next), face_base + index, edge(previous, vertex)]
+            }).collect::<Vec<Vec<usize>>>()
+        }).collect();
+
+        ControlMesh {
+            creases: ControlMesh::child_creases(&topology, &edge_vertex),
+            positions: vertex_points.into_iter().chain(edge_points).chain(face_points).collect(),
+            faces: faces,
+        }
+    }
+
+    // Loop 1987 for triangle meshes, splitting each triangle in four, with
+    // the crease rules of Hoppe et al. 1994. New vertices are the original
+    // ones, then one per edge.
+    fn loop_step(&self) -> ControlMesh {
+        let topology = self.topology();
+
+        let edge_points = topology.edges.iter().map(|edge| {
+            let ends = [&self.positions[edge.vertices[0]], &self.positions[edge.vertices[1]]];
+            let middle = lerp(ends[0], ends[1], 0.5);
+            if edge.faces.len() != 2 {
+                return middle;
+            }
+
+            // 3/8 of each end and 1/8 of the corners opposite the edge,
+            // there is one as triangles don't repeat corners
+            let opposite = edge.faces.iter().map(|face| {
+                let corner = self.faces[*face].iter().find(|vertex| !edge.vertices.contains(vertex)).unwrap();
+                &self.positions[*corner]
+            }).collect::<Vec<&Point>>();
+            let smooth = middle.mult(0.75).add(&lerp(opposite[0], opposite[1], 0.5).mult(0.25));
+            lerp(&smooth, &middle, edge.sharp_weight())
+        }).collect::<Vec<Point>>();
+
+        let vertex_points = (0..self.positions.len()).map(|vertex| {
+            let edges = &topology.vertex_edges[vertex];
+            if edges.is_empty() {
+                return self.positions[vertex].clone();
+            }
+
+            // Warren's weights for the neighbours
+            let valence = edges.len();
+            let beta = if valence == 3 { 3.0 / 16.0 } else { 3.0 / (8.0 * valence as f64) };
+            let neighbours = edges.iter()
+                .fold(Point::from((0.0, 0.0, 0.0)), |sum, edge| sum.add(&self.positions[topology.edges[*edge].other(vertex)]));
+            let smooth = self.positions[vertex].mult(1.0 - valence as f64 * beta).add(&neighbours.mult(beta));
+            self.vertex_point(&topology, vertex, smooth)
+        }).collect::<Vec<Point>>();
+
+        let edge_base = vertex_points.len();
+        let edge_vertex = |edge: usize| edge_base + edge;
+        let faces = self.faces.iter().flat_map(|face| {
+            let edge = |a: usize, b: usize| edge_vertex(topology.lookup[&key(a, b)]);
+            let (a, b, c) = (face[0], face[1], face[2]);
+            let (ab, bc, ca) = (edge(a, b), edge(b, c), edge(c, a));
+            vec![vec![a, ab, ca], vec![b, bc, ab], vec![c, ca, bc], vec![ab, bc, ca]]
+        }).collect();
+
+        ControlMesh {
+            creases: ControlMesh::child_creases(&topology, &edge_vertex),
+            positions: vertex_points.into_iter().chain(edge_points).collect(),
+            faces: faces,
+        }
+    }
+
+    // Triangulated as fans, with vertex normals averaged over the faces
+    // around each vertex. Vertices on sharp edges are split, so each side
+    // of a crease is shaded on its own.
+    pub fn to_mesh(&self, material: MaterialId) -> Result<TriangleMesh, LoadErr> {
+        let topology = self.topology();
+
+        // corners of faces, grouped across the smooth edges they share
+        let corner_base = self.faces.iter().scan(0, |base, face| {
+            let start = *base;
+            *base += face.len();
+            Some(start)
+        }).collect::<Vec<usize>>();
+        // only asked for faces known to have the vertex, from the edges
+        let corner = |face: usize, vertex: usize| {
+            corner_base[face] + self.faces[face].iter().position(|v| *v == vertex).unwrap()
+        };
+        let mut groups = (0..self.faces.iter().map(|face| face.len()).sum()).collect::<Vec<usize>>();
+        for edge in topology.edges.iter().filter(|edge| !edge.is_sharp()) {
+            let [f0, f1] = [edge.faces[0], edge.faces[1]];
+            for vertex in edge.vertices.iter() {
+                let a = find(&mut groups, corner(f0, *vertex));
+                let b = find(&mut groups, corner(f1, *vertex));
+                groups[a] = b;
+            }
+        }
+
+        let face_normals = self.faces.iter().map(|face| {
+            // Newell's method, for polygons that aren't quite planar
+            let mut normal = Point::from((0.0, 0.0, 0.0));
+            for (index, vertex) in face.iter().enumerate() {
+                let (p, q) = (&self.positions[*vertex], &self.positions[face[(index + 1) % face.len()]]);
+                normal = normal.add(&Point::from((
+                    (p.y - q.y) * (p.z + q.z),
+                    (p.z - q.z) * (p.x + q.x),
+                    (p.x - q.x) * (p.y + q.y))));
+            }
+            normal
+        }).collect::<Vec<Point>>();
+
+        // one mesh vertex per group, summing the area weighted normals of
+        // its faces
+        let mut vertex_of_group = HashMap::new();
+        let mut positions = Vec::new();
+        let mut normals = Vec::<Point>::new();
+        let mut triangles = Vec::new();
+        for (index, face) in self.faces.iter().enumerate() {
+            let vertices = face.iter().map(|vertex| {
+                let group = find(&mut groups, corner(index, *vertex));
+                let mesh_vertex = *vertex_of_group.entry(group).or_insert_with(|| {
+                    positions.push(self.positions[*vertex].clone());
+                    normals.push(Point::from((0.0, 0.0, 0.0)));
+                    positions.len() - 1
+                });
+                normals[mesh_vertex] = normals[mesh_vertex].add(&face_normals[index]);
+                mesh_vertex
+            }).collect::<Vec<usize>>();
+
+            for i in 1..vertices.len() - 1 {
+                triangles.push([vertices[0], vertices[i], vertices[i + 1]]);
+            }
+        }
+
+        // a vertex whose faces cancel out takes the normal of one of them
+        for [a, b, c] in triangles.iter() {
+            for vertex in [a, b, c].iter() {
+                if !(normals[**vertex].abs() > 0.0) {
+                    normals[**vertex] = triangle_normal([&positions[*a], &positions[*b], &positions[*c]]);
+                }
+            }
+        }
+
+        TriangleMesh::new(positions, Some(normals), None, None, triangles, material)
+    }
+}
+
+// Smooth surface refined from a control mesh, turned into triangles up
+// front and traced like any other mesh
+pub struct SubdivisionSurface {
+    mesh: TriangleMesh,
+}
+
+impl SubdivisionSurface {
+    pub fn new(control: &ControlMesh, levels: u32, material: MaterialId) -> Result<SubdivisionSurface, LoadErr> {
+        Ok(SubdivisionSurface {
+            mesh: control.subdivide(levels).to_mesh(material)?,
+        })
+    }
+}
+
+impl Intersect for SubdivisionSurface {
+    fn intersect(&self, ray: &LightRay) -> Option<Hit> {
+        self.mesh.intersect(ray)
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.mesh.bounds()
+    }
+
+    fn occludes(&self, ray: &LightRay) -> bool {
+        self.mesh.occludes(ray)
+    }
+
+    fn intersect_packet(&self, packet: &RayPacket, rays: &[LightRay], lanes: Mask) -> [Option<Hit>; PACKET_SIZE] {
+        self.mesh.intersect_packet(packet, rays, lanes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // corner i of the cube from -1 to 1 has x, y and z from its bits
+    fn corner(index: usize) -> Point {
+        let coordinate = |bit: usize| if index & bit == 0 { -1.0 } else { 1.0 };
+        Point::from((coordinate(1), coordinate(2), coordinate(4)))
+    }
+
+    fn cube_faces() -> Vec<Vec<usize>> {
+        vec![vec![0, 2, 3, 1], vec![4, 5, 7, 6], vec![0, 1, 5, 4], vec![2, 6, 7, 3], vec![0, 4, 6, 2], vec![1, 3, 7, 5]]
+    }
+
+    fn cube() -> ControlMesh {
+        ControlMesh::new((0..8).map(corner).collect(), cube_faces(), Vec::new()).unwrap()
+    }
+
+    fn assert_near(point: &Point, expected: &Point) {
+        assert!(point.add(&expected.mult(-1.0)).abs() < 1e-12, "{:?}, expected {:?}", point, expected);
+    }
+
+    fn fails(mesh: Result<ControlMesh, LoadErr>, message: &str) {
+        match mesh {
+            Ok(_) => panic!("built, expected '{}'", message),
+            Err(err) => assert_eq!(format!("{}", err), message),
+        }
+    }
+
+    #[test]
+    fn catmull_clark_cube() {
+        let cube = cube();
+        let topology = cube.topology();
+        let refined = cube.subdivide(1);
+        assert_eq!(refined.positions.len(), 8 + 12 + 6);
+        assert_eq!(refined.faces.len(), 24);
+        assert!(refined.faces.iter().all(|face| face.len() == 4));
+
+        // corners have three faces, (Q + 2R) / 3 with Q a third of the way
+        // to the corner and R two thirds of the way gives 5 / 9 of it
+        for vertex in 0..8 {
+            assert_near(&refined.positions[vertex], &corner(vertex).mult(5.0 / 9.0));
+        }
+        // edge points average the ends and the two face centers, 3 / 4 of
+        // the edge's midpoint
+        for (index, edge) in topology.edges.iter().enumerate() {
+            let middle = lerp(&corner(edge.vertices[0]), &corner(edge.vertices[1]), 0.5);
+            assert_near(&refined.positions[8 + index], &middle.mult(0.75));
+        }
+        for (index, face) in cube.faces.iter().enumerate() {
+            let center = average(face.iter().map(|vertex| &cube.positions[*vertex]));
+            assert_near(&refined.positions[20 + index], &center);
+            assert!((center.abs() - 1.0).abs() < 1e-12);
+        }
+
+        // every quad keeps an original corner, going round it the same way
+        // as the face it came from
+        for (index, face) in refined.faces.iter().enumerate() {
+            let original = &cube.faces[index / 4];
+            assert_eq!(face[0], original[index % 4]);
+            assert_eq!(face[2], 20 + index / 4);
+        }
+        assert!(ControlMesh::new(refined.positions.clone(), refined.faces.clone(), Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_faces() {
+        let corners = (0..8).map(corner).collect::<Vec<Point>>();
+        fails(ControlMesh::new(corners.clone(), vec![vec![0, 1]], Vec::new()), "face 0 has 2 corners, needs at least 3");
+        fails(ControlMesh::new(corners.clone(), vec![vec![0, 1, 8]], Vec::new()), "face 0 uses vertex 8, there are 8");
+        fails(ControlMesh::new(corners.clone(), vec![vec![0, 1, 3, 1]], Vec::new()), "face 0 uses vertex 1 twice");
+        fails(ControlMesh::new(corners.clone(), vec![vec![0, 1, 2]], vec![([0, 9], 1.0)]), "crease 0 9 uses a missing vertex, there are 8");
+    }
+
+    #[test]
+    fn rejects_inconsistent_meshes() {
+        let corners = (0..8).map(corner).collect::<Vec<Point>>();
+
+        // the cube's second face turned inside out
+        let mut faces = cube_faces();
+        faces[1].reverse();
+        fails(ControlMesh::new(corners.clone(), faces, Vec::new()),
+            "faces 1 and 2 both run from vertex 5 to 4, the mesh is non-manifold or inconsistently wound");
+
+        // three triangles on the edge from 0 to 1
+        let faces = vec![vec![0, 1, 2], vec![1, 0, 3], vec![1, 0, 4]];
+        fails(ControlMesh::new(corners.clone(), faces, Vec::new()),
+            "faces 1 and 2 both run from vertex 1 to 0, the mesh is non-manifold or inconsistently wound");
+
+        // an open strip is fine, its outer edges are boundaries
+        assert!(ControlMesh::new(corners, vec![vec![0, 1, 3, 2], vec![2, 3, 7, 6]], Vec::new()).is_ok());
+    }
+}
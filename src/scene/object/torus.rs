@@ -0,0 +1,109 @@
+use std::f64::consts::PI;
+
+use crate::scene::object::{Intersect, Hit, Interval};
+use crate::scene::object::local_hit::{LocalHit, to_local, local_bounds, closest, intervals, azimuth};
+use crate::scene::object::polynomial::solve_quartic;
+use crate::scene::primitives::{Point, Frame, Bounds};
+use crate::scene::bsdf::MaterialId;
+use crate::scene::LightRay;
+use crate::scene::load_err::LoadErr;
+
+// Ring torus around the axis through its center, a tube of the minor
+// radius swept around a circle of the major one, which has to be the
+// larger. u goes around the axis and v around the tube, starting on the
+// outside.
+pub struct Torus {
+    position: Point,
+    frame: Frame,
+    major_radius: f64,
+    minor_radius: f64,
+    material: MaterialId,
+}
+
+impl Torus {
+    pub fn new(center: Point, axis: Point, major_radius: f64, minor_radius: f64, material: MaterialId) -> Result<Torus, LoadErr> {
+        if !(minor_radius > 0.0 && major_radius > minor_radius) {
+            return Err(LoadErr::from(format!(
+                "torus radii {} and {} need the major one larger than the minor one, which is above 0",
+                major_radius, minor_radius)));
+        }
+        Ok(Torus {
+            position: center,
+            frame: Frame::from_normal(&axis.normalize()),
+            major_radius: major_radius,
+            minor_radius: minor_radius,
+            material: material,
+        })
+    }
+}
+
+impl Torus {
+    // every hit along the ray's line, in local space
+    fn local_hits(&self, ray: &LightRay) -> Vec<LocalHit> {
+        let (origin, direction) = to_local(ray, &self.position, &self.frame);
+
+        // start the ray from its closest approach to the center, so far away
+        // rays do not lose the roots to cancellation
+        let length2 = direction.dot(&direction);
+        let shift = -origin.dot(&direction) / length2;
+        let origin = origin.add(&direction.mult(shift));
+
+        // (|p|^2 - R^2 - r^2)^2 = 4 R^2 (r^2 - z^2), expanded along the ray
+        let major2 = self.major_radius * self.major_radius;
+        let minor2 = self.minor_radius * self.minor_radius;
+        let e = origin.dot(&origin) - major2 - minor2;
+        let f = origin.dot(&direction);
+        let coefficients = [
+            length2 * length2,
+            4.0 * length2 * f,
+            2.0 * length2 * e + 4.0 * f * f + 4.0 * major2 * direction.z * direction.z,
+            4.0 * f * e + 8.0 * major2 * origin.z * direction.z,
+            e * e - 4.0 * major2 * (minor2 - origin.z * origin.z),
+        ];
+
+        solve_quartic(&coefficients).into_iter().map(|root| {
+            // project back onto the tube around the nearest point of the ring
+            let local = origin.add(&direction.mult(root));
+            let phi = azimuth(&local);
+            let ring = Point::from((self.major_radius * phi.cos(), self.major_radius * phi.sin(), 0.0));
+            let normal = local.add(&ring.mult(-1.0)).normalize();
+            let local = ring.add(&normal.mult(self.minor_radius));
+
+            let distance = (local.x * local.x + local.y * local.y).sqrt();
+            let theta = local.z.atan2(distance - self.major_radius);
+            let theta = if theta < 0.0 { theta + 2.0 * PI } else { theta };
+
+            LocalHit {
+                t: root + shift,
+                uv: (phi / (2.0 * PI), theta / (2.0 * PI)),
+                dpdu: Point::from((-2.0 * PI * local.y, 2.0 * PI * local.x, 0.0)),
+                dpdv: Point::from((
+                    -2.0 * PI * self.minor_radius * theta.sin() * phi.cos(),
+                    -2.0 * PI * self.minor_radius * theta.sin() * phi.sin(),
+                    2.0 * PI * self.minor_radius * theta.cos())),
+                normal: normal,
+                point: local,
+            }
+        }).collect()
+    }
+}
+
+impl Intersect for Torus {
+    fn intersect(&self, ray: &LightRay) -> Option<Hit> {
+        closest(ray, self.local_hits(ray))
+            .map(|hit| hit.to_world(ray, &self.position, &self.frame, self.material))
+    }
+
+    fn bounds(&self) -> Bounds {
+        let reach = self.major_radius + self.minor_radius;
+        local_bounds(
+            &Point::from((-reach, -reach, -self.minor_radius)),
+            &Point::from((reach, reach, self.minor_radius)),
+            &self.position,
+            &self.frame)
+    }
+
+    fn intervals(&self, ray: &LightRay) -> Option<Vec<Interval>> {
+        Some(intervals(ray, self.local_hits(ray), &self.position, &self.frame, self.material))
+    }
+}
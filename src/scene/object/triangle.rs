@@ -0,0 +1,201 @@
+use crate::scene::primitives::Point;
+use crate::scene::LightRay;
+use crate::scene::packet::{self, RayPacket, Mask, F64x4};
+
+// Watertight ray triangle intersection (Woop et al. 2013). The triangle is
+// moved into a space where the ray starts at the origin and runs along +z,
+// so the edge tests become 2d and give the same answer for a ray on an edge
+// shared by two triangles, no ray slips through between them and only one
+// of them is hit.
+//
+// Returns the distance and the barycentric weights of the three vertices.
+pub fn intersect_triangle(ray: &LightRay, vertices: [&Point; 3]) -> Option<(f64, [f64; 3])> {
+    let direction = ray.dir();
+    let d = [direction.x, direction.y, direction.z];
+
+    // the largest direction component becomes z
+    let kz = if d[0].abs() > d[1].abs() {
+        if d[0].abs() > d[2].abs() { 0 } else { 2 }
+    }
+    else {
+        if d[1].abs() > d[2].abs() { 1 } else { 2 }
+    };
+    let kx = (kz + 1) % 3;
+    let ky = (kx + 1) % 3;
+
+    let shear_x = -d[kx] / d[kz];
+    let shear_y = -d[ky] / d[kz];
+    let shear_z = 1.0 / d[kz];
+
+    let mut p = [[0.0; 3]; 3];
+    for (i, vertex) in vertices.iter().enumerate() {
+        let offset = vertex.add(&ray.pos().mult(-1.0));
+        let offset = [offset.x, offset.y, offset.z];
+        p[i] = [
+            offset[kx] + shear_x * offset[kz],
+            offset[ky] + shear_y * offset[kz],
+            offset[kz] * shear_z,
+        ];
+    }
+
+    // signed edge functions, the ray is inside when they all agree
+    let e0 = p[1][0] * p[2][1] - p[1][1] * p[2][0];
+    let e1 = p[2][0] * p[0][1] - p[2][1] * p[0][0];
+    let e2 = p[0][0] * p[1][1] - p[0][1] * p[1][0];
+    if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+        return None;
+    }
+
+    let determinant = e0 + e1 + e2;
+    if determinant == 0.0 {
+        return None;
+    }
+
+    // A ray right on an edge would be inside both triangles sharing it, it
+    // goes to the one the edge runs up, or right along, when the triangle
+    // is turned counterclockwise. Its neighbour runs the edge the other way.
+    for (i, e) in [e0, e1, e2].iter().enumerate() {
+        if *e == 0.0 {
+            let (a, b) = (&p[(i + 1) % 3], &p[(i + 2) % 3]);
+            let dx = (b[0] - a[0]) * determinant.signum();
+            let dy = (b[1] - a[1]) * determinant.signum();
+            if !(dy > 0.0 || (dy == 0.0 && dx > 0.0)) {
+                return None;
+            }
+        }
+    }
+
+    let t = (e0 * p[0][2] + e1 * p[1][2] + e2 * p[2][2]) / determinant;
+    if !ray.ray.contains(t) {
+        return None;
+    }
+
+    Some((t, [e0 / determinant, e1 / determinant, e2 / determinant]))
+}
+
+// Lanes of a packet that may hit the triangle, to be confirmed with
+// intersect_triangle. The barycentrics from Moller and Trumbore's test
+// aren't watertight, so they only rule out rays clearly outside, and rays
+// nearly in the triangle's plane are always left in.
+pub fn triangle_candidates(packet: &RayPacket, vertices: [&Point; 3]) -> Mask {
+    const TOLERANCE: f64 = 1e-7;
+
+    let splat = |point: &Point| [F64x4::splat(point.x), F64x4::splat(point.y), F64x4::splat(point.z)];
+    let cross = |a: &[F64x4; 3], b: &[F64x4; 3]| [
+        a[1].mult(&b[2]).sub(&a[2].mult(&b[1])),
+        a[2].mult(&b[0]).sub(&a[0].mult(&b[2])),
+        a[0].mult(&b[1]).sub(&a[1].mult(&b[0]))];
+
+    let e1 = vertices[1].add(&vertices[0].mult(-1.0));
+    let e2 = vertices[2].add(&vertices[0].mult(-1.0));
+    let area = e1.cross(&e2).abs();
+
+    let p = cross(&packet.direction, &splat(&e2));
+    let determinant = packet::dot(&splat(&e1), &p);
+    let offset = packet.offset(vertices[0]);
+    let q = cross(&offset, &splat(&e1));
+    let u = packet::dot(&offset, &p).div(&determinant);
+    let v = packet::dot(&packet.direction, &q).div(&determinant);
+
+    let outside = u.lt(&F64x4::splat(-TOLERANCE))
+        .or(&v.lt(&F64x4::splat(-TOLERANCE)))
+        .or(&u.add(&v).gt(&F64x4::splat(1.0 + TOLERANCE)));
+    let grazing = determinant.copysign(&F64x4::splat(1.0)).lt(&F64x4::splat(1e-6 * area));
+    outside.and(&grazing.invert()).invert()
+}
+
+// Partial derivatives of position over a triangle with the given uvs,
+// None when the uvs don't span an area
+pub fn triangle_derivatives(vertices: [&Point; 3], uvs: [(f64, f64); 3]) -> Option<(Point, Point)> {
+    let du02 = uvs[0].0 - uvs[2].0;
+    let dv02 = uvs[0].1 - uvs[2].1;
+    let du12 = uvs[1].0 - uvs[2].0;
+    let dv12 = uvs[1].1 - uvs[2].1;
+    let dp02 = vertices[0].add(&vertices[2].mult(-1.0));
+    let dp12 = vertices[1].add(&vertices[2].mult(-1.0));
+
+    let determinant = du02 * dv12 - dv02 * du12;
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+
+    let inverse = 1.0 / determinant;
+    let dpdu = dp02.mult(dv12).add(&dp12.mult(-dv02)).mult(inverse);
+    let dpdv = dp02.mult(-du12).add(&dp12.mult(du02)).mult(inverse);
+    Some((dpdu, dpdv))
+}
+
+// uvs assumed for triangles that don't have any
+pub const DEFAULT_UVS: [(f64, f64); 3] = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)];
+
+// Normal of a triangle, facing the side its vertices wind counterclockwise
+// around when seen from
+pub fn triangle_normal(vertices: [&Point; 3]) -> Point {
+    let e1 = vertices[1].add(&vertices[0].mult(-1.0));
+    let e2 = vertices[2].add(&vertices[0].mult(-1.0));
+    e1.cross(&e2).normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::primitives::Ray;
+    use crate::scene::sampler::Sampler;
+
+    // unit square split along its diagonal, wound the same way
+    fn square() -> [[Point; 3]; 2] {
+        let corner = |x: f64, y: f64| Point::from((x, y, 0.0));
+        [[corner(0.0, 0.0), corner(1.0, 0.0), corner(1.0, 1.0)],
+         [corner(0.0, 0.0), corner(1.0, 1.0), corner(0.0, 1.0)]]
+    }
+
+    fn hits(ray: &LightRay) -> usize {
+        square().iter()
+            .filter(|triangle| intersect_triangle(ray, [&triangle[0], &triangle[1], &triangle[2]]).is_some())
+            .count()
+    }
+
+    #[test]
+    fn hits_inside() {
+        let ray = LightRay::new(Ray::new(Point::from((0.7, 0.2, 1.0)), Point::from((0.0, 0.0, -1.0))));
+        let [first, _] = square();
+        let (t, b) = intersect_triangle(&ray, [&first[0], &first[1], &first[2]]).unwrap();
+        assert!((t - 1.0).abs() < 1e-12);
+        assert!((b[0] - 0.3).abs() < 1e-12 && (b[1] - 0.5).abs() < 1e-12 && (b[2] - 0.2).abs() < 1e-12, "{:?}", b);
+    }
+
+    #[test]
+    fn misses_outside_and_behind() {
+        let outside = LightRay::new(Ray::new(Point::from((1.5, 0.5, 1.0)), Point::from((0.0, 0.0, -1.0))));
+        assert_eq!(hits(&outside), 0);
+        let behind = LightRay::new(Ray::new(Point::from((0.5, 0.2, -1.0)), Point::from((0.0, 0.0, -1.0))));
+        assert_eq!(hits(&behind), 0);
+    }
+
+    #[test]
+    fn shared_edge_hit_once() {
+        // straight down onto the diagonal, where the edge functions are
+        // exactly zero
+        for i in 1..10 {
+            let x = i as f64 / 10.0;
+            for direction in [(0.0, 0.0, -1.0), (0.0, 0.0, 1.0)].iter() {
+                let origin = Point::from((x, x, 0.0)).add(&Point::from(*direction).mult(-1.0));
+                let ray = LightRay::new(Ray::new(origin, Point::from(*direction)));
+                assert_eq!(hits(&ray), 1, "ray down onto ({}, {})", x, x);
+            }
+        }
+
+        // and from all over at points along it
+        let mut sampler = Sampler::new(3);
+        for _ in 0..1000 {
+            let along = sampler.next_f64();
+            let target = Point::from((along, along, 0.0));
+            let origin = Point::from((
+                4.0 * sampler.next_f64() - 2.0,
+                4.0 * sampler.next_f64() - 2.0,
+                0.1 + 2.0 * sampler.next_f64()));
+            let ray = LightRay::new(Ray::new(origin.clone(), target.add(&origin.mult(-1.0))));
+            assert_eq!(hits(&ray), 1, "ray from {:?} to {:?}", origin, target);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use crate::scene::object::{Intersect, Hit, Bvh};
+use crate::scene::object::triangle::{intersect_triangle, triangle_candidates, triangle_derivatives, triangle_normal, DEFAULT_UVS};
+use crate::scene::primitives::{Point, Frame, Spectrum, Bounds};
+use crate::scene::bsdf::MaterialId;
+use crate::scene::LightRay;
+use crate::scene::packet::{RayPacket, Mask, PACKET_SIZE};
+use crate::scene::load_err::LoadErr;
+
+// Triangles indexing into shared vertex buffers. Normals, uvs and colors
+// are optional, given per vertex and interpolated across each triangle.
+pub struct TriangleMesh {
+    positions: Vec<Point>,
+    normals: Option<Vec<Point>>,
+    uvs: Option<Vec<(f64, f64)>>,
+    // multiplied into the material, like a texture
+    colors: Option<Vec<Spectrum>>,
+    triangles: Vec<[usize; 3]>,
+    material: MaterialId,
+    // over the triangles, by index
+    bvh: Bvh,
+}
+
+impl TriangleMesh {
+    // Fails when an index is out of range or a normal, uv or color buffer
+    // doesn't have one entry per position
+    pub fn new(positions: Vec<Point>,
+        normals: Option<Vec<Point>>,
+        uvs: Option<Vec<(f64, f64)>>,
+        colors: Option<Vec<Spectrum>>,
+        triangles: Vec<[usize; 3]>,
+        material: MaterialId) -> Result<TriangleMesh, LoadErr> {
+
+        let count = positions.len();
+        let buffers = [
+            ("normals", normals.as_ref().map(|normals| normals.len())),
+            ("uvs", uvs.as_ref().map(|uvs| uvs.len())),
+            ("colors", colors.as_ref().map(|colors| colors.len()))];
+        for (name, length) in buffers.iter() {
+            if let Some(length) = length.filter(|length| *length != count) {
+                return Err(LoadErr::from(format!("mesh has {} {} for {} vertices", length, name, count)));
+            }
+        }
+        if let Some(index) = triangles.iter().position(|triangle| triangle.iter().any(|index| *index >= count)) {
+            let [i0, i1, i2] = triangles[index];
+            return Err(LoadErr::from(format!("mesh triangle {} uses vertices {} {} {}, there are {}", index, i0, i1, i2, count)));
+        }
+
+        let bounds = triangles.iter().map(|[i0, i1, i2]| {
+            Bounds::from_points([&positions[*i0], &positions[*i1], &positions[*i2]].iter().cloned())
+        }).collect::<Vec<Bounds>>();
+
+        Ok(TriangleMesh {
+            bvh: Bvh::new(&bounds),
+            positions: positions,
+            normals: normals.map(|normals| normals.iter().map(|normal| normal.normalize()).collect()),
+            uvs: uvs,
+            colors: colors,
+            triangles: triangles,
+            material: material,
+        })
+    }
+
+    fn vertices(&self, triangle: usize) -> [&Point; 3] {
+        let [i0, i1, i2] = self.triangles[triangle];
+        [&self.positions[i0], &self.positions[i1], &self.positions[i2]]
+    }
+
+    // Full hit record for a ray known to hit the given triangle
+    fn hit(&self, ray: &LightRay, triangle: usize, t: f64, b: [f64; 3]) -> Hit {
+        let indices = self.triangles[triangle];
+        let vertices = self.vertices(triangle);
+        let point = vertices[0].mult(b[0])
+            .add(&vertices[1].mult(b[1]))
+            .add(&vertices[2].mult(b[2]));
+
+        let uvs = match &self.uvs {
+            Some(uvs) => [uvs[indices[0]], uvs[indices[1]], uvs[indices[2]]],
+            None => DEFAULT_UVS,
+        };
+        let uv = (
+            b[0] * uvs[0].0 + b[1] * uvs[1].0 + b[2] * uvs[2].0,
+            b[0] * uvs[0].1 + b[1] * uvs[1].1 + b[2] * uvs[2].1);
+
+        let mut geometric_normal = triangle_normal(vertices);
+        let (dpdu, dpdv) = triangle_derivatives(vertices, uvs).unwrap_or_else(|| {
+            let frame = Frame::from_normal(&geometric_normal);
+            (frame.tangent, frame.bitangent)
+        });
+
+        let shading_normal = match &self.normals {
+            Some(normals) => {
+                let normal = normals[indices[0]].mult(b[0])
+                    .add(&normals[indices[1]].mult(b[1]))
+                    .add(&normals[indices[2]].mult(b[2]));
+                if normal.abs() > 0.0 {
+                    // vertex normals say which way is out, whatever the winding
+                    let normal = normal.normalize();
+                    if normal.dot(&geometric_normal) < 0.0 {
+                        geometric_normal = geometric_normal.mult(-1.0);
+                    }
+                    normal
+                }
+                else {
+                    geometric_normal.clone()
+                }
+            },
+            None => geometric_normal.clone(),
+        };
+
+        let mut hit = Hit::new(t, point, geometric_normal, ray.dir(), uv, dpdu, dpdv, Some(self.material));
+        hit.shading_normal = shading_normal;
+        hit.primitive = triangle;
+        hit.color = self.colors.as_ref().map(|colors| {
+            colors[indices[0]].mult(b[0])
+                .add(&colors[indices[1]].mult(b[1]))
+                .add(&colors[indices[2]].mult(b[2]))
+        });
+        hit
+    }
+}
+
+impl Intersect for TriangleMesh {
+    fn intersect(&self, ray: &LightRay) -> Option<Hit> {
+        self.bvh.closest(&ray.ray, |triangle| {
+            intersect_triangle(ray, self.vertices(triangle)).map(|(t, b)| (t, (triangle, t, b)))
+        }).map(|(triangle, t, b)| self.hit(ray, triangle, t, b))
+    }
+
+    fn intersect_packet(&self, packet: &RayPacket, rays: &[LightRay], lanes: Mask) -> [Option<Hit>; PACKET_SIZE] {
+        let found = self.bvh.closest_packet(packet, lanes, |triangle, lanes| {
+            let vertices = self.vertices(triangle);
+            let mut hits: [Option<(f64, (usize, f64, [f64; 3]))>; PACKET_SIZE] = Default::default();
+            for lane in triangle_candidates(packet, vertices).and(&lanes).lanes() {
+                hits[lane] = intersect_triangle(&rays[lane], vertices).map(|(t, b)| (t, (triangle, t, b)));
+            }
+            hits
+        });
+
+        let mut hits: [Option<Hit>; PACKET_SIZE] = Default::default();
+        for (lane, found) in found.iter().enumerate() {
+            hits[lane] = found.map(|(triangle, t, b)| self.hit(&rays[lane], triangle, t, b));
+        }
+        hits
+    }
+
+    fn bounds(&self) -> Bounds {
+        self.bvh.bounds()
+    }
+
+    fn occludes(&self, ray: &LightRay) -> bool {
+        self.bvh.any(&ray.ray, |triangle| intersect_triangle(ray, self.vertices(triangle)).is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::primitives::Ray;
+
+    fn square() -> (Vec<Point>, Vec<[usize; 3]>) {
+        (vec![
+            Point::from((0.0, 0.0, 0.0)),
+            Point::from((1.0, 0.0, 0.0)),
+            Point::from((1.0, 1.0, 0.0)),
+            Point::from((0.0, 1.0, 0.0))],
+         vec![[0, 1, 2], [0, 2, 3]])
+    }
+
+    fn fails(mesh: Result<TriangleMesh, LoadErr>, message: &str) {
+        match mesh {
+            Ok(_) => panic!("expected '{}'", message),
+            Err(err) => assert_eq!(format!("{}", err), message),
+        }
+    }
+
+    #[test]
+    fn rejects_short_buffers() {
+        let (positions, triangles) = square();
+        let two_normals = vec![Point::from((0.0, 0.0, 1.0)); 2];
+        fails(TriangleMesh::new(positions.clone(), Some(two_normals), None, None, triangles.clone(), 0),
+            "mesh has 2 normals for 4 vertices");
+        fails(TriangleMesh::new(positions.clone(), None, Some(vec![(0.0, 0.0); 5]), None, triangles.clone(), 0),
+            "mesh has 5 uvs for 4 vertices");
+        fails(TriangleMesh::new(positions, None, None, Some(vec![Spectrum::new(1.0, 1.0, 1.0)]), triangles, 0),
+            "mesh has 1 colors for 4 vertices");
+    }
+
+    #[test]
+    fn rejects_missing_vertex() {
+        let (positions, _) = square();
+        fails(TriangleMesh::new(positions, None, None, None, vec![[0, 1, 2], [0, 2, 4]], 0),
+            "mesh triangle 1 uses vertices 0 2 4, there are 4");
+    }
+
+    #[test]
+    fn shared_edge_hit() {
+        let (positions, triangles) = square();
+        let normals = vec![Point::from((0.0, 0.0, 1.0)); 4];
+        let mesh = TriangleMesh::new(positions, Some(normals), None, None, triangles, 0).unwrap();
+
+        // straight down onto the diagonal the two triangles share
+        let ray = LightRay::new(Ray::new(Point::from((0.25, 0.25, 1.0)), Point::from((0.0, 0.0, -1.0))));
+        let hit = mesh.intersect(&ray).expect("ray through the shared edge slipped through");
+        assert!((hit.t - 1.0).abs() < 1e-12);
+        assert!(hit.shading_normal.add(&Point::from((0.0, 0.0, -1.0))).abs() < 1e-12);
+    }
+}
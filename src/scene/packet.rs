@@ -0,0 +1,539 @@
+// Packets of camera rays traced together. Rays through neighbouring pixels
+// visit mostly the same bvh nodes and primitives, so each node's box and
+// each sphere or triangle is tested against the whole packet at once.
+//
+// F64x4 holds one value per ray. Its operations use AVX when the build
+// enables it (RUSTFLAGS="-C target-cpu=native" for example), a pair of SSE2
+// registers on other x86_64 builds, and plain loops everywhere else. They
+// all round the same way as the scalar code, so packets find exactly the
+// hits single rays would. The tests check whichever lanes the build uses
+// against the plain loops, run them with AVX enabled as well to cover both
+// x86_64 versions.
+
+use crate::scene::primitives::{Point, Bounds};
+use crate::scene::LightRay;
+
+pub const PACKET_SIZE: usize = 4;
+
+pub use lanes::F64x4;
+
+#[cfg(not(target_arch = "x86_64"))]
+use scalar as lanes;
+
+// Set of lanes in a packet, one bit each
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mask(u8);
+
+impl Mask {
+    pub fn from_bits(bits: u8) -> Self {
+        Mask(bits & ((1 << PACKET_SIZE) - 1))
+    }
+
+    pub fn first(count: usize) -> Self {
+        Mask::from_bits(((1u32 << count) - 1) as u8)
+    }
+
+    pub fn any(&self) -> bool {
+        self.0 != 0
+    }
+
+    pub fn has(&self, lane: usize) -> bool {
+        self.0 & (1 << lane) != 0
+    }
+
+    pub fn and(&self, other: &Mask) -> Mask {
+        Mask(self.0 & other.0)
+    }
+
+    pub fn or(&self, other: &Mask) -> Mask {
+        Mask(self.0 | other.0)
+    }
+
+    pub fn invert(&self) -> Mask {
+        Mask::from_bits(!self.0)
+    }
+
+    pub fn lanes(self) -> impl Iterator<Item = usize> {
+        (0..PACKET_SIZE).filter(move |lane| self.has(*lane))
+    }
+}
+
+// Lanes are named like Point's methods. min and max give other when either
+// value is NaN, the way the SSE instructions do, and comparisons with NaN
+// are false.
+#[cfg(all(target_arch = "x86_64", target_feature = "avx"))]
+mod lanes {
+    use std::arch::x86_64::*;
+
+    use super::Mask;
+
+    #[derive(Clone, Copy, Debug)]
+    pub struct F64x4(__m256d);
+
+    impl F64x4 {
+        pub fn splat(value: f64) -> Self {
+            unsafe { F64x4(_mm256_set1_pd(value)) }
+        }
+
+        pub fn new(values: [f64; 4]) -> Self {
+            unsafe { F64x4(_mm256_loadu_pd(values.as_ptr())) }
+        }
+
+        pub fn to_array(&self) -> [f64; 4] {
+            let mut values = [0.0; 4];
+            unsafe { _mm256_storeu_pd(values.as_mut_ptr(), self.0) };
+            values
+        }
+
+        pub fn add(&self, other: &F64x4) -> F64x4 {
+            unsafe { F64x4(_mm256_add_pd(self.0, other.0)) }
+        }
+
+        pub fn sub(&self, other: &F64x4) -> F64x4 {
+            unsafe { F64x4(_mm256_sub_pd(self.0, other.0)) }
+        }
+
+        pub fn mult(&self, other: &F64x4) -> F64x4 {
+            unsafe { F64x4(_mm256_mul_pd(self.0, other.0)) }
+        }
+
+        pub fn div(&self, other: &F64x4) -> F64x4 {
+            unsafe { F64x4(_mm256_div_pd(self.0, other.0)) }
+        }
+
+        pub fn min(&self, other: &F64x4) -> F64x4 {
+            unsafe { F64x4(_mm256_min_pd(self.0, other.0)) }
+        }
+
+        pub fn max(&self, other: &F64x4) -> F64x4 {
+            unsafe { F64x4(_mm256_max_pd(self.0, other.0)) }
+        }
+
+        pub fn sqrt(&self) -> F64x4 {
+            unsafe { F64x4(_mm256_sqrt_pd(self.0)) }
+        }
+
+        // magnitude of self with the sign bit of other
+        pub fn copysign(&self, other: &F64x4) -> F64x4 {
+            unsafe {
+                let sign = _mm256_set1_pd(-0.0);
+                F64x4(_mm256_or_pd(_mm256_andnot_pd(sign, self.0), _mm256_and_pd(sign, other.0)))
+            }
+        }
+
+        pub fn lt(&self, other: &F64x4) -> Mask {
+            unsafe { Mask::from_bits(_mm256_movemask_pd(_mm256_cmp_pd(self.0, other.0, _CMP_LT_OQ)) as u8) }
+        }
+
+        pub fn gt(&self, other: &F64x4) -> Mask {
+            unsafe { Mask::from_bits(_mm256_movemask_pd(_mm256_cmp_pd(self.0, other.0, _CMP_GT_OQ)) as u8) }
+        }
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", not(target_feature = "avx")))]
+mod lanes {
+    use std::arch::x86_64::*;
+
+    use super::Mask;
+
+    // low and high pairs of lanes
+    #[derive(Clone, Copy, Debug)]
+    pub struct F64x4(__m128d, __m128d);
+
+    // SSE2 is part of every x86_64 cpu, so these are always safe to call
+    macro_rules! both {
+        ($op: ident, $a: expr, $b: expr) => {
+            unsafe { F64x4($op($a.0, $b.0), $op($a.1, $b.1)) }
+        };
+    }
+
+    impl F64x4 {
+        pub fn splat(value: f64) -> Self {
+            unsafe { F64x4(_mm_set1_pd(value), _mm_set1_pd(value)) }
+        }
+
+        pub fn new(values: [f64; 4]) -> Self {
+            unsafe { F64x4(_mm_loadu_pd(values.as_ptr()), _mm_loadu_pd(values[2..].as_ptr())) }
+        }
+
+        pub fn to_array(&self) -> [f64; 4] {
+            let mut values = [0.0; 4];
+            unsafe {
+                _mm_storeu_pd(values.as_mut_ptr(), self.0);
+                _mm_storeu_pd(values[2..].as_mut_ptr(), self.1);
+            }
+            values
+        }
+
+        pub fn add(&self, other: &F64x4) -> F64x4 {
+            both!(_mm_add_pd, self, other)
+        }
+
+        pub fn sub(&self, other: &F64x4) -> F64x4 {
+            both!(_mm_sub_pd, self, other)
+        }
+
+        pub fn mult(&self, other: &F64x4) -> F64x4 {
+            both!(_mm_mul_pd, self, other)
+        }
+
+        pub fn div(&self, other: &F64x4) -> F64x4 {
+            both!(_mm_div_pd, self, other)
+        }
+
+        pub fn min(&self, other: &F64x4) -> F64x4 {
+            both!(_mm_min_pd, self, other)
+        }
+
+        pub fn max(&self, other: &F64x4) -> F64x4 {
+            both!(_mm_max_pd, self, other)
+        }
+
+        pub fn sqrt(&self) -> F64x4 {
+            unsafe { F64x4(_mm_sqrt_pd(self.0), _mm_sqrt_pd(self.1)) }
+        }
+
+        // magnitude of self with the sign bit of other
+        pub fn copysign(&self, other: &F64x4) -> F64x4 {
+            let sign = F64x4::splat(-0.0);
+            let magnitude = both!(_mm_andnot_pd, sign, self);
+            let sign = both!(_mm_and_pd, sign, other);
+            both!(_mm_or_pd, magnitude, sign)
+        }
+
+        pub fn lt(&self, other: &F64x4) -> Mask {
+            let lanes = both!(_mm_cmplt_pd, self, other);
+            unsafe { Mask::from_bits((_mm_movemask_pd(lanes.0) | _mm_movemask_pd(lanes.1) << 2) as u8) }
+        }
+
+        pub fn gt(&self, other: &F64x4) -> Mask {
+            let lanes = both!(_mm_cmpgt_pd, self, other);
+            unsafe { Mask::from_bits((_mm_movemask_pd(lanes.0) | _mm_movemask_pd(lanes.1) << 2) as u8) }
+        }
+    }
+}
+
+// also built for tests on x86_64, to check the SIMD lanes against
+#[cfg(any(test, not(target_arch = "x86_64")))]
+mod scalar {
+    use super::Mask;
+
+    #[derive(Clone, Copy, Debug)]
+    pub struct F64x4([f64; 4]);
+
+    impl F64x4 {
+        fn map<F: Fn(f64, f64) -> f64>(&self, other: &F64x4, op: F) -> F64x4 {
+            let mut values = self.0;
+            for (value, other) in values.iter_mut().zip(other.0.iter()) {
+                *value = op(*value, *other);
+            }
+            F64x4(values)
+        }
+
+        fn compare<F: Fn(f64, f64) -> bool>(&self, other: &F64x4, op: F) -> Mask {
+            let bits = (0..4).filter(|lane| op(self.0[*lane], other.0[*lane]))
+                .fold(0, |bits, lane| bits | 1 << lane);
+            Mask::from_bits(bits)
+        }
+
+        pub fn splat(value: f64) -> Self {
+            F64x4([value; 4])
+        }
+
+        pub fn new(values: [f64; 4]) -> Self {
+            F64x4(values)
+        }
+
+        pub fn to_array(&self) -> [f64; 4] {
+            self.0
+        }
+
+        pub fn add(&self, other: &F64x4) -> F64x4 {
+            self.map(other, |a, b| a + b)
+        }
+
+        pub fn sub(&self, other: &F64x4) -> F64x4 {
+            self.map(other, |a, b| a - b)
+        }
+
+        pub fn mult(&self, other: &F64x4) -> F64x4 {
+            self.map(other, |a, b| a * b)
+        }
+
+        pub fn div(&self, other: &F64x4) -> F64x4 {
+            self.map(other, |a, b| a / b)
+        }
+
+        pub fn min(&self, other: &F64x4) -> F64x4 {
+            self.map(other, |a, b| if a < b { a } else { b })
+        }
+
+        pub fn max(&self, other: &F64x4) -> F64x4 {
+            self.map(other, |a, b| if a > b { a } else { b })
+        }
+
+        pub fn sqrt(&self) -> F64x4 {
+            self.map(self, |a, _| a.sqrt())
+        }
+
+        // magnitude of self with the sign bit of other
+        pub fn copysign(&self, other: &F64x4) -> F64x4 {
+            self.map(other, |a, b| a.copysign(b))
+        }
+
+        pub fn lt(&self, other: &F64x4) -> Mask {
+            self.compare(other, |a, b| a < b)
+        }
+
+        pub fn gt(&self, other: &F64x4) -> Mask {
+            self.compare(other, |a, b| a > b)
+        }
+    }
+}
+
+// Up to PACKET_SIZE rays laid out a component at a time. Lanes past the
+// last ray repeat the first one and are left out of active.
+pub struct RayPacket {
+    pub origin: [F64x4; 3],
+    pub direction: [F64x4; 3],
+    pub inverse_direction: [F64x4; 3],
+    pub t_min: F64x4,
+    pub t_max: F64x4,
+    pub active: Mask,
+    // signs of the first ray's direction, which picks the order the
+    // children of bvh nodes are visited in for the whole packet
+    pub negative: [bool; 3],
+}
+
+impl RayPacket {
+    // Panics without any rays or with more than PACKET_SIZE
+    pub fn new(rays: &[LightRay]) -> RayPacket {
+        assert!(!rays.is_empty() && rays.len() <= PACKET_SIZE, "packet needs 1 to {} rays", PACKET_SIZE);
+        let lane = |value: &dyn Fn(&LightRay) -> f64| {
+            let mut values = [0.0; PACKET_SIZE];
+            for (index, value_at) in values.iter_mut().enumerate() {
+                *value_at = value(&rays[if index < rays.len() { index } else { 0 }]);
+            }
+            F64x4::new(values)
+        };
+
+        let first = rays[0].dir();
+        RayPacket {
+            origin: [lane(&|ray| ray.pos().x), lane(&|ray| ray.pos().y), lane(&|ray| ray.pos().z)],
+            direction: [lane(&|ray| ray.dir().x), lane(&|ray| ray.dir().y), lane(&|ray| ray.dir().z)],
+            inverse_direction: [
+                lane(&|ray| 1.0 / ray.dir().x),
+                lane(&|ray| 1.0 / ray.dir().y),
+                lane(&|ray| 1.0 / ray.dir().z)],
+            t_min: lane(&|ray| ray.ray.t_min),
+            t_max: lane(&|ray| ray.ray.t_max),
+            active: Mask::first(rays.len()),
+            negative: [first.x < 0.0, first.y < 0.0, first.z < 0.0],
+        }
+    }
+
+    // Lanes whose ray passes through the box somewhere between its t_min
+    // and the given t_max, the same test as Bounds::hit
+    pub fn hits_bounds(&self, bounds: &Bounds, t_max: &F64x4) -> Mask {
+        let padding = F64x4::splat(1.0 + 4.0 * std::f64::EPSILON);
+        let mut t_near = self.t_min;
+        let mut t_far = *t_max;
+        let slabs = [(&bounds.min.x, &bounds.max.x), (&bounds.min.y, &bounds.max.y), (&bounds.min.z, &bounds.max.z)];
+        for (axis, (min, max)) in slabs.iter().enumerate() {
+            let t0 = F64x4::splat(**min).sub(&self.origin[axis]).mult(&self.inverse_direction[axis]);
+            let t1 = F64x4::splat(**max).sub(&self.origin[axis]).mult(&self.inverse_direction[axis]);
+
+            // NaN from a ray lying in a slab plane leaves the interval as is
+            t_near = t0.min(&t1).max(&t_near);
+            t_far = t1.max(&t0).mult(&padding).min(&t_far);
+        }
+        t_near.gt(&t_far).invert()
+    }
+
+    // origin of each lane, less the given point
+    pub fn offset(&self, point: &Point) -> [F64x4; 3] {
+        [
+            self.origin[0].sub(&F64x4::splat(point.x)),
+            self.origin[1].sub(&F64x4::splat(point.y)),
+            self.origin[2].sub(&F64x4::splat(point.z))]
+    }
+}
+
+pub fn dot(a: &[F64x4; 3], b: &[F64x4; 3]) -> F64x4 {
+    a[0].mult(&b[0]).add(&a[1].mult(&b[1])).add(&a[2].mult(&b[2]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::scene::object::{Intersect, Hit, Sphere, TriangleMesh, Bvh};
+    use crate::scene::primitives::Ray;
+    use crate::scene::sampler::Sampler;
+
+    // same value, or NaN for both
+    fn same(a: f64, b: f64) -> bool {
+        a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan())
+    }
+
+    #[test]
+    fn lanes_match_scalar() {
+        let values = [0.0, -0.0, 1.0, -1.5, 3.7, 1e-310, -1e300, std::f64::INFINITY, std::f64::NEG_INFINITY, std::f64::NAN];
+        let mut sampler = Sampler::new(8);
+        let mut pick = || {
+            let mut lanes = [0.0; 4];
+            for lane in lanes.iter_mut() {
+                *lane = values[(sampler.next_f64() * values.len() as f64) as usize % values.len()];
+            }
+            lanes
+        };
+
+        for _ in 0..2000 {
+            let (a, b) = (pick(), pick());
+            let (simd_a, simd_b) = (F64x4::new(a), F64x4::new(b));
+            let (scalar_a, scalar_b) = (scalar::F64x4::new(a), scalar::F64x4::new(b));
+
+            let pairs = [
+                (simd_a.add(&simd_b), scalar_a.add(&scalar_b), "add"),
+                (simd_a.sub(&simd_b), scalar_a.sub(&scalar_b), "sub"),
+                (simd_a.mult(&simd_b), scalar_a.mult(&scalar_b), "mult"),
+                (simd_a.div(&simd_b), scalar_a.div(&scalar_b), "div"),
+                (simd_a.min(&simd_b), scalar_a.min(&scalar_b), "min"),
+                (simd_a.max(&simd_b), scalar_a.max(&scalar_b), "max"),
+                (simd_a.sqrt(), scalar_a.sqrt(), "sqrt"),
+                (simd_a.copysign(&simd_b), scalar_a.copysign(&scalar_b), "copysign"),
+                (F64x4::splat(a[1]), scalar::F64x4::splat(a[1]), "splat")];
+            for (simd, scalar, name) in pairs.iter() {
+                let (simd, scalar) = (simd.to_array(), scalar.to_array());
+                assert!((0..4).all(|lane| same(simd[lane], scalar[lane])),
+                    "{} of {:?} and {:?} gave {:?}, expected {:?}", name, a, b, simd, scalar);
+            }
+            assert_eq!(simd_a.lt(&simd_b), scalar_a.lt(&scalar_b), "{:?} < {:?}", a, b);
+            assert_eq!(simd_a.gt(&simd_b), scalar_a.gt(&scalar_b), "{:?} > {:?}", a, b);
+        }
+    }
+
+    fn random_point(sampler: &mut Sampler, size: f64) -> Point {
+        Point::from((
+            size * (2.0 * sampler.next_f64() - 1.0),
+            size * (2.0 * sampler.next_f64() - 1.0),
+            size * (2.0 * sampler.next_f64() - 1.0)))
+    }
+
+    // Packets of close together rays like a camera's, and of rays from
+    // anywhere going every which way, some shorter than four
+    fn packets(sampler: &mut Sampler, count: usize) -> Vec<Vec<LightRay>> {
+        (0..count).map(|i| {
+            let size = if i % 5 == 4 { 1 + i % 3 } else { PACKET_SIZE };
+            let origin = random_point(sampler, 12.0);
+            let toward = random_point(sampler, 1.0);
+            (0..size).map(|_| {
+                let ray = if i % 2 == 0 {
+                    Ray::new(origin.clone(), toward.add(&random_point(sampler, 0.02)))
+                }
+                else {
+                    Ray::bounded(random_point(sampler, 12.0), random_point(sampler, 1.0), 0.0, 30.0 * sampler.next_f64())
+                };
+                LightRay::new(ray)
+            }).collect()
+        }).collect()
+    }
+
+    fn assert_same_hit(found: &Option<Hit>, expected: &Option<Hit>, lane: usize) {
+        match (found, expected) {
+            (None, None) => (),
+            (Some(found), Some(expected)) => {
+                assert!(same(found.t, expected.t), "lane {} hit at {}, expected {}", lane, found.t, expected.t);
+                assert_eq!(found.primitive, expected.primitive, "lane {}", lane);
+                assert_eq!(found.material, expected.material, "lane {}", lane);
+                assert!(found.point.add(&expected.point.mult(-1.0)).abs() == 0.0, "lane {}", lane);
+            },
+            _ => panic!("lane {} found {:?}, expected {:?}", lane, found.as_ref().map(|hit| hit.t), expected.as_ref().map(|hit| hit.t)),
+        }
+    }
+
+    // the packet's hits against each ray's own, with every set of lanes
+    // masked off in turn, giving how many rays hit
+    fn assert_matches_rays(object: &dyn Intersect, packets: &[Vec<LightRay>]) -> usize {
+        let mut hits = 0;
+        for rays in packets.iter() {
+            let packet = RayPacket::new(rays);
+            for bits in 0..(1 << PACKET_SIZE) {
+                let lanes = Mask::from_bits(bits).and(&packet.active);
+                let found = object.intersect_packet(&packet, rays, lanes);
+                for lane in 0..PACKET_SIZE {
+                    let expected = if lanes.has(lane) { object.intersect(&rays[lane]) } else { None };
+                    hits += expected.is_some() as usize;
+                    assert_same_hit(&found[lane], &expected, lane);
+                }
+            }
+        }
+        hits
+    }
+
+    fn spheres(sampler: &mut Sampler) -> Vec<Sphere> {
+        (0..200).map(|i| Sphere::new(random_point(sampler, 10.0), i, 0.1 + sampler.next_f64())).collect()
+    }
+
+    #[test]
+    fn sphere_packets_match_rays() {
+        let mut sampler = Sampler::new(9);
+        let spheres = spheres(&mut sampler);
+        let packets = packets(&mut sampler, 300);
+        let hits = spheres.iter().take(20).map(|sphere| assert_matches_rays(sphere, &packets)).sum::<usize>();
+        assert!(hits > 0, "nothing was hit");
+
+        // from inside, and from the surface
+        let sphere = Sphere::new(Point::from((0.0, 0.0, 0.0)), 0, 2.0);
+        let rays = [(0.0, 0.0, 0.0), (2.0, 0.0, 0.0), (0.0, -2.0, 0.0), (5.0, 5.0, 5.0)].iter()
+            .map(|origin| LightRay::new(Ray::new(Point::from(*origin), Point::from((1.0, 1.0, -1.0)))))
+            .collect::<Vec<LightRay>>();
+        assert!(assert_matches_rays(&sphere, &[rays]) > 0);
+    }
+
+    #[test]
+    fn mesh_packets_match_rays() {
+        // triangles scattered every which way, some of them sharing edges
+        let mut sampler = Sampler::new(10);
+        let positions = (0..600).map(|_| random_point(&mut sampler, 10.0)).collect::<Vec<Point>>();
+        let triangles = (0..400).map(|i| {
+            if i % 2 == 0 {
+                [i, i + 1, i + 2]
+            }
+            else {
+                let corner = (sampler.next_f64() * 598.0) as usize;
+                [corner, corner + 1, 599 - i / 2]
+            }
+        }).collect::<Vec<[usize; 3]>>();
+        let mesh = TriangleMesh::new(positions, None, None, None, triangles, 0).unwrap();
+        assert!(assert_matches_rays(&mesh, &packets(&mut sampler, 300)) > 0);
+    }
+
+    #[test]
+    fn bvh_packets_match_closest() {
+        let mut sampler = Sampler::new(11);
+        let objects = spheres(&mut sampler).into_iter().map(|sphere| Arc::new(sphere) as Arc<dyn Intersect>).collect::<Vec<_>>();
+        let bvh = Bvh::new(&objects.iter().map(|object| object.bounds()).collect::<Vec<_>>());
+
+        for rays in packets(&mut sampler, 500).iter() {
+            let packet = RayPacket::new(rays);
+            for bits in [0b1111, 0b0101, 0b1000, 0b0110].iter() {
+                let lanes = Mask::from_bits(*bits).and(&packet.active);
+                let found = bvh.closest_packet(&packet, lanes, |index, lanes| {
+                    objects[index].intersect_packet(&packet, rays, lanes).map(|hit| hit.map(|hit| (hit.t, hit)))
+                });
+                for lane in 0..PACKET_SIZE {
+                    let expected = if lanes.has(lane) {
+                        bvh.closest(&rays[lane].ray, |index| objects[index].intersect(&rays[lane]).map(|hit| (hit.t, hit)))
+                    }
+                    else {
+                        None
+                    };
+                    assert_same_hit(&found[lane], &expected, lane);
+                }
+            }
+        }
+    }
+}
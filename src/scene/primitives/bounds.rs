@@ -0,0 +1,111 @@
+use super::{Point, Ray, Transform};
+
+// Axis aligned bounding box. The empty box has min above max, infinite
+// ones stand for objects without bounds like planes.
+#[derive(Clone, Debug)]
+pub struct Bounds {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Bounds {
+    pub fn new(min: Point, max: Point) -> Self {
+        Bounds {
+            min: min,
+            max: max,
+        }
+    }
+
+    pub fn empty() -> Self {
+        Bounds::new(
+            Point::from((std::f64::INFINITY, std::f64::INFINITY, std::f64::INFINITY)),
+            Point::from((std::f64::NEG_INFINITY, std::f64::NEG_INFINITY, std::f64::NEG_INFINITY)))
+    }
+
+    pub fn infinite() -> Self {
+        Bounds::new(
+            Point::from((std::f64::NEG_INFINITY, std::f64::NEG_INFINITY, std::f64::NEG_INFINITY)),
+            Point::from((std::f64::INFINITY, std::f64::INFINITY, std::f64::INFINITY)))
+    }
+
+    pub fn from_points<'a, I: IntoIterator<Item = &'a Point>>(points: I) -> Self {
+        points.into_iter().fold(Bounds::empty(), |bounds, point| bounds.include(point))
+    }
+
+    pub fn include(&self, point: &Point) -> Bounds {
+        Bounds::new(
+            Point::from((self.min.x.min(point.x), self.min.y.min(point.y), self.min.z.min(point.z))),
+            Point::from((self.max.x.max(point.x), self.max.y.max(point.y), self.max.z.max(point.z))))
+    }
+
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        self.include(&other.min).include(&other.max)
+    }
+
+    // the part inside both, empty when they don't overlap
+    pub fn overlap(&self, other: &Bounds) -> Bounds {
+        Bounds::new(
+            Point::from((self.min.x.max(other.min.x), self.min.y.max(other.min.y), self.min.z.max(other.min.z))),
+            Point::from((self.max.x.min(other.max.x), self.max.y.min(other.max.y), self.max.z.min(other.max.z))))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn is_finite(&self) -> bool {
+        [&self.min, &self.max].iter().all(|point| point.x.is_finite() && point.y.is_finite() && point.z.is_finite())
+    }
+
+    pub fn centroid(&self) -> Point {
+        self.min.add(&self.max).mult(0.5)
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let size = self.max.add(&self.min.mult(-1.0));
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    // Box around the transformed corners. Infinite and empty bounds stay
+    // as they are.
+    pub fn transform(&self, transform: &Transform) -> Bounds {
+        if self.is_empty() || !self.is_finite() {
+            return self.clone();
+        }
+
+        let corners = (0..8).map(|corner| transform.point(&Point::from((
+            if corner & 1 == 0 { self.min.x } else { self.max.x },
+            if corner & 2 == 0 { self.min.y } else { self.max.y },
+            if corner & 4 == 0 { self.min.z } else { self.max.z })))).collect::<Vec<Point>>();
+        Bounds::from_points(corners.iter())
+    }
+
+    // Whether the ray passes through the box somewhere between t_min and
+    // t_max, given the reciprocal of its direction. The far distance is
+    // padded so rounding never loses a box the ray only just touches.
+    pub fn hit(&self, ray: &Ray, inverse_direction: &Point, t_max: f64) -> bool {
+        let mut t_near = ray.t_min;
+        let mut t_far = t_max;
+        let slabs = [
+            (self.min.x, self.max.x, ray.position.x, inverse_direction.x),
+            (self.min.y, self.max.y, ray.position.y, inverse_direction.y),
+            (self.min.z, self.max.z, ray.position.z, inverse_direction.z),
+        ];
+        for (min, max, origin, inverse) in slabs.iter() {
+            let t0 = (min - origin) * inverse;
+            let t1 = (max - origin) * inverse;
+            let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+            // NaN from a ray lying in a slab plane is ignored by max and min
+            t_near = t_near.max(t0);
+            t_far = t_far.min(t1 * (1.0 + 4.0 * std::f64::EPSILON));
+            if t_near > t_far {
+                return false;
+            }
+        }
+        true
+    }
+}
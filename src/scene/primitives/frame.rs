@@ -0,0 +1,56 @@
+use super::Point;
+
+// Orthonormal basis around a normal. Shading happens in the local space
+// of this frame, where the normal is +z.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub tangent: Point,
+    pub bitangent: Point,
+    pub normal: Point,
+}
+
+impl Frame {
+    // builds an arbitrary but continuous tangent (Duff et al. 2017)
+    pub fn from_normal(normal: &Point) -> Self {
+        let sign = 1.0f64.copysign(normal.z);
+        let a = -1.0 / (sign + normal.z);
+        let b = normal.x * normal.y * a;
+
+        Frame {
+            tangent: Point::from((1.0 + sign * normal.x * normal.x * a, sign * b, -sign * normal.x)),
+            bitangent: Point::from((b, sign + normal.y * normal.y * a, -normal.y)),
+            normal: normal.clone(),
+        }
+    }
+
+    // tangent follows the given direction projected onto the surface, so
+    // anisotropic materials line up with the surface parameterization. Falls
+    // back to an arbitrary one when the direction is along the normal.
+    pub fn from_normal_tangent(normal: &Point, tangent: &Point) -> Self {
+        let projected = tangent.add(&normal.mult(-normal.dot(tangent)));
+        let length = projected.abs();
+        if !(length > 1e-9 * tangent.abs()) {
+            return Frame::from_normal(normal);
+        }
+
+        let tangent = projected.mult(1.0 / length);
+        Frame {
+            bitangent: normal.cross(&tangent),
+            tangent: tangent,
+            normal: normal.clone(),
+        }
+    }
+
+    pub fn to_local(&self, direction: &Point) -> Point {
+        Point::from((
+            direction.dot(&self.tangent),
+            direction.dot(&self.bitangent),
+            direction.dot(&self.normal)))
+    }
+
+    pub fn to_world(&self, direction: &Point) -> Point {
+        self.tangent.mult(direction.x)
+            .add(&self.bitangent.mult(direction.y))
+            .add(&self.normal.mult(direction.z))
+    }
+}
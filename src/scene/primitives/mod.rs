@@ -1,10 +1,18 @@
+pub mod bounds;
 pub mod color;
+pub mod frame;
 pub mod point;
 pub mod ray;
+pub mod spectrum;
+pub mod transform;
 
+pub use bounds::Bounds;
 pub use color::Color;
+pub use frame::Frame;
 pub use point::Point;
 pub use ray::Ray;
+pub use spectrum::Spectrum;
+pub use transform::Transform;
 
 pub enum Rotation {
     XY,
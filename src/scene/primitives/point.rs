@@ -58,6 +58,14 @@ impl Point {
     pub fn dot(&self, other: &Point) -> f64 {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
+
+    pub fn cross(&self, other: &Point) -> Point {
+        Point {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
 }
 
 impl From<(f64, f64, f64)> for Point {
@@ -2,19 +2,105 @@ use std::convert::From;
 
 use super::Point;
 
+// Bound on the relative error of a computed intersection point, per
+// component. Intersection routines have to stay within it, reprojecting
+// points onto the surface where plain ray arithmetic isn't accurate enough.
+const POINT_ERROR: f64 = 64.0 * std::f64::EPSILON;
+
+// Ray with the interval [t_min, t_max] along it that hits are accepted in
 #[derive(Clone, Debug)]
 pub struct Ray {
     pub position: Point,
     pub direction: Point,
+    pub t_min: f64,
+    pub t_max: f64,
 }
 
 impl Ray {
     pub fn new(position: Point, direction: Point) -> Self {
+        Ray::bounded(position, direction, 0.0, std::f64::INFINITY)
+    }
+
+    pub fn bounded(position: Point, direction: Point, t_min: f64, t_max: f64) -> Self {
         Ray {
             position: position,
             direction: direction.normalize(),
+            t_min: t_min,
+            t_max: t_max,
         }
     }
+
+    // Ray leaving a surface point, with its origin pushed off the surface
+    // far enough to the side it leaves on that it can't hit the surface
+    // it started on again
+    pub fn spawn(position: &Point, normal: &Point, direction: Point) -> Self {
+        Ray::new(offset_origin(position, normal, &direction), direction)
+    }
+
+    // Ray leaving a surface point towards a target, which isn't counted as
+    // a hit itself
+    pub fn spawn_to(position: &Point, normal: &Point, target: &Point) -> Self {
+        let origin = offset_origin(position, normal, &target.add(&position.mult(-1.0)));
+        let offset = target.add(&origin.mult(-1.0));
+        let distance = offset.abs();
+        Ray::bounded(origin, offset, 0.0, distance * (1.0 - POINT_ERROR))
+    }
+
+    pub fn at(&self, t: f64) -> Point {
+        self.position.add(&self.direction.mult(t))
+    }
+
+    pub fn contains(&self, t: f64) -> bool {
+        t > self.t_min && t < self.t_max
+    }
+}
+
+// Moves a computed surface point along the normal, to the side direction
+// points towards, past the error bound on its position. The offset scales
+// with the point's distance from the origin, where floating point spacing
+// grows, and each component is then rounded away from the surface so the
+// move isn't lost to rounding when the result is stored.
+fn offset_origin(position: &Point, normal: &Point, direction: &Point) -> Point {
+    let error = Point::from((position.x.abs(), position.y.abs(), position.z.abs())).mult(POINT_ERROR);
+    let distance = normal.x.abs() * error.x + normal.y.abs() * error.y + normal.z.abs() * error.z;
+    let mut offset = normal.mult(distance);
+    if direction.dot(normal) < 0.0 {
+        offset = offset.mult(-1.0);
+    }
+
+    let origin = position.add(&offset);
+    let round_away = |value: f64, offset: f64| {
+        if offset > 0.0 {
+            next_up(value)
+        }
+        else if offset < 0.0 {
+            next_down(value)
+        }
+        else {
+            value
+        }
+    };
+    Point::from((
+        round_away(origin.x, offset.x),
+        round_away(origin.y, offset.y),
+        round_away(origin.z, offset.z)))
+}
+
+// smallest float greater than value
+fn next_up(value: f64) -> f64 {
+    if value.is_infinite() && value > 0.0 {
+        return value;
+    }
+    if value == 0.0 {
+        return f64::from_bits(1);
+    }
+
+    let bits = value.to_bits();
+    f64::from_bits(if value > 0.0 { bits + 1 } else { bits - 1 })
+}
+
+fn next_down(value: f64) -> f64 {
+    -next_up(-value)
 }
 
 impl From<(f64, f64, f64, f64, f64, f64)> for Ray {
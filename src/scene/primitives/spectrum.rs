@@ -0,0 +1,59 @@
+use std::convert::From;
+
+use super::Color;
+
+// Linear rgb radiance, unbounded unlike the 8 bit display Color
+#[derive(Clone, Debug)]
+pub struct Spectrum {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+}
+
+impl Spectrum {
+    pub fn new(r: f64, g: f64, b: f64) -> Self {
+        Spectrum {r: r, g: g, b: b}
+    }
+
+    pub fn black() -> Self {
+        Spectrum::new(0.0, 0.0, 0.0)
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.r == 0.0 && self.g == 0.0 && self.b == 0.0
+    }
+
+    pub fn add(&self, other: &Spectrum) -> Spectrum {
+        Spectrum::new(self.r + other.r, self.g + other.g, self.b + other.b)
+    }
+
+    pub fn mult(&self, value: f64) -> Spectrum {
+        Spectrum::new(self.r * value, self.g * value, self.b * value)
+    }
+
+    // component-wise product, used to filter light by surfaces
+    pub fn modulate(&self, other: &Spectrum) -> Spectrum {
+        Spectrum::new(self.r * other.r, self.g * other.g, self.b * other.b)
+    }
+
+    // clamp into displayable range
+    pub fn to_color(&self) -> Color {
+        let to_byte = |value: f64| (value.max(0.0).min(1.0) * 255.0).floor() as u8;
+        Color::new(to_byte(self.r), to_byte(self.g), to_byte(self.b))
+    }
+}
+
+impl From<&Color> for Spectrum {
+    fn from(color: &Color) -> Self {
+        Spectrum::new(
+            color.r as f64 / 255.0,
+            color.g as f64 / 255.0,
+            color.b as f64 / 255.0)
+    }
+}
+
+impl From<(f64, f64, f64)> for Spectrum {
+    fn from(rgb: (f64, f64, f64)) -> Self {
+        Spectrum::new(rgb.0, rgb.1, rgb.2)
+    }
+}
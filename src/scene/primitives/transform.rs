@@ -0,0 +1,194 @@
+use super::Point;
+
+type Matrix = [[f64; 4]; 4];
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+// Affine transform as a 4x4 matrix, stored along with its inverse so
+// going either way costs the same
+#[derive(Clone, Debug)]
+pub struct Transform {
+    matrix: Matrix,
+    inverse: Matrix,
+}
+
+impl Transform {
+    pub fn translation(offset: &Point) -> Self {
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for (axis, value) in [offset.x, offset.y, offset.z].iter().enumerate() {
+            matrix[axis][3] = *value;
+            inverse[axis][3] = -value;
+        }
+
+        Transform {
+            matrix: matrix,
+            inverse: inverse,
+        }
+    }
+
+    // scale along each axis, none of which may be zero
+    pub fn scaling(x: f64, y: f64, z: f64) -> Self {
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for (axis, value) in [x, y, z].iter().enumerate() {
+            matrix[axis][axis] = *value;
+            inverse[axis][axis] = 1.0 / value;
+        }
+
+        Transform {
+            matrix: matrix,
+            inverse: inverse,
+        }
+    }
+
+    // rotation by theta radians around an axis through the origin,
+    // counterclockwise looking down the axis
+    pub fn rotation(axis: &Point, theta: f64) -> Self {
+        let axis = axis.normalize();
+        let (sin, cos) = theta.sin_cos();
+        let a = [axis.x, axis.y, axis.z];
+
+        let mut matrix = IDENTITY;
+        for row in 0..3 {
+            for column in 0..3 {
+                let identity = if row == column { 1.0 } else { 0.0 };
+                matrix[row][column] = a[row] * a[column] * (1.0 - cos) + identity * cos;
+            }
+        }
+        matrix[0][1] -= a[2] * sin;
+        matrix[0][2] += a[1] * sin;
+        matrix[1][0] += a[2] * sin;
+        matrix[1][2] -= a[0] * sin;
+        matrix[2][0] -= a[1] * sin;
+        matrix[2][1] += a[0] * sin;
+
+        // rotations are orthogonal, the inverse is the transpose
+        Transform {
+            inverse: transpose(&matrix),
+            matrix: matrix,
+        }
+    }
+
+    // this transform followed by another one
+    pub fn then(&self, next: &Transform) -> Transform {
+        Transform {
+            matrix: multiply(&next.matrix, &self.matrix),
+            inverse: multiply(&self.inverse, &next.inverse),
+        }
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn point(&self, point: &Point) -> Point {
+        apply(&self.matrix, point, 1.0)
+    }
+
+    // directions and other differences of points, unaffected by translation
+    pub fn vector(&self, vector: &Point) -> Point {
+        apply(&self.matrix, vector, 0.0)
+    }
+
+    // Normals go by the inverse transpose so they stay perpendicular to the
+    // surface when it is scaled unevenly, and come out normalized
+    pub fn normal(&self, normal: &Point) -> Point {
+        apply(&transpose(&self.inverse), normal, 0.0).normalize()
+    }
+}
+
+fn apply(matrix: &Matrix, point: &Point, w: f64) -> Point {
+    let row = |row: &[f64; 4]| row[0] * point.x + row[1] * point.y + row[2] * point.z + row[3] * w;
+    Point::from((row(&matrix[0]), row(&matrix[1]), row(&matrix[2])))
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut product = [[0.0; 4]; 4];
+    for row in 0..4 {
+        for column in 0..4 {
+            product[row][column] = (0..4).map(|i| a[row][i] * b[i][column]).sum();
+        }
+    }
+    product
+}
+
+fn transpose(matrix: &Matrix) -> Matrix {
+    let mut transposed = [[0.0; 4]; 4];
+    for row in 0..4 {
+        for column in 0..4 {
+            transposed[row][column] = matrix[column][row];
+        }
+    }
+    transposed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    fn assert_matrix(matrix: &Matrix, expected: &Matrix) {
+        for row in 0..4 {
+            for column in 0..4 {
+                assert!((matrix[row][column] - expected[row][column]).abs() < 1e-12,
+                    "{:?}, expected {:?}", matrix, expected);
+            }
+        }
+    }
+
+    fn assert_point(point: &Point, expected: (f64, f64, f64)) {
+        assert!(point.add(&Point::from(expected).mult(-1.0)).abs() < 1e-12, "{:?}, expected {:?}", point, expected);
+    }
+
+    fn mixed() -> Transform {
+        Transform::scaling(2.0, 0.5, 3.0)
+            .then(&Transform::rotation(&Point::from((1.0, 2.0, -1.0)), 0.7))
+            .then(&Transform::translation(&Point::from((4.0, -1.0, 2.5))))
+    }
+
+    #[test]
+    fn then_inverse_is_identity() {
+        let transform = mixed();
+        let round_trip = transform.then(&transform.inverse());
+        assert_matrix(&round_trip.matrix, &IDENTITY);
+        assert_matrix(&round_trip.inverse, &IDENTITY);
+        assert_matrix(&multiply(&transform.matrix, &transform.inverse), &IDENTITY);
+    }
+
+    #[test]
+    fn then_applies_in_order() {
+        // scaled first and moved after, so the offset isn't scaled
+        let transform = Transform::scaling(2.0, 2.0, 2.0)
+            .then(&Transform::translation(&Point::from((1.0, 0.0, 0.0))));
+        assert_point(&transform.point(&Point::from((1.0, 1.0, 1.0))), (3.0, 2.0, 2.0));
+        assert_point(&transform.vector(&Point::from((1.0, 1.0, 1.0))), (2.0, 2.0, 2.0));
+    }
+
+    #[test]
+    fn rotation_is_counterclockwise() {
+        let quarter = Transform::rotation(&Point::from((0.0, 0.0, 1.0)), PI / 2.0);
+        assert_point(&quarter.point(&Point::from((1.0, 0.0, 0.0))), (0.0, 1.0, 0.0));
+        assert_point(&quarter.point(&Point::from((0.0, 1.0, 0.0))), (-1.0, 0.0, 0.0));
+        assert_point(&quarter.inverse().point(&Point::from((0.0, 1.0, 0.0))), (1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn normals_stay_perpendicular() {
+        // the plane x + y = 0 squashed along x
+        let transform = Transform::scaling(0.25, 1.0, 1.0);
+        let normal = transform.normal(&Point::from((1.0, 1.0, 0.0)));
+        let along = transform.vector(&Point::from((1.0, -1.0, 0.0)));
+        assert!(normal.dot(&along).abs() < 1e-12);
+        assert!((normal.abs() - 1.0).abs() < 1e-12);
+    }
+}
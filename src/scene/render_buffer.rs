@@ -0,0 +1,248 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::scene::primitives::{Point, Color, Spectrum};
+use crate::scene::bsdf::MaterialId;
+
+// What the camera ray saw at its first hit, used to guide the denoiser
+// and shown as arbitrary output variables
+#[derive(Clone, Debug)]
+pub struct Features {
+    pub albedo: Spectrum,
+    pub normal: Point,
+    // distance from the camera, infinite when nothing was hit
+    pub depth: f64,
+    pub position: Point,
+    // index into Scene::objects, None for lights and the background
+    pub object: Option<usize>,
+    pub material: Option<MaterialId>,
+}
+
+// Radiance arriving along a ray split by where it came from, so lights can
+// be rescaled after rendering. The parts sum to the full radiance.
+#[derive(Clone, Debug)]
+pub struct LightGroups {
+    // light reflected or scattered from each of Scene::lights, in order
+    pub lights: Vec<Spectrum>,
+    // background seen directly or through media
+    pub environment: Spectrum,
+    // lights seen directly
+    pub emission: Spectrum,
+}
+
+impl LightGroups {
+    pub fn new(light_count: usize) -> Self {
+        LightGroups {
+            lights: vec![Spectrum::black(); light_count],
+            environment: Spectrum::black(),
+            emission: Spectrum::black(),
+        }
+    }
+
+    // add per light radiance, in the order of Scene::lights, scaled
+    pub fn add_lights(&mut self, radiance: &Vec<Spectrum>, scale: f64) {
+        for (total, radiance) in self.lights.iter_mut().zip(radiance.iter()) {
+            *total = total.add(&radiance.mult(scale));
+        }
+    }
+
+    pub fn total(&self) -> Spectrum {
+        self.lights.iter().fold(self.environment.add(&self.emission), |total, light| total.add(light))
+    }
+}
+
+// Channels a frame can be viewed or saved as
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aov {
+    Beauty,
+    Albedo,
+    Normal,
+    Depth,
+    Position,
+    Object,
+    Material,
+}
+
+pub const AOVS: [Aov; 7] = [Aov::Beauty, Aov::Albedo, Aov::Normal, Aov::Depth,
+    Aov::Position, Aov::Object, Aov::Material];
+
+impl Aov {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Beauty => "beauty",
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Object => "object",
+            Aov::Material => "material",
+        }
+    }
+
+    // the channel after this one, wrapping around
+    pub fn next(&self) -> Aov {
+        let index = AOVS.iter().position(|aov| aov == self).unwrap();
+        AOVS[(index + 1) % AOVS.len()]
+    }
+}
+
+impl Features {
+    pub fn background() -> Self {
+        Features {
+            albedo: Spectrum::new(1.0, 1.0, 1.0),
+            normal: Point::from((0.0, 0.0, 0.0)),
+            depth: std::f64::INFINITY,
+            position: Point::from((0.0, 0.0, 0.0)),
+            object: None,
+            material: None,
+        }
+    }
+}
+
+// Everything traced for one frame, row major from the upper left
+pub struct RenderBuffer {
+    pub width: u32,
+    pub height: u32,
+    pub color: Vec<Spectrum>,
+    pub features: Vec<Features>,
+    // radiance per light group, empty unless asked for when rendering
+    pub groups: Vec<LightGroups>,
+}
+
+impl RenderBuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        let size = (width * height) as usize;
+        RenderBuffer {
+            width: width,
+            height: height,
+            color: vec![Spectrum::black(); size],
+            features: vec![Features::background(); size],
+            groups: Vec::new(),
+        }
+    }
+
+    pub fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    // radiance from one light for every pixel, None when the buffer
+    // has no light groups or the light doesn't exist
+    pub fn light_group(&self, light: usize) -> Option<Vec<Spectrum>> {
+        if self.groups.is_empty() || light >= self.groups[0].lights.len() {
+            return None;
+        }
+        Some(self.groups.iter().map(|groups| groups.lights[light].clone()).collect())
+    }
+
+    pub fn environment_group(&self) -> Vec<Spectrum> {
+        self.groups.iter().map(|groups| groups.environment.clone()).collect()
+    }
+
+    pub fn emission_group(&self) -> Vec<Spectrum> {
+        self.groups.iter().map(|groups| groups.emission.clone()).collect()
+    }
+
+    // Displayable colors of a feature channel. Depth and position are
+    // normalized to the range covered by this frame, indices get an
+    // arbitrary color each. Beauty is returned untouched.
+    pub fn aov_colors(&self, aov: Aov) -> Vec<Color> {
+        let hit = self.features.iter().filter(|features| features.depth.is_finite());
+
+        match aov {
+            Aov::Beauty => self.color.iter().map(|color| color.to_color()).collect(),
+            Aov::Albedo => self.features.iter().map(|features| features.albedo.to_color()).collect(),
+            Aov::Normal => self.features.iter().map(|features| {
+                let normal = &features.normal;
+                Spectrum::new(normal.x * 0.5 + 0.5, normal.y * 0.5 + 0.5, normal.z * 0.5 + 0.5).to_color()
+            }).collect(),
+            Aov::Depth => {
+                let (near, far) = hit.fold((std::f64::INFINITY, 0.0), |(near, far): (f64, f64), features| {
+                    (near.min(features.depth), far.max(features.depth))
+                });
+                self.features.iter().map(|features| {
+                    // nearest is white fading to dark gray, background black
+                    let value = if features.depth.is_infinite() {
+                        0.0
+                    }
+                    else if far > near {
+                        1.0 - 0.9 * (features.depth - near) / (far - near)
+                    }
+                    else {
+                        1.0
+                    };
+                    Spectrum::new(value, value, value).to_color()
+                }).collect()
+            },
+            Aov::Position => {
+                let infinity = std::f64::INFINITY;
+                let (min, max) = hit.fold(
+                    (Point::from((infinity, infinity, infinity)), Point::from((-infinity, -infinity, -infinity))),
+                    |(min, max), features| {
+                        let p = &features.position;
+                        (Point::from((min.x.min(p.x), min.y.min(p.y), min.z.min(p.z))),
+                         Point::from((max.x.max(p.x), max.y.max(p.y), max.z.max(p.z))))
+                    });
+                let scale = |value: f64, min: f64, max: f64| {
+                    if max > min { (value - min) / (max - min) } else { 0.5 }
+                };
+                self.features.iter().map(|features| {
+                    if features.depth.is_infinite() {
+                        return Color::new(0, 0, 0);
+                    }
+                    let p = &features.position;
+                    Spectrum::new(scale(p.x, min.x, max.x), scale(p.y, min.y, max.y), scale(p.z, min.z, max.z)).to_color()
+                }).collect()
+            },
+            Aov::Object => self.features.iter().map(|features| id_color(features.object)).collect(),
+            Aov::Material => self.features.iter().map(|features| id_color(features.material)).collect(),
+        }
+    }
+}
+
+// well separated color for an index, black for none
+fn id_color(id: Option<usize>) -> Color {
+    match id {
+        None => Color::new(0, 0, 0),
+        Some(id) => {
+            // spread consecutive ids around the hue circle by the golden ratio
+            let hue = ((id as f64 + 1.0) * 0.618033988749895).fract() * 6.0;
+            let x = 1.0 - (hue % 2.0 - 1.0).abs();
+            let (r, g, b) = match hue as u32 {
+                0 => (1.0, x, 0.0),
+                1 => (x, 1.0, 0.0),
+                2 => (0.0, 1.0, x),
+                3 => (0.0, x, 1.0),
+                4 => (x, 0.0, 1.0),
+                _ => (1.0, 0.0, x),
+            };
+            Spectrum::new(r, g, b).to_color()
+        },
+    }
+}
+
+// Binary PPM, the simplest format every image viewer understands
+pub fn write_ppm<P: AsRef<Path>>(path: P, width: u32, height: u32, pixels: &[Color]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "P6\n{} {}\n255\n", width, height)?;
+    for color in pixels.iter() {
+        file.write_all(&[color.r, color.g, color.b])?;
+    }
+    file.flush()
+}
+
+// Little endian PFM, keeping unclamped linear radiance so images can be
+// rescaled and summed without losing anything
+pub fn write_pfm<P: AsRef<Path>>(path: P, width: u32, height: u32, pixels: &[Spectrum]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "PF\n{} {}\n-1.0\n", width, height)?;
+    // rows are stored bottom to top
+    for row in pixels.chunks(width as usize).rev() {
+        for color in row.iter() {
+            for value in [color.r, color.g, color.b].iter() {
+                file.write_all(&(*value as f32).to_le_bytes())?;
+            }
+        }
+    }
+    file.flush()
+}
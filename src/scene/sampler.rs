@@ -0,0 +1,33 @@
+// Small xorshift generator so the tracer doesn't need an external rng crate.
+// Each pixel gets its own seeded sampler, which keeps frames reproducible.
+
+pub struct Sampler {
+    state: u64,
+}
+
+impl Sampler {
+    pub fn new(seed: u64) -> Self {
+        // scramble the seed so neighbouring pixels don't start correlated,
+        // and make sure the state is never zero
+        let mut state = seed.wrapping_add(0x9e3779b97f4a7c15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94d049bb133111eb);
+        state ^= state >> 31;
+
+        Sampler {
+            state: if state == 0 { 0x2545f4914f6cdd1d } else { state },
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    // uniform value in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,158 @@
+// Image textures, read from PPM or PGM files (ascii P3 and P2, or binary
+// P6 and P5). Gray images give gray texels.
+//
+// Texel values are taken as linear, like every other Color in the scene.
+// Lookups wrap around at the edges and are bilinearly filtered. v runs up
+// the image, the convention OBJ and most modelling tools use.
+
+use std::fs;
+use std::path::Path;
+
+use crate::scene::primitives::Spectrum;
+use crate::scene::load_err::LoadErr;
+
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    // row major from the top left, as stored in the file
+    texels: Vec<Spectrum>,
+}
+
+impl ImageTexture {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LoadErr> {
+        let bytes = fs::read(path.as_ref())?;
+        Self::parse(&bytes)
+            .map_err(|err| LoadErr::from(format!("{}: {}", path.as_ref().display(), err)))
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, LoadErr> {
+        let image = read_netpbm(bytes)?;
+        let texels = image.samples.chunks(image.channels).map(|texel| {
+            if image.channels == 1 {
+                Spectrum::new(texel[0], texel[0], texel[0])
+            }
+            else {
+                Spectrum::new(texel[0], texel[1], texel[2])
+            }
+        }).collect();
+
+        Ok(ImageTexture {
+            width: image.width,
+            height: image.height,
+            texels: texels,
+        })
+    }
+
+    fn texel(&self, x: i64, y: i64) -> &Spectrum {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        &self.texels[y * self.width + x]
+    }
+
+    pub fn lookup(&self, uv: (f64, f64)) -> Spectrum {
+        // texel centers sit at half integer coordinates
+        let x = uv.0 * self.width as f64 - 0.5;
+        let y = (1.0 - uv.1) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.texel(x0, y0).mult(1.0 - fx).add(&self.texel(x0 + 1, y0).mult(fx));
+        let bottom = self.texel(x0, y0 + 1).mult(1.0 - fx).add(&self.texel(x0 + 1, y0 + 1).mult(fx));
+        top.mult(1.0 - fy).add(&bottom.mult(fy))
+    }
+}
+
+// Pixels of a netpbm image as read from the file
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    // 1 for gray images, 3 for color
+    pub channels: usize,
+    // row major from the top left, scaled to [0, 1] by the maximum value
+    pub samples: Vec<f64>,
+}
+
+// Reads a PPM or PGM image, ascii or binary
+pub fn read_netpbm(bytes: &[u8]) -> Result<Image, LoadErr> {
+    // header tokens are whitespace separated, with # comments to the end
+    // of the line, and a single whitespace byte before binary data
+    let mut position = 0;
+    let mut next_token = || -> Option<String> {
+        loop {
+            while position < bytes.len() && bytes[position].is_ascii_whitespace() {
+                position += 1;
+            }
+            if position < bytes.len() && bytes[position] == b'#' {
+                while position < bytes.len() && bytes[position] != b'\n' {
+                    position += 1;
+                }
+                continue;
+            }
+            break;
+        }
+
+        let start = position;
+        while position < bytes.len() && !bytes[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if start == position {
+            None
+        }
+        else {
+            Some(String::from_utf8_lossy(&bytes[start..position]).into_owned())
+        }
+    };
+
+    let magic = next_token().ok_or_else(|| String::from("empty file"))?;
+    let (channels, binary) = match magic.as_str() {
+        "P2" => (1, false),
+        "P3" => (3, false),
+        "P5" => (1, true),
+        "P6" => (3, true),
+        _ => return Err(LoadErr::from(format!("expected a P2, P3, P5 or P6 image, found '{}'", magic))),
+    };
+
+    let mut header = [0usize; 3];
+    for (value, name) in header.iter_mut().zip(["width", "height", "maximum value"].iter()) {
+        let token = next_token().ok_or_else(|| format!("missing {}", name))?;
+        *value = token.parse().map_err(|_| format!("bad {} '{}'", name, token))?;
+    }
+    let [width, height, max_value] = header;
+    if width == 0 || height == 0 || max_value == 0 || max_value > 65535 {
+        return Err(LoadErr::from(format!("bad image header {} {} {}", width, height, max_value)));
+    }
+
+    let count = width.checked_mul(height).and_then(|pixels| pixels.checked_mul(channels))
+        .ok_or_else(|| format!("image size {}x{} is too large", width, height))?;
+    let samples = if !binary {
+        (0..count).map(|_| {
+            let token = next_token().ok_or_else(|| String::from("not enough pixel values"))?;
+            token.parse::<usize>().map_err(|_| format!("bad pixel value '{}'", token))
+        }).collect::<Result<Vec<usize>, String>>()?
+    }
+    else {
+        drop(next_token);
+        let body = &bytes[(position + 1).min(bytes.len())..];
+        let sample_size = if max_value > 255 { 2 } else { 1 };
+        let size = count.checked_mul(sample_size)
+            .ok_or_else(|| format!("image size {}x{} is too large", width, height))?;
+        if body.len() < size {
+            return Err(LoadErr::from(format!("expected {} bytes of pixels, found {}", size, body.len())));
+        }
+        if sample_size == 1 {
+            body[..count].iter().map(|byte| *byte as usize).collect()
+        }
+        else {
+            body.chunks(2).take(count).map(|pair| ((pair[0] as usize) << 8) | pair[1] as usize).collect()
+        }
+    };
+
+    let scale = 1.0 / max_value as f64;
+    Ok(Image {
+        width: width,
+        height: height,
+        channels: channels,
+        samples: samples.iter().map(|sample| *sample as f64 * scale).collect(),
+    })
+}